@@ -0,0 +1,101 @@
+//! Benchmarks the receipt store under concurrent mixed read/write workloads. A store with a single shard has one lock
+//! over every receipt, as the store did before it was sharded, and serves as the baseline for the sharded store.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use futures_util::FutureExt;
+use serve_ex::{config::StoreConfig, data::Receipt, db::Connection};
+use uuid::Uuid;
+
+/// The number of threads making requests concurrently.
+const THREADS: u64 = 8;
+
+/// The number of receipts stored before measuring.
+const PRELOADED: u64 = 10_000;
+
+/// The workloads measured, as the name and the share of operations that are writes, in percent.
+const WORKLOADS: [(&str, u64); 3] = [("read_heavy", 10), ("mixed", 50), ("write_heavy", 90)];
+
+/// The stores measured, as the name and the number of shards.
+const STORES: [(&str, usize); 2] = [("single_lock", 1), ("sharded", 16)];
+
+/// Constructs a receipt for a distinct transaction.
+fn receipt(transaction: u64) -> Receipt {
+    let mut receipt: Receipt = serde_json::from_str(
+        r#"{
+            "retailer": "Target",
+            "purchaseDate": "2022-01-02",
+            "purchaseTime": "13:13",
+            "total": "1.25",
+            "items": [{ "shortDescription": "Pepsi - 12-oz", "price": "1.25" }]
+        }"#,
+    )
+    .unwrap();
+    receipt.transaction_number = Some(transaction.to_string());
+    receipt
+}
+
+/// Stores a receipt. None of the store's operations actually wait, so their futures complete on the first poll.
+fn store(connection: &Connection, transaction: u64) -> Uuid {
+    connection
+        .store_receipt(receipt(transaction), None)
+        .now_or_never()
+        .unwrap()
+        .unwrap()
+        .unwrap()
+}
+
+fn workloads(c: &mut Criterion) {
+    for (workload, write_percent) in WORKLOADS {
+        let mut group = c.benchmark_group(workload);
+        for (name, shards) in STORES {
+            let connection = Connection::new().with_eviction(StoreConfig {
+                // bound the store so long runs don't exhaust memory
+                capacity: Some(1_000_000),
+                shards,
+                ..Default::default()
+            });
+            let ids: Vec<Uuid> = (0..PRELOADED).map(|i| store(&connection, i)).collect();
+            let transactions = AtomicU64::new(PRELOADED);
+
+            group.bench_function(BenchmarkId::from_parameter(name), |b| {
+                b.iter_custom(|iters| {
+                    let start = Instant::now();
+                    thread::scope(|scope| {
+                        for thread in 0..THREADS {
+                            let (connection, ids, transactions) =
+                                (&connection, &ids, &transactions);
+                            scope.spawn(move || {
+                                for i in (0..iters).filter(|i| i % THREADS == thread) {
+                                    if i % 100 < write_percent {
+                                        store(
+                                            connection,
+                                            transactions.fetch_add(1, Ordering::Relaxed),
+                                        );
+                                    } else {
+                                        let id = ids[(i % PRELOADED) as usize];
+                                        connection.load_receipt(id).now_or_never().unwrap();
+                                    }
+                                }
+                            });
+                        }
+                    });
+                    start.elapsed()
+                })
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().measurement_time(Duration::from_secs(5));
+    targets = workloads
+}
+criterion_main!(benches);
@@ -0,0 +1,11 @@
+//! Generates the gRPC service from its protobuf definition. The definition is compiled with protox, so building
+//! doesn't need protoc installed.
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto");
+    let descriptors = protox::compile(["receipts.proto"], ["proto"])?;
+    tonic_prost_build::configure()
+        .build_client(false)
+        .compile_fds(descriptors)?;
+    Ok(())
+}
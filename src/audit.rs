@@ -0,0 +1,65 @@
+//! Contains the audit log, an append-only record of the changes staff make to members' points, who made them and
+//! when.
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{ledger::ReasonCode, review::ReceiptStatus};
+
+/// A change recorded in the audit log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(
+    tag = "action",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum AuditAction {
+    /// Points on a receipt were adjusted.
+    AdjustReceipt {
+        receipt_id: Uuid,
+        adjustment_id: Uuid,
+        points: i64,
+        reason: ReasonCode,
+    },
+    /// A receipt pending review was approved or rejected.
+    ReviewReceipt {
+        receipt_id: Uuid,
+        status: ReceiptStatus,
+    },
+    /// Points were granted or revoked from a member's balance.
+    AdjustBalance {
+        user_id: Uuid,
+        transaction_id: Uuid,
+        points: i64,
+        reason: String,
+    },
+}
+
+/// An entry in the audit log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    pub id: Uuid,
+
+    /// The subject of the principal who made the change.
+    pub actor: String,
+
+    #[serde(with = "time::serde::rfc3339")]
+    pub recorded_at: OffsetDateTime,
+
+    #[serde(flatten)]
+    pub action: AuditAction,
+}
+
+impl AuditEntry {
+    /// Constructs a new entry recorded now.
+    pub fn new(actor: String, action: AuditAction) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            actor,
+            recorded_at: OffsetDateTime::now_utc(),
+            action,
+        }
+    }
+}
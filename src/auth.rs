@@ -0,0 +1,543 @@
+//! Contains authentication for this application. The [`Authentication`] middleware identifies the client making each
+//! request from its credentials, and handlers get the identified client using the [`Principal`] extractor.
+//!
+//! Clients authenticate with either a static API key in the `X-Api-Key` header, or a JWT bearer token in the
+//! `Authorization` header signed with HS256 or RS256. Clients presenting no credentials are granted the configured
+//! anonymous scopes.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    future::{ready, Ready},
+    io,
+    sync::Arc,
+};
+
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{header, StatusCode},
+    FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError,
+};
+use futures_util::{
+    future::{Either, MapOk},
+    TryFutureExt,
+};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{config::AuthConfig, routes::ErrorResponse};
+
+/// The header carrying a client's API key.
+const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// The subject of clients presenting no credentials.
+const ANONYMOUS_SUBJECT: &str = "anonymous";
+
+/// A permission a client may be granted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Scope {
+    #[serde(rename = "receipts:read")]
+    ReceiptsRead,
+    #[serde(rename = "receipts:write")]
+    ReceiptsWrite,
+    #[serde(rename = "users:read")]
+    UsersRead,
+    #[serde(rename = "users:write")]
+    UsersWrite,
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+impl Scope {
+    /// Gets the name of this scope as it appears in configuration and tokens.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::ReceiptsRead => "receipts:read",
+            Self::ReceiptsWrite => "receipts:write",
+            Self::UsersRead => "users:read",
+            Self::UsersWrite => "users:write",
+            Self::Admin => "admin",
+        }
+    }
+
+    /// Parses a scope from its name. Returns None if the name is not a known scope.
+    fn from_name(name: &str) -> Option<Self> {
+        [
+            Self::ReceiptsRead,
+            Self::ReceiptsWrite,
+            Self::UsersRead,
+            Self::UsersWrite,
+            Self::Admin,
+        ]
+        .into_iter()
+        .find(|scope| scope.name() == name)
+    }
+}
+
+/// The authenticated client making a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal {
+    /// The name of the client: the API key's name, or the token's subject.
+    pub subject: String,
+
+    /// The member the client acts on behalf of, if any.
+    pub user_id: Option<Uuid>,
+
+    /// The scopes the client has been granted.
+    pub scopes: HashSet<Scope>,
+}
+
+impl Principal {
+    /// Determines whether the client presented no credentials.
+    pub fn is_anonymous(&self) -> bool {
+        self.subject == ANONYMOUS_SUBJECT && self.user_id.is_none()
+    }
+
+    /// Checks that the client has been granted the scope.
+    pub fn require_scope(&self, scope: Scope) -> Result<(), AuthError> {
+        if self.scopes.contains(&scope) {
+            Ok(())
+        } else {
+            Err(AuthError::MissingScope(scope))
+        }
+    }
+}
+
+impl FromRequest for Principal {
+    type Error = AuthError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        // the principal is only missing if the route isn't wrapped in the authentication middleware
+        ready(
+            req.extensions()
+                .get::<Principal>()
+                .cloned()
+                .ok_or(AuthError::Unauthenticated),
+        )
+    }
+}
+
+/// Reasons a request can fail authentication or authorization.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthError {
+    /// The client presented no credentials, and anonymous access is disabled.
+    Unauthenticated,
+    /// The client presented an API key that is not configured.
+    InvalidApiKey,
+    /// The client presented a bearer token that could not be validated.
+    InvalidToken(String),
+    /// The client is authenticated but lacks a scope the request requires.
+    MissingScope(Scope),
+    /// The client is authenticated but may not act on the requested resource.
+    Forbidden,
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unauthenticated => write!(f, "authentication required"),
+            Self::InvalidApiKey => write!(f, "invalid API key"),
+            Self::InvalidToken(msg) => write!(f, "invalid bearer token: {msg}"),
+            Self::MissingScope(scope) => write!(f, "missing scope `{}`", scope.name()),
+            Self::Forbidden => write!(f, "access to this resource is forbidden"),
+        }
+    }
+}
+
+impl ResponseError for AuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::MissingScope(_) | Self::Forbidden => StatusCode::FORBIDDEN,
+            _ => StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if self.status_code() == StatusCode::UNAUTHORIZED {
+            response.insert_header((header::WWW_AUTHENTICATE, "Bearer"));
+        }
+        response.json(ErrorResponse {
+            error: self.to_string(),
+        })
+    }
+}
+
+/// A configured API key.
+#[derive(Debug, Clone)]
+struct ApiKey {
+    name: String,
+    user_id: Option<Uuid>,
+    scopes: HashSet<Scope>,
+}
+
+/// The claims this application reads from a JWT.
+#[derive(Debug, Clone, Deserialize)]
+struct Claims {
+    /// The subject of the token. If this is a UUID, it identifies a member.
+    sub: String,
+
+    /// The space-separated scopes granted by the token.
+    #[serde(default)]
+    scope: String,
+}
+
+/// Identifies clients from the credentials on their requests.
+#[derive(Default)]
+pub struct Authenticator {
+    /// The scopes granted to clients presenting no credentials.
+    anonymous_scopes: HashSet<Scope>,
+
+    /// The configured API keys, by the hex-encoded SHA-256 hash of the key.
+    api_keys: HashMap<String, ApiKey>,
+
+    /// The key for validating HS256 tokens, if HS256 tokens are accepted.
+    hs256_key: Option<DecodingKey>,
+
+    /// The key for validating RS256 tokens, if RS256 tokens are accepted.
+    rs256_key: Option<DecodingKey>,
+
+    /// The required issuer of tokens, if any.
+    issuer: Option<String>,
+
+    /// The required audience of tokens, if any.
+    audience: Option<String>,
+}
+
+impl Authenticator {
+    /// Constructs an authenticator from the configuration, reading any key files it names.
+    pub fn from_config(config: &AuthConfig) -> io::Result<Self> {
+        let api_keys = config
+            .api_keys
+            .iter()
+            .map(|key| {
+                let api_key = ApiKey {
+                    name: key.name.clone(),
+                    user_id: key.user_id,
+                    scopes: key.scopes.iter().copied().collect(),
+                };
+                (key.key_hash.to_ascii_lowercase(), api_key)
+            })
+            .collect();
+        let hs256_key = match &config.jwt.hs256_secret {
+            Some(path) => {
+                let mut secret = fs::read(path)?;
+                // editors and `echo` end files with a newline that isn't part of the secret
+                let len = secret
+                    .iter()
+                    .rposition(|b| !b.is_ascii_whitespace())
+                    .map_or(0, |i| i + 1);
+                secret.truncate(len);
+                Some(DecodingKey::from_secret(&secret))
+            }
+            None => None,
+        };
+        let rs256_key = match &config.jwt.rs256_public_key {
+            Some(path) => {
+                Some(DecodingKey::from_rsa_pem(&fs::read(path)?).map_err(io::Error::other)?)
+            }
+            None => None,
+        };
+        Ok(Self {
+            anonymous_scopes: config.anonymous_scopes.iter().copied().collect(),
+            api_keys,
+            hs256_key,
+            rs256_key,
+            issuer: config.jwt.issuer.clone(),
+            audience: config.jwt.audience.clone(),
+        })
+    }
+
+    /// Identifies the client making the request.
+    pub fn authenticate(&self, req: &HttpRequest) -> Result<Principal, AuthError> {
+        self.authenticate_credentials(
+            req.headers().get(API_KEY_HEADER).map(|key| key.as_bytes()),
+            req.headers()
+                .get(header::AUTHORIZATION)
+                .map(|authorization| authorization.as_bytes()),
+        )
+    }
+
+    /// Identifies a client from the values of the API key and authorization headers on its request, or their
+    /// equivalents in other protocols.
+    pub fn authenticate_credentials(
+        &self,
+        api_key: Option<&[u8]>,
+        authorization: Option<&[u8]>,
+    ) -> Result<Principal, AuthError> {
+        if let Some(key) = api_key {
+            return self.authenticate_api_key(key);
+        }
+        if let Some(authorization) = authorization {
+            let token = std::str::from_utf8(authorization)
+                .ok()
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .ok_or_else(|| AuthError::InvalidToken("expected a bearer token".to_owned()))?;
+            return self.authenticate_token(token.trim());
+        }
+        if self.anonymous_scopes.is_empty() {
+            return Err(AuthError::Unauthenticated);
+        }
+        Ok(Principal {
+            subject: ANONYMOUS_SUBJECT.to_owned(),
+            user_id: None,
+            scopes: self.anonymous_scopes.clone(),
+        })
+    }
+
+    /// Identifies a client from an API key.
+    fn authenticate_api_key(&self, key: &[u8]) -> Result<Principal, AuthError> {
+        let hash = hex::encode(Sha256::digest(key));
+        let api_key = self.api_keys.get(&hash).ok_or(AuthError::InvalidApiKey)?;
+        Ok(Principal {
+            subject: api_key.name.clone(),
+            user_id: api_key.user_id,
+            scopes: api_key.scopes.clone(),
+        })
+    }
+
+    /// Identifies a client from a JWT.
+    fn authenticate_token(&self, token: &str) -> Result<Principal, AuthError> {
+        let invalid = |e: jsonwebtoken::errors::Error| AuthError::InvalidToken(e.to_string());
+        let header = jsonwebtoken::decode_header(token).map_err(invalid)?;
+        let key = match header.alg {
+            Algorithm::HS256 => self.hs256_key.as_ref(),
+            Algorithm::RS256 => self.rs256_key.as_ref(),
+            _ => None,
+        }
+        .ok_or_else(|| {
+            AuthError::InvalidToken(format!("unsupported algorithm {:?}", header.alg))
+        })?;
+
+        let mut validation = Validation::new(header.alg);
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        let claims = jsonwebtoken::decode::<Claims>(token, key, &validation)
+            .map_err(invalid)?
+            .claims;
+
+        Ok(Principal {
+            user_id: Uuid::parse_str(&claims.sub).ok(),
+            scopes: claims
+                .scope
+                .split_whitespace()
+                .filter_map(Scope::from_name)
+                .collect(),
+            subject: claims.sub,
+        })
+    }
+}
+
+/// Middleware that authenticates every request, rejecting requests with invalid credentials.
+#[derive(Clone)]
+pub struct Authentication {
+    authenticator: Arc<Authenticator>,
+}
+
+impl Authentication {
+    /// Constructs the middleware using the given authenticator.
+    pub fn new(authenticator: Authenticator) -> Self {
+        Self {
+            authenticator: Arc::new(authenticator),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Authentication
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = AuthenticationMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AuthenticationMiddleware {
+            service,
+            authenticator: self.authenticator.clone(),
+        }))
+    }
+}
+
+/// The service produced by the [`Authentication`] middleware.
+pub struct AuthenticationMiddleware<S> {
+    service: S,
+    authenticator: Arc<Authenticator>,
+}
+
+impl<S, B> Service<ServiceRequest> for AuthenticationMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = Either<
+        MapOk<S::Future, fn(ServiceResponse<B>) -> Self::Response>,
+        Ready<Result<Self::Response, Self::Error>>,
+    >;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        match self.authenticator.authenticate(req.request()) {
+            Ok(principal) => {
+                req.extensions_mut().insert(principal);
+                Either::Left(
+                    self.service
+                        .call(req)
+                        .map_ok(ServiceResponse::map_into_left_body),
+                )
+            }
+            Err(e) => {
+                let response = e.error_response().map_into_right_body();
+                Either::Right(ready(Ok(req.into_response(response))))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+
+    use super::*;
+    use crate::config::{ApiKeyConfig, JwtConfig};
+
+    const SECRET: &[u8] = b"test secret";
+
+    fn authenticator() -> Authenticator {
+        let config = AuthConfig {
+            anonymous_scopes: vec![],
+            api_keys: vec![ApiKeyConfig {
+                name: "crm".to_owned(),
+                key_hash: hex::encode(Sha256::digest(b"letmein")),
+                scopes: vec![Scope::ReceiptsRead],
+                user_id: None,
+            }],
+            ..Default::default()
+        };
+        Authenticator {
+            hs256_key: Some(DecodingKey::from_secret(SECRET)),
+            ..Authenticator::from_config(&config).unwrap()
+        }
+    }
+
+    #[test]
+    fn api_key() {
+        let req = TestRequest::default()
+            .insert_header((API_KEY_HEADER, "letmein"))
+            .to_http_request();
+        let principal = authenticator().authenticate(&req).unwrap();
+        assert_eq!(principal.subject, "crm");
+        assert!(principal.require_scope(Scope::ReceiptsRead).is_ok());
+        assert_eq!(
+            principal.require_scope(Scope::ReceiptsWrite),
+            Err(AuthError::MissingScope(Scope::ReceiptsWrite))
+        );
+
+        let req = TestRequest::default()
+            .insert_header((API_KEY_HEADER, "letmeout"))
+            .to_http_request();
+        assert_eq!(
+            authenticator().authenticate(&req),
+            Err(AuthError::InvalidApiKey)
+        );
+    }
+
+    #[test]
+    fn bearer_token() {
+        let user_id = Uuid::new_v4();
+        let claims = json!({
+            "sub": user_id.to_string(),
+            "scope": "receipts:write unknown:scope",
+            "exp": 4_000_000_000u64,
+        });
+        let token = jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(SECRET),
+        )
+        .unwrap();
+
+        let req = TestRequest::default()
+            .insert_header((header::AUTHORIZATION, format!("Bearer {token}")))
+            .to_http_request();
+        let principal = authenticator().authenticate(&req).unwrap();
+        assert_eq!(principal.user_id, Some(user_id));
+        assert_eq!(principal.scopes, HashSet::from([Scope::ReceiptsWrite]));
+
+        // tokens signed with a different secret are rejected
+        let token = jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(b"wrong secret"),
+        )
+        .unwrap();
+        let req = TestRequest::default()
+            .insert_header((header::AUTHORIZATION, format!("Bearer {token}")))
+            .to_http_request();
+        assert!(matches!(
+            authenticator().authenticate(&req),
+            Err(AuthError::InvalidToken(_))
+        ));
+    }
+
+    #[test]
+    fn secret_file() {
+        let path = std::env::temp_dir().join(format!("serve-ex-{}", Uuid::new_v4()));
+        fs::write(&path, b"test secret\r\n").unwrap();
+        let config = AuthConfig {
+            jwt: JwtConfig {
+                hs256_secret: Some(path.clone()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let authenticator = Authenticator::from_config(&config).unwrap();
+        fs::remove_file(path).unwrap();
+
+        // the trailing newline isn't part of the secret
+        let claims = json!({ "sub": "crm", "exp": 4_000_000_000u64 });
+        let token = jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(SECRET),
+        )
+        .unwrap();
+        let req = TestRequest::default()
+            .insert_header((header::AUTHORIZATION, format!("Bearer {token}")))
+            .to_http_request();
+        assert!(authenticator.authenticate(&req).is_ok());
+    }
+
+    #[test]
+    fn anonymous() {
+        let req = TestRequest::default().to_http_request();
+        assert_eq!(
+            authenticator().authenticate(&req),
+            Err(AuthError::Unauthenticated)
+        );
+
+        // anonymous access must be opted into
+        let authenticator = Authenticator::from_config(&AuthConfig::default()).unwrap();
+        assert_eq!(
+            authenticator.authenticate(&req),
+            Err(AuthError::Unauthenticated)
+        );
+    }
+}
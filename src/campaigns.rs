@@ -0,0 +1,261 @@
+//! Contains promotional campaigns awarding bonus points on top of a receipt's ruleset points. Campaigns run between
+//! two purchase dates and can be limited to a retailer or to receipts with a matching item. Each awards either a
+//! multiple of the receipt's ruleset points or a flat bonus, optionally capped.
+//!
+//! Campaigns stack as follows: every matching stackable campaign applies, and of the matching campaigns that don't
+//! stack, only the one awarding the largest bonus applies.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use time::Date;
+use uuid::Uuid;
+
+use crate::{data::serialization, db::ReceiptRecord};
+
+/// The bonus points a campaign awards.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase", deny_unknown_fields)]
+pub enum Reward {
+    /// Multiplies the receipt's ruleset points, e.g. 2 for double points. The bonus is the points added, rounded down.
+    Multiplier { multiplier: f64 },
+    /// Awards a flat number of points.
+    Bonus { points: u64 },
+}
+
+/// A promotional campaign.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Campaign {
+    pub name: String,
+
+    /// The first purchase date the campaign applies to.
+    #[serde(with = "serialization::date")]
+    pub starts_on: Date,
+
+    /// The last purchase date the campaign applies to.
+    #[serde(with = "serialization::date")]
+    pub ends_on: Date,
+
+    /// The retailer the campaign is limited to, matching either the ID or the name of the receipt's retailer, ignoring
+    /// case. If absent, receipts from any retailer match.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retailer: Option<String>,
+
+    /// Text the campaign requires in the description of some item on the receipt, ignoring case. If absent, receipts
+    /// with any items match.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub item_description: Option<String>,
+
+    pub reward: Reward,
+
+    /// Whether the campaign applies alongside other campaigns.
+    #[serde(default)]
+    pub stackable: bool,
+
+    /// The most bonus points the campaign awards per receipt.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cap: Option<u64>,
+}
+
+impl Campaign {
+    /// Determines whether this campaign is acceptable. Campaigns must fulfill these requirements to be acceptable:
+    /// - the name must be nonempty
+    /// - the campaign must not end before it starts
+    /// - matchers, if present, must be nonempty
+    /// - multipliers must be finite and at least 1
+    pub fn is_acceptable(&self) -> bool {
+        let matcher_ok = |matcher: &Option<String>| {
+            matcher
+                .as_ref()
+                .map_or(true, |matcher| !matcher.trim().is_empty())
+        };
+        let reward_ok = match self.reward {
+            Reward::Multiplier { multiplier } => multiplier.is_finite() && multiplier >= 1.0,
+            Reward::Bonus { .. } => true,
+        };
+        !self.name.trim().is_empty()
+            && self.starts_on <= self.ends_on
+            && matcher_ok(&self.retailer)
+            && matcher_ok(&self.item_description)
+            && reward_ok
+    }
+
+    /// Determines whether the campaign applies to the receipt.
+    pub fn matches(&self, record: &ReceiptRecord) -> bool {
+        let receipt = &record.receipt;
+        let retailer_ok = self.retailer.as_ref().map_or(true, |retailer| {
+            record.retailer_id.as_deref() == Some(retailer.as_str())
+                || record.retailer_name().eq_ignore_ascii_case(retailer)
+        });
+        let item_ok = self.item_description.as_ref().map_or(true, |text| {
+            let text = text.to_lowercase();
+            receipt
+                .items
+                .iter()
+                .any(|item| item.short_description.to_lowercase().contains(&text))
+        });
+        (self.starts_on..=self.ends_on).contains(&receipt.purchase_date) && retailer_ok && item_ok
+    }
+
+    /// Calculates the bonus points the campaign awards a receipt earning the given ruleset points.
+    pub fn bonus(&self, ruleset_points: u64) -> u64 {
+        let bonus = match self.reward {
+            Reward::Multiplier { multiplier } => {
+                (ruleset_points as f64 * (multiplier - 1.0)).floor() as u64
+            }
+            Reward::Bonus { points } => points,
+        };
+        self.cap.map_or(bonus, |cap| bonus.min(cap))
+    }
+}
+
+/// Bonus points awarded by a campaign.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CampaignBonus {
+    pub campaign_id: Uuid,
+    pub points: u64,
+}
+
+/// Calculates the bonus points the campaigns award a receipt earning the given ruleset points, following the stacking
+/// rules. Bonuses are ordered by campaign ID.
+pub fn bonuses(
+    campaigns: &HashMap<Uuid, Campaign>,
+    record: &ReceiptRecord,
+    ruleset_points: u64,
+) -> Vec<CampaignBonus> {
+    let mut bonuses = Vec::new();
+    let mut best_exclusive: Option<CampaignBonus> = None;
+    for (&campaign_id, campaign) in campaigns {
+        if !campaign.matches(record) {
+            continue;
+        }
+        let bonus = CampaignBonus {
+            campaign_id,
+            points: campaign.bonus(ruleset_points),
+        };
+        if campaign.stackable {
+            bonuses.push(bonus);
+        } else if best_exclusive.map_or(true, |best| {
+            // break ties by ID so the result doesn't depend on hash order
+            (bonus.points, best.campaign_id) > (best.points, bonus.campaign_id)
+        }) {
+            best_exclusive = Some(bonus);
+        }
+    }
+    bonuses.extend(best_exclusive);
+    bonuses.sort_by_key(|bonus| bonus.campaign_id);
+    bonuses
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{macros::date, OffsetDateTime};
+
+    use super::*;
+
+    fn record(retailer: &str, description: &str) -> ReceiptRecord {
+        let receipt = serde_json::from_value(serde_json::json!({
+            "retailer": retailer,
+            "purchaseDate": "2024-03-09",
+            "purchaseTime": "13:13",
+            "total": "1.25",
+            "items": [{ "shortDescription": description, "price": "1.25" }]
+        }))
+        .unwrap();
+        ReceiptRecord {
+            receipt,
+            retailer_id: None,
+            retailer_name: None,
+            owner_id: None,
+            stored_at: OffsetDateTime::now_utc(),
+            award: Default::default(),
+            risk: Default::default(),
+            status: Default::default(),
+            review: None,
+            adjustments: Vec::new(),
+        }
+    }
+
+    fn campaign(reward: Reward, stackable: bool) -> Campaign {
+        Campaign {
+            name: "Spring".to_owned(),
+            starts_on: date!(2024 - 03 - 01),
+            ends_on: date!(2024 - 03 - 31),
+            retailer: None,
+            item_description: None,
+            reward,
+            stackable,
+            cap: None,
+        }
+    }
+
+    #[test]
+    fn matching() {
+        let gatorade = Campaign {
+            item_description: Some("gatorade".to_owned()),
+            ..campaign(Reward::Bonus { points: 100 }, false)
+        };
+        assert!(gatorade.matches(&record("Walgreens", "GATORADE 20oz")));
+        assert!(!gatorade.matches(&record("Walgreens", "Pepsi")));
+
+        let target = Campaign {
+            retailer: Some("target".to_owned()),
+            ..campaign(Reward::Multiplier { multiplier: 2.0 }, false)
+        };
+        assert!(target.matches(&record("Target", "Pepsi")));
+        assert!(!target.matches(&record("Walmart", "Pepsi")));
+
+        let april = Campaign {
+            starts_on: date!(2024 - 04 - 01),
+            ends_on: date!(2024 - 04 - 30),
+            ..campaign(Reward::Bonus { points: 100 }, false)
+        };
+        assert!(!april.matches(&record("Target", "Pepsi")));
+    }
+
+    #[test]
+    fn stacking() {
+        let ids = [Uuid::from_u128(1), Uuid::from_u128(2), Uuid::from_u128(3)];
+        let campaigns = HashMap::from([
+            (
+                ids[0],
+                campaign(Reward::Multiplier { multiplier: 2.0 }, false),
+            ),
+            (ids[1], campaign(Reward::Bonus { points: 100 }, false)),
+            (
+                ids[2],
+                Campaign {
+                    cap: Some(10),
+                    ..campaign(Reward::Multiplier { multiplier: 3.0 }, true)
+                },
+            ),
+        ]);
+        // only the larger exclusive bonus applies, alongside the capped stackable bonus
+        assert_eq!(
+            bonuses(&campaigns, &record("Target", "Pepsi"), 31),
+            vec![
+                CampaignBonus {
+                    campaign_id: ids[1],
+                    points: 100
+                },
+                CampaignBonus {
+                    campaign_id: ids[2],
+                    points: 10
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn acceptable() {
+        assert!(campaign(Reward::Bonus { points: 100 }, false).is_acceptable());
+        assert!(!campaign(Reward::Multiplier { multiplier: 0.5 }, false).is_acceptable());
+        let backwards = Campaign {
+            ends_on: date!(2024 - 02 - 01),
+            ..campaign(Reward::Bonus { points: 100 }, false)
+        };
+        assert!(!backwards.is_acceptable());
+    }
+}
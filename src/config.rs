@@ -0,0 +1,487 @@
+//! Contains configuration for this application. Configuration is read from the JSON file named by the
+//! `SERVE_EX_CONFIG` environment variable. Any settings not present in the file (or all of them, if the variable is
+//! unset) take their default values.
+
+use std::{collections::HashMap, env, fs, io, path::PathBuf};
+
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{auth::Scope, fraud::RiskWeights};
+
+/// The environment variable naming the configuration file.
+const CONFIG_PATH_VAR: &str = "SERVE_EX_CONFIG";
+
+/// Configuration for this application.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct Config {
+    /// Settings for parsing submitted receipts.
+    pub receipts: ReceiptConfig,
+
+    /// Settings for the retailer registry.
+    pub retailers: RetailerConfig,
+
+    /// Settings for awarding points.
+    pub points: PointsConfig,
+
+    /// Settings for authenticating clients.
+    pub auth: AuthConfig,
+
+    /// Settings for limiting the rate of client requests.
+    pub rate_limits: RateLimitConfig,
+
+    /// Settings for the in-memory receipt store.
+    pub store: StoreConfig,
+
+    /// Settings for the points ledger.
+    pub ledger: LedgerConfig,
+
+    /// Settings for scoring receipts for fraud.
+    pub fraud: FraudConfig,
+
+    /// Settings for delivering webhook notifications.
+    pub webhooks: WebhookConfig,
+
+    /// Settings for the stream of server-sent events.
+    pub events: EventsConfig,
+
+    /// Settings for processing receipts asynchronously.
+    pub jobs: JobConfig,
+
+    /// Settings for importing receipts from CSV files.
+    pub import: ImportConfig,
+
+    /// Settings for the gRPC interface.
+    pub grpc: GrpcConfig,
+
+    /// Settings for persisting the database to disk.
+    pub persistence: PersistenceConfig,
+}
+
+impl Config {
+    /// Loads the configuration from the file named by `SERVE_EX_CONFIG`, or the default configuration if the
+    /// variable is unset.
+    pub fn load() -> io::Result<Self> {
+        let Some(path) = env::var_os(CONFIG_PATH_VAR) else {
+            return Ok(Self::default());
+        };
+        let contents = fs::read(path)?;
+        let config: Self = serde_json::from_slice(&contents).map_err(io::Error::other)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks settings whose values are well-formed but unusable.
+    pub fn validate(&self) -> io::Result<()> {
+        let invalid = |msg: String| Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        for (route, limit) in &self.rate_limits.routes {
+            if !(limit.refill_per_second.is_finite() && limit.refill_per_second > 0.0) {
+                return invalid(format!(
+                    "rate limit for \"{route}\" must refill at a positive rate"
+                ));
+            }
+        }
+        if self.events.keep_alive_seconds == 0 {
+            return invalid("events must be kept alive at least every second".to_owned());
+        }
+        if self.webhooks.max_attempts == 0 {
+            return invalid("webhook deliveries must be attempted at least once".to_owned());
+        }
+        if self.webhooks.timeout_seconds == 0 {
+            return invalid("webhook deliveries must time out after at least a second".to_owned());
+        }
+        Ok(())
+    }
+}
+
+/// Settings for parsing submitted receipts.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct ReceiptConfig {
+    /// Whether to reject receipts containing fields the application does not recognize.
+    pub strict: bool,
+
+    /// The maximum number of items a receipt may contain.
+    pub max_items: usize,
+
+    /// The maximum length, in characters, of any string in a receipt.
+    pub max_string_length: usize,
+
+    /// The maximum size, in bytes, of a receipt request body.
+    pub max_body_bytes: usize,
+}
+
+impl Default for ReceiptConfig {
+    fn default() -> Self {
+        Self {
+            strict: false,
+            max_items: 500,
+            max_string_length: 200,
+            max_body_bytes: 64 * 1024,
+        }
+    }
+}
+
+/// Settings for the retailer registry.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct RetailerConfig {
+    /// The JSON file to load known retailers from. If absent, no retailers are known.
+    pub registry: Option<PathBuf>,
+
+    /// The minimum similarity, from 0 to 1, for a retailer name to fuzzily match a known retailer.
+    pub fuzzy_threshold: f64,
+}
+
+impl Default for RetailerConfig {
+    fn default() -> Self {
+        Self {
+            registry: None,
+            fuzzy_threshold: 0.85,
+        }
+    }
+}
+
+/// Settings for awarding points.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct PointsConfig {
+    /// The JSON file to load rulesets from. If absent, only the standard ruleset is used.
+    pub rulesets: Option<PathBuf>,
+}
+
+/// Settings for authenticating clients. By default, every client must present credentials.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct AuthConfig {
+    /// The scopes granted to clients presenting no credentials. If empty, all clients must present credentials.
+    pub anonymous_scopes: Vec<Scope>,
+
+    /// The API keys clients may authenticate with.
+    pub api_keys: Vec<ApiKeyConfig>,
+
+    /// Settings for authenticating clients with JWT bearer tokens.
+    pub jwt: JwtConfig,
+}
+
+/// An API key clients may authenticate with.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ApiKeyConfig {
+    /// The name identifying the client the key was issued to.
+    pub name: String,
+
+    /// The hex-encoded SHA-256 hash of the key. Keys themselves are never stored in configuration.
+    pub key_hash: String,
+
+    /// The scopes granted to clients presenting the key.
+    pub scopes: Vec<Scope>,
+
+    /// The member clients presenting the key act on behalf of, if any.
+    #[serde(default)]
+    pub user_id: Option<Uuid>,
+}
+
+/// Settings for authenticating clients with JWT bearer tokens. Tokens are only accepted if a key for their algorithm
+/// is configured.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct JwtConfig {
+    /// The file containing the shared secret for HS256 tokens. Trailing ASCII whitespace, such as a final newline, isn't
+    /// part of the secret.
+    pub hs256_secret: Option<PathBuf>,
+
+    /// The PEM file containing the RSA public key for RS256 tokens.
+    pub rs256_public_key: Option<PathBuf>,
+
+    /// The issuer tokens must have, if any.
+    pub issuer: Option<String>,
+
+    /// The audience tokens must have, if any.
+    pub audience: Option<String>,
+}
+
+/// Settings for limiting the rate of client requests.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// The limit for each route, keyed by method and path pattern, e.g. `POST /receipts/process`. Routes without a
+    /// limit are not limited.
+    pub routes: HashMap<String, LimitConfig>,
+}
+
+/// The rate limit for a route. Each client may make `capacity` requests in a burst, after which it may make
+/// `refill_per_second` requests per second.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct LimitConfig {
+    /// The maximum number of requests a client may make in a burst.
+    pub capacity: u32,
+
+    /// The number of requests per second a client may make once its burst is used. Must be positive.
+    pub refill_per_second: f64,
+}
+
+/// Settings for the in-memory receipt store. Once the store holds `capacity` receipts, the oldest receipts are evicted
+/// to make room for new ones. Receipts older than `ttl_seconds` are evicted as well.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct StoreConfig {
+    /// The maximum number of receipts to store. If absent, the number of receipts is unbounded.
+    pub capacity: Option<usize>,
+
+    /// How long to keep each receipt, in seconds. If absent, receipts are kept until evicted for capacity.
+    pub ttl_seconds: Option<u64>,
+
+    /// The number of evicted receipt IDs to remember, so reads of evicted receipts can be reported as expired.
+    pub evicted_id_capacity: usize,
+
+    /// The number of shards to split the store into, rounded up to a power of two of at most 256. Writes to different
+    /// shards proceed concurrently. `capacity` bounds the store as a whole, whatever the number of shards.
+    pub shards: usize,
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        Self {
+            capacity: None,
+            ttl_seconds: None,
+            evicted_id_capacity: 100_000,
+            shards: 16,
+        }
+    }
+}
+
+/// Settings for the points ledger.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct LedgerConfig {
+    /// How many days earned points last before expiring. If absent, points never expire.
+    pub expiry_days: Option<u64>,
+}
+
+/// Settings for scoring receipts for fraud.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct FraudConfig {
+    /// The risk score at or above which receipts are held for review instead of being awarded points. If absent,
+    /// receipts are never held.
+    pub hold_threshold: Option<u32>,
+
+    /// The weight each suspicious pattern adds to a receipt's risk score.
+    pub weights: RiskWeights,
+}
+
+/// Settings for delivering webhook notifications.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// How many times to attempt each delivery before giving up. Must be at least 1.
+    pub max_attempts: u32,
+
+    /// How long to wait before the first retry, in milliseconds. The wait doubles with each retry.
+    pub initial_backoff_millis: u64,
+
+    /// How long to wait for a subscriber to respond, in seconds. Must be at least 1.
+    pub timeout_seconds: u64,
+
+    /// How many of the most recent delivery attempts to keep in the delivery log.
+    pub delivery_log_capacity: usize,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff_millis: 1000,
+            timeout_seconds: 10,
+            delivery_log_capacity: 1000,
+        }
+    }
+}
+
+/// Settings for the stream of server-sent events.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct EventsConfig {
+    /// How many of the most recent events to keep for clients resuming the stream.
+    pub buffer_capacity: usize,
+
+    /// How often to send a comment to keep idle streams open, in seconds. Must be at least 1.
+    pub keep_alive_seconds: u64,
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        Self {
+            buffer_capacity: 1000,
+            keep_alive_seconds: 15,
+        }
+    }
+}
+
+/// Settings for processing receipts asynchronously.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct JobConfig {
+    /// The number of background workers processing receipts, each on its own thread.
+    pub workers: usize,
+
+    /// How many receipts may wait for a worker. Receipts submitted while the queue is full are refused.
+    pub queue_capacity: usize,
+
+    /// How many finished jobs to remember for clients polling their status.
+    pub retained_jobs: usize,
+}
+
+impl Default for JobConfig {
+    fn default() -> Self {
+        Self {
+            workers: 4,
+            queue_capacity: 1000,
+            retained_jobs: 10000,
+        }
+    }
+}
+
+/// Settings for importing receipts from CSV files.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct ImportConfig {
+    /// The maximum size, in bytes, of an import request body.
+    pub max_body_bytes: usize,
+
+    /// The names of the columns receipt and item fields are read from.
+    pub columns: ColumnMapping,
+}
+
+impl Default for ImportConfig {
+    fn default() -> Self {
+        Self {
+            max_body_bytes: 10 * 1024 * 1024,
+            columns: ColumnMapping::default(),
+        }
+    }
+}
+
+/// The names of the CSV columns receipt and item fields are read from. Each row holds an item, along with the fields
+/// of the receipt it's on. The defaults match the column names of an export with a row per item.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct ColumnMapping {
+    /// The column identifying the receipt each item is on. If the file has no such column, items are grouped into
+    /// receipts by their retailer, purchase date, purchase time and total instead.
+    pub receipt_id: String,
+
+    pub retailer: String,
+    pub purchase_date: String,
+    pub purchase_time: String,
+    pub total: String,
+    pub short_description: String,
+    pub price: String,
+
+    /// Columns for optional receipt fields identifying the transaction, so receipts imported again are recognized as
+    /// duplicates. Files needn't have them.
+    pub store_id: String,
+    pub register_number: String,
+    pub transaction_number: String,
+
+    /// Columns for optional item fields. Files needn't have them.
+    pub quantity: String,
+    pub unit_price: String,
+    pub sku: String,
+    pub upc: String,
+    pub category: String,
+}
+
+impl Default for ColumnMapping {
+    fn default() -> Self {
+        Self {
+            receipt_id: "receiptId".to_owned(),
+            retailer: "retailer".to_owned(),
+            purchase_date: "purchaseDate".to_owned(),
+            purchase_time: "purchaseTime".to_owned(),
+            total: "total".to_owned(),
+            short_description: "shortDescription".to_owned(),
+            price: "price".to_owned(),
+            store_id: "storeId".to_owned(),
+            register_number: "registerNumber".to_owned(),
+            transaction_number: "transactionNumber".to_owned(),
+            quantity: "quantity".to_owned(),
+            unit_price: "unitPrice".to_owned(),
+            sku: "sku".to_owned(),
+            upc: "upc".to_owned(),
+            category: "category".to_owned(),
+        }
+    }
+}
+
+/// Settings for the gRPC interface.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct GrpcConfig {
+    /// The port the gRPC interface is served on, on localhost. If null, the default, the gRPC interface isn't served.
+    pub port: Option<u16>,
+}
+
+/// Configuration for persisting the database to disk. When a directory is given, the database is snapshotted there
+/// every `snapshot_interval_seconds`, and every write in between is appended to a write-ahead log. Both are loaded on
+/// startup.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct PersistenceConfig {
+    /// The directory to persist the database to. If absent, the database is kept only in memory.
+    pub directory: Option<PathBuf>,
+
+    /// How often to write a snapshot, in seconds.
+    pub snapshot_interval_seconds: u64,
+
+    /// Whether to flush each write to disk before responding. This guards against losing writes to a power failure,
+    /// at the cost of write latency.
+    pub sync_writes: bool,
+}
+
+impl Default for PersistenceConfig {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            snapshot_interval_seconds: 300,
+            sync_writes: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validation() {
+        assert!(Config::default().validate().is_ok());
+
+        for refill_per_second in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let mut config = Config::default();
+            config.rate_limits.routes.insert(
+                "POST /receipts/process".to_owned(),
+                LimitConfig {
+                    capacity: 10,
+                    refill_per_second,
+                },
+            );
+            assert!(config.validate().is_err(), "{refill_per_second}");
+        }
+
+        let mut config = Config::default();
+        config.events.keep_alive_seconds = 0;
+        assert!(config.validate().is_err());
+
+        let mut config = Config::default();
+        config.webhooks.max_attempts = 0;
+        assert!(config.validate().is_err());
+
+        let mut config = Config::default();
+        config.webhooks.timeout_seconds = 0;
+        assert!(config.validate().is_err());
+    }
+}
@@ -8,7 +8,7 @@ use serde::{Deserialize, Serialize};
 use time::{Date, Time};
 
 /// Contains serialization/deserialization helpers for data types.
-mod serialization;
+pub(crate) mod serialization;
 
 /// A price on a receipt containing dollars and cents.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -17,6 +17,28 @@ pub struct Price {
     pub cents: u8,
 }
 
+impl Price {
+    /// Converts this price to a whole number of cents. Returns None on overflow.
+    pub fn to_cents(self) -> Option<u64> {
+        self.dollars
+            .checked_mul(100)?
+            .checked_add(u64::from(self.cents))
+    }
+
+    /// Constructs a price from a whole number of cents.
+    pub fn from_cents(cents: u64) -> Self {
+        Self {
+            dollars: cents / 100,
+            cents: (cents % 100) as u8,
+        }
+    }
+
+    /// Multiplies this price by a quantity. Returns None on overflow.
+    pub fn checked_mul(self, quantity: u64) -> Option<Self> {
+        self.to_cents()?.checked_mul(quantity).map(Self::from_cents)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Item {
@@ -25,16 +47,155 @@ pub struct Item {
 
     /// The total price paid for this item.
     pub price: Price,
+
+    /// The number of units purchased. Absent means a single unit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quantity: Option<u32>,
+
+    /// The price of a single unit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit_price: Option<Price>,
+
+    /// The retailer's stock keeping unit for the item.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sku: Option<String>,
+
+    /// The universal product code for the item. UPC-A, EAN-8, EAN-13 and GTIN-14 codes are accepted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upc: Option<String>,
+
+    /// The product category of the item, e.g. "beverages".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
 }
 
 impl Item {
-    /// Determines whether this item is acceptable. An item is acceptable if the short description contains only words.
+    /// Determines whether this item is acceptable. Items must fulfill these requirements to be acceptable:
+    /// - the short description and category must contain only words
+    /// - the quantity, if present, must be nonzero
+    /// - if both quantity and unit price are present, their product must equal the price
+    /// - if the unit price is present without a quantity, it must equal the price
+    /// - the SKU, if present, must be nonempty and alphanumeric (dashes allowed)
+    /// - the UPC, if present, must be a GTIN with a valid check digit
     pub fn is_acceptable(&self) -> bool {
         static REGEX: OnceLock<Regex> = OnceLock::new();
         let regex = REGEX
             .get_or_init(|| Regex::new(r"^[\w\s-]+$").expect("description regex should be valid"));
+        static SKU_REGEX: OnceLock<Regex> = OnceLock::new();
+        let sku_regex = SKU_REGEX
+            .get_or_init(|| Regex::new(r"^[A-Za-z0-9-]+$").expect("sku regex should be valid"));
+
+        let quantity_ok = self.quantity.map_or(true, |q| q > 0);
+        let unit_price_ok = match (self.quantity, self.unit_price) {
+            (Some(quantity), Some(unit_price)) => {
+                unit_price.checked_mul(quantity.into()) == Some(self.price)
+            }
+            (None, Some(unit_price)) => unit_price == self.price,
+            (_, None) => true,
+        };
 
         regex.is_match(&self.short_description)
+            && self.category.as_deref().map_or(true, |c| regex.is_match(c))
+            && self.sku.as_deref().map_or(true, |s| sku_regex.is_match(s))
+            && self.upc.as_deref().map_or(true, is_valid_gtin)
+            && quantity_ok
+            && unit_price_ok
+    }
+
+    /// The number of units this item represents.
+    pub fn units(&self) -> u64 {
+        self.quantity.map_or(1, u64::from)
+    }
+}
+
+/// Determines whether the given code is a GTIN (UPC-A, EAN-8, EAN-13 or GTIN-14) with a valid check digit.
+fn is_valid_gtin(code: &str) -> bool {
+    if !matches!(code.len(), 8 | 12 | 13 | 14) || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return false;
+    }
+    // weights alternate 3, 1, 3, ... starting from the digit just left of the check digit
+    let sum: u32 = code
+        .bytes()
+        .rev()
+        .skip(1)
+        .enumerate()
+        .map(|(i, b)| u32::from(b - b'0') * if i % 2 == 0 { 3 } else { 1 })
+        .sum();
+    let check = (10 - sum % 10) % 10;
+    code.bytes().last().map(|b| u32::from(b - b'0')) == Some(check)
+}
+
+/// The method used to pay for a purchase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PaymentMethod {
+    Cash,
+    Credit,
+    Debit,
+    GiftCard,
+    Mobile,
+    Check,
+    Other,
+}
+
+/// The store location a receipt was printed at.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StoreLocation {
+    /// The retailer's identifier for the store, e.g. the "1234" in "Store #1234".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store_id: Option<String>,
+
+    /// The street address of the store as printed on the receipt.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+}
+
+/// A single tax charged on a receipt.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaxLine {
+    /// The name of the tax as printed on the receipt, e.g. "State sales tax".
+    pub description: String,
+
+    /// The amount of tax charged.
+    pub amount: Price,
+}
+
+/// Key uniquely identifying a transaction, used to detect duplicate receipt submissions.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TransactionKey {
+    /// The canonical identity of the retailer, so the same transaction is recognized under any spelling.
+    retailer: String,
+    store_id: Option<String>,
+    register_number: Option<String>,
+    transaction_number: String,
+    purchase_date: Date,
+}
+
+impl TransactionKey {
+    /// Encodes the key as bytes that are the same for equal keys and differ for any others, in every build of the
+    /// application.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        for field in [
+            Some(&self.retailer),
+            self.store_id.as_ref(),
+            self.register_number.as_ref(),
+            Some(&self.transaction_number),
+        ] {
+            // fields are length-prefixed so no two keys run together into the same bytes
+            match field {
+                Some(value) => {
+                    encoded.push(1);
+                    encoded.extend((value.len() as u64).to_be_bytes());
+                    encoded.extend(value.as_bytes());
+                }
+                None => encoded.push(0),
+            }
+        }
+        encoded.extend(self.purchase_date.to_julian_day().to_be_bytes());
+        encoded
     }
 }
 
@@ -58,20 +219,180 @@ pub struct Receipt {
 
     /// The total amount paid on the receipt.
     pub total: Price,
+
+    /// The store location the receipt is from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store: Option<StoreLocation>,
+
+    /// The register or lane the purchase was rung up on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub register_number: Option<String>,
+
+    /// The transaction number printed on the receipt.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transaction_number: Option<String>,
+
+    /// How the purchase was paid for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payment_method: Option<PaymentMethod>,
+
+    /// The amount paid before taxes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subtotal: Option<Price>,
+
+    /// The taxes charged on the purchase.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub taxes: Vec<TaxLine>,
 }
 
 impl Receipt {
     /// Determines whether this receipt is acceptable. Receipts must fulfill these requirements to be acceptable:
     /// - the receipt must have at least one item
     /// - all items must be acceptable
-    /// - the retailer name must contain only words
+    /// - the retailer name must contain only words, optionally followed by a store number such as "#1234"
+    /// - if both a subtotal and taxes are present, the subtotal plus taxes must equal the total
     pub fn is_acceptable(&self) -> bool {
         static REGEX: OnceLock<Regex> = OnceLock::new();
-        let regex = REGEX
-            .get_or_init(|| Regex::new(r"^[\w\s&-]+$").expect("retailer regex should be valid"));
+        let regex = REGEX.get_or_init(|| {
+            Regex::new(r"^[\w\s&-]+(?:#\d+)?$").expect("retailer regex should be valid")
+        });
 
         regex.is_match(&self.retailer)
             && !self.items.is_empty()
             && self.items.iter().all(Item::is_acceptable)
+            && self.totals_balance()
+    }
+
+    /// Checks that the subtotal and taxes add up to the total, if both are present.
+    fn totals_balance(&self) -> bool {
+        let Some(subtotal) = self.subtotal else {
+            return true;
+        };
+        if self.taxes.is_empty() {
+            return true;
+        }
+        let mut sum = subtotal.to_cents();
+        for tax in self.taxes.iter() {
+            sum = sum
+                .zip(tax.amount.to_cents())
+                .and_then(|(acc, amount)| acc.checked_add(amount));
+        }
+        sum.is_some() && sum == self.total.to_cents()
+    }
+
+    /// Gets the key identifying the transaction this receipt records, if the receipt has a transaction number.
+    /// `retailer` is the canonical identity of the receipt's retailer.
+    pub fn transaction_key(&self, retailer: &str) -> Option<TransactionKey> {
+        let transaction_number = self.transaction_number.clone()?;
+        Some(TransactionKey {
+            retailer: retailer.to_owned(),
+            store_id: self.store.as_ref().and_then(|s| s.store_id.clone()),
+            register_number: self.register_number.clone(),
+            transaction_number,
+            purchase_date: self.purchase_date,
+        })
+    }
+}
+
+/// A member who submits receipts and accrues points.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct User {
+    /// The member's display name.
+    pub name: String,
+
+    /// The member's email address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+}
+
+impl User {
+    /// Determines whether this user is acceptable. Users must fulfill these requirements to be acceptable:
+    /// - the name must be nonempty and contain only words
+    /// - the email, if present, must look like an email address
+    pub fn is_acceptable(&self) -> bool {
+        static NAME_REGEX: OnceLock<Regex> = OnceLock::new();
+        let name_regex = NAME_REGEX
+            .get_or_init(|| Regex::new(r"^[\w\s.'-]+$").expect("name regex should be valid"));
+        static EMAIL_REGEX: OnceLock<Regex> = OnceLock::new();
+        let email_regex = EMAIL_REGEX.get_or_init(|| {
+            Regex::new(r"^[^@\s]+@[^@\s]+\.[^@\s]+$").expect("email regex should be valid")
+        });
+
+        !self.name.trim().is_empty()
+            && name_regex.is_match(&self.name)
+            && self
+                .email
+                .as_deref()
+                .map_or(true, |e| email_regex.is_match(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gatorade() -> Item {
+        Item {
+            short_description: "Gatorade".to_owned(),
+            price: Price {
+                dollars: 9,
+                cents: 0,
+            },
+            quantity: Some(4),
+            unit_price: Some(Price {
+                dollars: 2,
+                cents: 25,
+            }),
+            sku: Some("GAT-32OZ".to_owned()),
+            upc: Some("052000338775".to_owned()),
+            category: Some("beverages".to_owned()),
+        }
+    }
+
+    #[test]
+    fn item_with_quantity() {
+        assert!(gatorade().is_acceptable());
+        assert_eq!(gatorade().units(), 4);
+    }
+
+    #[test]
+    fn item_price_mismatch() {
+        let item = Item {
+            quantity: Some(3),
+            ..gatorade()
+        };
+        assert!(!item.is_acceptable());
+        let item = Item {
+            quantity: Some(0),
+            unit_price: None,
+            ..gatorade()
+        };
+        assert!(!item.is_acceptable());
+    }
+
+    #[test]
+    fn unit_price_without_quantity() {
+        let item = Item {
+            quantity: None,
+            ..gatorade()
+        };
+        assert!(!item.is_acceptable());
+        let item = Item {
+            price: item.unit_price.unwrap(),
+            ..item
+        };
+        assert!(item.is_acceptable());
+        assert_eq!(item.units(), 1);
+    }
+
+    #[test]
+    fn gtin_check_digit() {
+        assert!(is_valid_gtin("052000338775"));
+        assert!(is_valid_gtin("4006381333931"));
+        assert!(is_valid_gtin("96385074"));
+        assert!(!is_valid_gtin("052000338776"));
+        assert!(!is_valid_gtin("05200033877"));
+        assert!(!is_valid_gtin("05200033877x"));
     }
 }
@@ -61,10 +61,35 @@ impl<'de> Deserialize<'de> for Price {
 
 #[cfg(test)]
 mod tests {
+    use std::fmt::Debug;
+
     use ::time::{Date, Time};
+    use serde::de::DeserializeOwned;
     use serde_test::{assert_tokens, Token};
 
     use super::*;
+    use crate::data::Receipt;
+
+    /// Asserts that the value encodes to the same MessagePack and CBOR as `expected`, and decodes back to itself.
+    fn assert_binary<T, E>(value: &T, expected: &E)
+    where
+        T: Serialize + DeserializeOwned + PartialEq + Debug,
+        E: Serialize + ?Sized,
+    {
+        let msgpack = rmp_serde::to_vec_named(value).unwrap();
+        assert_eq!(msgpack, rmp_serde::to_vec_named(expected).unwrap());
+        assert_eq!(&rmp_serde::from_slice::<T>(&msgpack).unwrap(), value);
+
+        let mut cbor = Vec::new();
+        ciborium::into_writer(value, &mut cbor).unwrap();
+        let mut expected_cbor = Vec::new();
+        ciborium::into_writer(expected, &mut expected_cbor).unwrap();
+        assert_eq!(cbor, expected_cbor);
+        assert_eq!(
+            &ciborium::from_reader::<T, _>(cbor.as_slice()).unwrap(),
+            value
+        );
+    }
 
     #[test]
     fn price() {
@@ -126,6 +151,29 @@ mod tests {
         );
     }
 
+    #[test]
+    fn optional_date() {
+        /// Wrapper used so serde knows to use our custom serialization.
+        #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+        struct MyDate(#[serde(with = "super::date::option")] Option<Date>);
+
+        use ::time::macros::date as d;
+        let dates = (MyDate(Some(d!(2024 - 10 - 17))), MyDate(None));
+        assert_tokens(
+            &dates,
+            &[
+                Token::Tuple { len: 2 },
+                Token::NewtypeStruct { name: "MyDate" },
+                Token::Some,
+                Token::NewtypeStruct { name: "Wrapper" },
+                Token::Str("2024-10-17"),
+                Token::NewtypeStruct { name: "MyDate" },
+                Token::None,
+                Token::TupleEnd,
+            ],
+        );
+    }
+
     #[test]
     fn time() {
         /// Wrapper used so serde knows to use our custom serialization.
@@ -155,4 +203,86 @@ mod tests {
             ],
         );
     }
+
+    #[test]
+    fn binary_price() {
+        let prices = (
+            Price {
+                dollars: 0,
+                cents: 50,
+            },
+            Price {
+                dollars: 999,
+                cents: 99,
+            },
+        );
+        assert_binary(&prices, &("0.50", "999.99"));
+    }
+
+    #[test]
+    fn binary_date() {
+        /// Wrapper used so serde knows to use our custom serialization.
+        #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+        struct MyDate(#[serde(with = "super::date")] Date);
+
+        /// Wrapper used so serde knows to use our custom serialization.
+        #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+        struct MyOptionalDate(#[serde(with = "super::date::option")] Option<Date>);
+
+        use ::time::macros::date as d;
+        assert_binary(
+            &(MyDate(d!(2024 - 10 - 17)), MyDate(d!(2000 - 02 - 29))),
+            &("2024-10-17", "2000-02-29"),
+        );
+        assert_binary(
+            &(
+                MyOptionalDate(Some(d!(2024 - 10 - 17))),
+                MyOptionalDate(None),
+            ),
+            &(Some("2024-10-17"), None::<&str>),
+        );
+    }
+
+    #[test]
+    fn binary_time() {
+        /// Wrapper used so serde knows to use our custom serialization.
+        #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+        struct MyTime(#[serde(with = "super::time")] Time);
+
+        use ::time::macros::time as t;
+        assert_binary(&(MyTime(t!(00:00)), MyTime(t!(17:15))), &("00:00", "17:15"));
+    }
+
+    #[test]
+    fn binary_receipt() {
+        let json = serde_json::json!({
+            "retailer": "M&M Corner Market",
+            "purchaseDate": "2022-03-20",
+            "purchaseTime": "14:33",
+            "total": "9.00",
+            "items": [
+                { "shortDescription": "Gatorade", "price": "2.25", "quantity": 4, "unitPrice": "2.25" }
+            ]
+        });
+        let receipt: Receipt = serde_json::from_value(json.clone()).unwrap();
+
+        // receipts are encoded as maps with the same fields as in JSON, leaving out absent optional fields
+        let msgpack = rmp_serde::to_vec_named(&receipt).unwrap();
+        assert_eq!(
+            rmp_serde::from_slice::<serde_json::Value>(&msgpack).unwrap(),
+            json
+        );
+        assert_eq!(rmp_serde::from_slice::<Receipt>(&msgpack).unwrap(), receipt);
+
+        let mut cbor = Vec::new();
+        ciborium::into_writer(&receipt, &mut cbor).unwrap();
+        assert_eq!(
+            ciborium::from_reader::<serde_json::Value, _>(cbor.as_slice()).unwrap(),
+            json
+        );
+        assert_eq!(
+            ciborium::from_reader::<Receipt, _>(cbor.as_slice()).unwrap(),
+            receipt
+        );
+    }
 }
@@ -29,4 +29,29 @@ pub fn deserialize<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<
     }
 
     deserializer.deserialize_str(DateVisitor)
-}
\ No newline at end of file
+}
+
+/// Custom serialization for optional dates, e.g. in query strings.
+pub mod option {
+    use serde::{Deserialize, Serialize};
+    use time::Date;
+
+    /// Wrapper used so serde knows to use our custom serialization.
+    #[derive(Serialize, Deserialize)]
+    struct Wrapper(#[serde(with = "super")] Date);
+
+    /// Serializes an optional Date to a yyyy-MM-dd string.
+    pub fn serialize<S: serde::Serializer>(
+        v: &Option<Date>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        v.map(Wrapper).serialize(serializer)
+    }
+
+    /// Deserializes an optional date from a yyyy-MM-dd string.
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Date>, D::Error> {
+        Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|w| w.0))
+    }
+}
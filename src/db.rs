@@ -1,42 +1,1611 @@
-use std::{sync::{RwLock, Arc}, collections::HashMap};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock, RwLockReadGuard,
+    },
+};
 
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
 use uuid::Uuid;
 
-use crate::data::Receipt;
+use persistence::{
+    Persistence, Snapshot, StoredCampaign, StoredReceipt, StoredSubscription, StoredUser, WalEntry,
+};
+use shards::Shards;
 
+pub use ledger::{EarnMismatch, LedgerCheck};
+
+use crate::{
+    audit::AuditEntry,
+    campaigns::{self, Campaign},
+    config::{
+        EventsConfig, FraudConfig, LedgerConfig, PersistenceConfig, StoreConfig, WebhookConfig,
+    },
+    data::{Receipt, TransactionKey, User},
+    events::{EventBuffer, ReceiptEvent},
+    fraud::{self, RiskAssessment},
+    ledger::{Ledger, ReceiptAdjustment, Transaction, TransactionKind},
+    points::{PointsAward, RulesetRegistry},
+    retailers::{self, RetailerRegistry},
+    review::{ReceiptStatus, Review},
+    webhooks::{DeliveryLog, Dispatcher, Subscription},
+};
+
+/// Contains manual points adjustments and the audit log's storage.
+mod audit;
+/// Contains the points ledger's storage.
+mod ledger;
+/// Contains persistence of the database to disk.
+mod persistence;
+/// Contains the review queue's storage.
+mod review;
+/// Contains the sharding of database tables.
+mod shards;
+/// Contains the sampling of receipts for reporting statistics.
+mod stats;
+/// Contains webhook subscriptions' storage and the notification of subscribers.
+mod webhooks;
+
+/// A receipt as stored in the database, along with data derived from it when it was stored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReceiptRecord {
+    /// The receipt as it was submitted.
+    #[serde(flatten)]
+    pub receipt: Receipt,
+
+    /// The ID of the canonical retailer the receipt is from, if the retailer is known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retailer_id: Option<String>,
+
+    /// The name of the canonical retailer the receipt is from, if the retailer is known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retailer_name: Option<String>,
+
+    /// The ID of the member who submitted the receipt, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner_id: Option<Uuid>,
+
+    /// When the receipt was stored.
+    #[serde(with = "time::serde::rfc3339")]
+    pub stored_at: OffsetDateTime,
+
+    /// The points awarded for the receipt when it was stored, or when they were last recalculated.
+    #[serde(default)]
+    pub award: PointsAward,
+
+    /// The risk that the receipt is fraudulent, scored when it was stored.
+    #[serde(default)]
+    pub risk: RiskAssessment,
+
+    /// Where the receipt is in the review process. Receipts are held for review if their risk score is too high.
+    #[serde(default)]
+    pub status: ReceiptStatus,
+
+    /// The decision made on the receipt, if it was reviewed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub review: Option<Review>,
+
+    /// Changes staff have made to the points awarded for the receipt, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub adjustments: Vec<ReceiptAdjustment>,
+}
+
+impl ReceiptRecord {
+    /// Gets the canonical name of the retailer the receipt is from, or the name on the receipt if the retailer is
+    /// not known.
+    pub fn retailer_name(&self) -> &str {
+        self.retailer_name
+            .as_deref()
+            .unwrap_or(&self.receipt.retailer)
+    }
+
+    /// Gets the key identifying the transaction the receipt records, if it has a transaction number. Receipts from a
+    /// known retailer are keyed by the retailer's ID, so the same transaction is recognized under any spelling.
+    pub fn transaction_key(&self) -> Option<TransactionKey> {
+        match &self.retailer_id {
+            Some(retailer_id) => self.receipt.transaction_key(retailer_id),
+            None => self
+                .receipt
+                .transaction_key(&retailers::normalize(&self.receipt.retailer)),
+        }
+    }
+
+    /// Gets the points for the receipt: the points awarded for it, plus any adjustments. Adjustments never take the
+    /// points below zero. Receipts that aren't approved are worth no points.
+    pub fn points(&self) -> u64 {
+        if self.status != ReceiptStatus::Approved {
+            return 0;
+        }
+        self.adjusted_points(self.adjustments.len())
+    }
+
+    /// Gets the points awarded for the receipt plus its first `count` adjustments, whatever its status. Adjustments
+    /// never take the points below zero.
+    fn adjusted_points(&self, count: usize) -> u64 {
+        let adjustment = self.adjustments[..count]
+            .iter()
+            .fold(0i64, |total, adjustment| {
+                total.saturating_add(adjustment.points)
+            });
+        if adjustment < 0 {
+            self.award.points.saturating_sub(adjustment.unsigned_abs())
+        } else {
+            self.award.points.saturating_add(adjustment.unsigned_abs())
+        }
+    }
+
+    /// Gets the points the adjustment at `index` credits to the receipt's owner once the receipt is approved: the
+    /// change it makes to the receipt's points. As the points never go below zero, this may be less than the
+    /// adjustment, or nothing at all.
+    fn credited_points(&self, index: usize) -> i64 {
+        let before = i64::try_from(self.adjusted_points(index)).unwrap_or(i64::MAX);
+        let after = i64::try_from(self.adjusted_points(index + 1)).unwrap_or(i64::MAX);
+        after - before
+    }
+}
+
+/// A shard of the receipts in our database, along with the indexes over them. These are kept together so they can be
+/// updated under a single lock.
+#[derive(Debug, Default)]
+struct ReceiptTable {
+    /// The receipts, by ID.
+    records: HashMap<Uuid, ReceiptRecord>,
+
+    /// Index of receipt IDs by transaction, used to deduplicate submissions of the same receipt.
+    transactions: HashMap<TransactionKey, Uuid>,
+
+    /// Receipt IDs in the order they were stored, oldest first, with the sequence numbers they were stored under.
+    /// Used to evict the oldest receipts. IDs of receipts already evicted are skipped.
+    order: VecDeque<(u64, Uuid)>,
+}
+
+impl ReceiptTable {
+    /// Adds a receipt stored under the given sequence number, replacing any receipt with the same ID. Returns whether
+    /// the receipt is new.
+    fn insert(&mut self, sequence: u64, id: Uuid, record: ReceiptRecord) -> bool {
+        if let Some(key) = record.transaction_key() {
+            self.transactions.insert(key, id);
+        }
+        let added = self.records.insert(id, record).is_none();
+        if added {
+            self.order.push_back((sequence, id));
+        }
+        added
+    }
+
+    /// Removes a receipt. Returns whether there was a receipt to remove.
+    fn evict(&mut self, id: Uuid) -> bool {
+        let Some(record) = self.records.remove(&id) else {
+            return false;
+        };
+        if let Some(key) = record.transaction_key() {
+            self.transactions.remove(&key);
+        }
+        true
+    }
+
+    /// Gets the sequence number and ID of the oldest receipt, first dropping the IDs of evicted receipts from the front
+    /// of the queue.
+    fn oldest(&mut self) -> Option<(u64, Uuid)> {
+        while let Some(&(sequence, id)) = self.order.front() {
+            if self.records.contains_key(&id) {
+                return Some((sequence, id));
+            }
+            self.order.pop_front();
+        }
+        None
+    }
+}
+
+/// The IDs of the most recently evicted receipts, so reads can tell them apart from IDs that never existed.
+#[derive(Debug, Default)]
+struct EvictedIds {
+    ids: HashSet<Uuid>,
+
+    /// `ids` in the order the receipts were evicted, oldest first, used to bound its size.
+    order: VecDeque<Uuid>,
+}
+
+/// Counts of receipts removed from the database to bound its size.
+#[derive(Debug, Default)]
+pub struct EvictionMetrics {
+    /// Receipts evicted to make room for new receipts.
+    pub capacity: AtomicU64,
+
+    /// Receipts evicted because they outlived their time to live.
+    pub expired: AtomicU64,
+}
+
+/// Where a receipt ended up when it was stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stored {
+    /// The receipt was stored under the ID.
+    New(Uuid),
+    /// A receipt for the same transaction, with the same owner, was already stored under the ID.
+    Duplicate(Uuid),
+    /// A receipt for the same transaction was already stored under the ID, but with a different owner.
+    Conflict(Uuid),
+}
+
+impl Stored {
+    /// Gets the ID of the stored receipt.
+    pub fn id(self) -> Uuid {
+        match self {
+            Self::New(id) | Self::Duplicate(id) | Self::Conflict(id) => id,
+        }
+    }
+}
 
 /// A "database connection". In a real application, this would connect to an actual database and wouldn't have terrible scaling properties.
 #[derive(Debug, Clone)]
 pub struct Connection {
     /// The receipts in our database.
-    receipts: Arc<RwLock<HashMap<Uuid, Receipt>>>,
+    receipts: Arc<Shards<ReceiptTable>>,
+
+    /// When receipts are evicted from the database.
+    eviction: Arc<StoreConfig>,
+
+    /// The number of receipts in the database, across every shard, so their total can be bounded.
+    receipt_total: Arc<AtomicUsize>,
+
+    /// The sequence number of the next receipt stored, used to find the oldest receipt across every shard.
+    next_sequence: Arc<AtomicU64>,
+
+    /// Held while evicting receipts for capacity, so concurrent writes don't each evict a receipt for the same overflow.
+    capacity_eviction: Arc<Mutex<()>>,
+
+    /// The IDs of the most recently evicted receipts.
+    evicted: Arc<Mutex<EvictedIds>>,
+
+    /// Counts of evicted receipts.
+    evictions: Arc<EvictionMetrics>,
+
+    /// The known retailers, used to assign receipts a canonical retailer.
+    retailers: Arc<RetailerRegistry>,
+
+    /// The rulesets receipts are awarded points under.
+    rulesets: Arc<RulesetRegistry>,
+
+    /// The members in our database.
+    users: Arc<Shards<HashMap<Uuid, User>>>,
+
+    /// The promotional campaigns awarding bonus points. There are few enough that a single lock suffices.
+    campaigns: Arc<RwLock<HashMap<Uuid, Campaign>>>,
+
+    /// Each member's points ledger, by member ID.
+    ledgers: Arc<Shards<HashMap<Uuid, Ledger>>>,
+
+    /// How long earned points last before expiring, if they expire.
+    points_expiry: Option<Duration>,
+
+    /// How receipts are scored for fraud, and when they are held for review.
+    fraud: Arc<FraudConfig>,
+
+    /// The webhook subscriptions. There are few enough that a single lock suffices.
+    subscriptions: Arc<RwLock<HashMap<Uuid, Subscription>>>,
+
+    /// How webhook notifications are delivered.
+    webhook_config: Arc<WebhookConfig>,
+
+    /// The most recent attempts to deliver webhook notifications.
+    deliveries: Arc<DeliveryLog>,
+
+    /// Delivers webhook notifications in the background.
+    dispatcher: Arc<Dispatcher>,
+
+    /// The events published to live dashboards as receipts are stored.
+    events: Arc<EventBuffer>,
+
+    /// The audit log of changes staff have made to members' points, oldest first. Entries are only ever appended.
+    audit_log: Arc<RwLock<Vec<AuditEntry>>>,
+
+    /// The files our database is persisted to, if any.
+    persistence: Option<Arc<Persistence>>,
 }
 
+impl Default for Connection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 /// Implementation of our fake connection. In a real application, this would make remote calls to the database, hence the
 /// functions being marked `async`.
 impl Connection {
     /// Constructs a new "connection" to the "database".
     pub fn new() -> Self {
+        Self::with_retailers(RetailerRegistry::default())
+    }
+
+    /// Constructs a new "connection" to the "database" that resolves receipts against the given retailers.
+    pub fn with_retailers(retailers: RetailerRegistry) -> Self {
         Self {
             receipts: Default::default(),
+            eviction: Default::default(),
+            receipt_total: Default::default(),
+            next_sequence: Default::default(),
+            capacity_eviction: Default::default(),
+            evicted: Default::default(),
+            evictions: Default::default(),
+            retailers: Arc::new(retailers),
+            rulesets: Default::default(),
+            users: Default::default(),
+            campaigns: Default::default(),
+            ledgers: Default::default(),
+            points_expiry: None,
+            fraud: Default::default(),
+            subscriptions: Default::default(),
+            webhook_config: Default::default(),
+            deliveries: Default::default(),
+            dispatcher: Default::default(),
+            events: Default::default(),
+            audit_log: Default::default(),
+            persistence: None,
+        }
+        .with_eviction(StoreConfig::default())
+    }
+
+    /// Sets the rulesets receipts are awarded points under. By default, only the standard ruleset is used.
+    pub fn with_rulesets(self, rulesets: RulesetRegistry) -> Self {
+        Self {
+            rulesets: Arc::new(rulesets),
+            ..self
+        }
+    }
+
+    /// Sets how the points ledger behaves. By default, points never expire.
+    pub fn with_ledger(self, config: &LedgerConfig) -> Self {
+        Self {
+            points_expiry: config
+                .expiry_days
+                .map(|days| Duration::days(days.try_into().unwrap_or(i64::MAX))),
+            ..self
+        }
+    }
+
+    /// Sets how receipts are scored for fraud. By default, receipts are scored but never held for review.
+    pub fn with_fraud(self, config: &FraudConfig) -> Self {
+        Self {
+            fraud: Arc::new(config.clone()),
+            ..self
         }
     }
 
+    /// Sets how webhook notifications are delivered.
+    pub fn with_webhooks(self, config: &WebhookConfig) -> Self {
+        Self {
+            webhook_config: Arc::new(config.clone()),
+            ..self
+        }
+    }
+
+    /// Sets how many events are kept for clients resuming the event stream. By default, 1000 are kept.
+    pub fn with_events(self, config: &EventsConfig) -> Self {
+        Self {
+            events: Arc::new(EventBuffer::new(config.buffer_capacity)),
+            ..self
+        }
+    }
+
+    /// Sets how the database is sharded and when receipts are evicted from it. By default, receipts are never evicted.
+    /// The database is emptied, so this should be called before anything is stored.
+    pub fn with_eviction(self, eviction: StoreConfig) -> Self {
+        Self {
+            receipts: Arc::new(Shards::new(eviction.shards)),
+            receipt_total: Default::default(),
+            evicted: Default::default(),
+            users: Arc::new(Shards::new(eviction.shards)),
+            ledgers: Arc::new(Shards::new(eviction.shards)),
+            eviction: Arc::new(eviction),
+            ..self
+        }
+    }
+
+    /// Persists the database to the configured directory, first loading any data previously persisted there.
+    /// If no directory is configured, the database is not persisted.
+    pub fn with_persistence(self, config: &PersistenceConfig) -> io::Result<Self> {
+        let Some(directory) = &config.directory else {
+            return Ok(self);
+        };
+        let (persistence, snapshot, entries) = Persistence::open(directory, config.sync_writes)?;
+
+        let Snapshot {
+            mut receipts,
+            mut users,
+            campaigns,
+            mut transactions,
+            mut audit_log,
+            subscriptions,
+            evicted,
+            ..
+        } = snapshot.unwrap_or_else(|| {
+            Snapshot::new(
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+            )
+        });
+        // receipts evicted since the snapshot are still in it or the log, and are evicted again once loaded
+        for id in evicted {
+            self.remember_evicted(id);
+        }
+        let mut subscriptions: HashMap<_, _> = subscriptions
+            .into_iter()
+            .map(|StoredSubscription { id, subscription }| (id, subscription))
+            .collect();
+        let mut campaigns: HashMap<_, _> = campaigns
+            .into_iter()
+            .map(|StoredCampaign { id, campaign }| (id, campaign))
+            .collect();
+        // updates to receipts are applied once every receipt is loaded
+        let mut receipt_updates = Vec::new();
+        for entry in entries {
+            match entry {
+                WalEntry::StoreReceipt(receipt) => receipts.push(*receipt),
+                WalEntry::StoreUser(user) => users.push(user),
+                WalEntry::StoreCampaign(StoredCampaign { id, campaign }) => {
+                    campaigns.insert(id, campaign);
+                }
+                WalEntry::DeleteCampaign { id } => {
+                    campaigns.remove(&id);
+                }
+                WalEntry::RecordTransaction(transaction) => transactions.push(transaction),
+                WalEntry::RecordAudit(entry) => audit_log.push(entry),
+                WalEntry::StoreSubscription(StoredSubscription { id, subscription }) => {
+                    subscriptions.insert(id, subscription);
+                }
+                WalEntry::DeleteSubscription { id } => {
+                    subscriptions.remove(&id);
+                }
+                entry @ (WalEntry::AdjustReceipt { .. } | WalEntry::ReviewReceipt { .. }) => {
+                    receipt_updates.push(entry)
+                }
+            }
+        }
+        for StoredReceipt { id, record } in receipts {
+            let sequence = self.next_sequence.fetch_add(1, Ordering::Relaxed);
+            if self.receipts.write(id).insert(sequence, id, record) {
+                self.receipt_total.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        for entry in receipt_updates {
+            match entry {
+                WalEntry::AdjustReceipt {
+                    receipt_id,
+                    adjustment,
+                } => {
+                    let mut table = self.receipts.write(receipt_id);
+                    let Some(record) = table.records.get_mut(&receipt_id) else {
+                        continue;
+                    };
+                    // an adjustment made while a snapshot was written may be in both the snapshot and the log
+                    if record.adjustments.iter().all(|a| a.id != adjustment.id) {
+                        record.adjustments.push(adjustment);
+                    }
+                }
+                WalEntry::ReviewReceipt { receipt_id, review } => {
+                    if let Some(record) =
+                        self.receipts.write(receipt_id).records.get_mut(&receipt_id)
+                    {
+                        record.status = review.status;
+                        record.review = Some(review);
+                    }
+                }
+                _ => unreachable!("only updates to receipts are deferred"),
+            }
+        }
+        let now = OffsetDateTime::now_utc();
+        for mut table in self.receipts.write_each() {
+            self.evict_expired(&mut table, now);
+        }
+        self.evict_over_capacity();
+        for StoredUser { id, user } in users {
+            self.users.write(id).insert(id, user);
+        }
+        *self.campaigns.write().unwrap_or_else(|e| e.into_inner()) = campaigns;
+        for transaction in transactions {
+            let user_id = transaction.user_id;
+            self.ledgers
+                .write(user_id)
+                .entry(user_id)
+                .or_default()
+                .record(transaction);
+        }
+        *self.audit_log.write().unwrap_or_else(|e| e.into_inner()) = audit_log;
+        *self
+            .subscriptions
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = subscriptions;
+
+        Ok(Self {
+            persistence: Some(Arc::new(persistence)),
+            ..self
+        })
+    }
+
+    /// Writes a snapshot of the database to disk, if the database is persisted.
+    pub async fn snapshot(&self) -> io::Result<()> {
+        let Some(persistence) = &self.persistence else {
+            return Ok(());
+        };
+        persistence.write_snapshot(|| {
+            let mut receipts = Vec::new();
+            for table in self.receipts.read_each() {
+                receipts.extend(table.order.iter().filter_map(|&(sequence, id)| {
+                    let record = table.records.get(&id)?.clone();
+                    Some((sequence, StoredReceipt { id, record }))
+                }));
+            }
+            // write receipts in the order they were stored so eviction order survives a restart
+            receipts.sort_unstable_by_key(|&(sequence, _)| sequence);
+            let receipts = receipts.into_iter().map(|(_, receipt)| receipt).collect();
+            let mut users = Vec::new();
+            for table in self.users.read_each() {
+                users.extend(table.iter().map(|(&id, user)| StoredUser {
+                    id,
+                    user: user.clone(),
+                }));
+            }
+            let campaigns = self
+                .campaigns
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .iter()
+                .map(|(&id, campaign)| StoredCampaign {
+                    id,
+                    campaign: campaign.clone(),
+                })
+                .collect();
+            let mut transactions = Vec::new();
+            for table in self.ledgers.read_each() {
+                for ledger in table.values() {
+                    transactions.extend(ledger.transactions().iter().cloned());
+                }
+            }
+            let audit_log = self
+                .audit_log
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone();
+            let subscriptions = self
+                .subscriptions
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .iter()
+                .map(|(&id, subscription)| StoredSubscription {
+                    id,
+                    subscription: subscription.clone(),
+                })
+                .collect();
+            let evicted = self
+                .evicted
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .order
+                .iter()
+                .copied()
+                .collect();
+            Snapshot::new(
+                receipts,
+                users,
+                campaigns,
+                transactions,
+                audit_log,
+                subscriptions,
+                evicted,
+            )
+        })
+    }
+
+    /// Blocks snapshots until the returned guard is dropped, if the database is persisted. Writes take this before
+    /// locking any table, and hold it until they're applied.
+    fn begin_write(&self) -> Option<RwLockReadGuard<'_, ()>> {
+        self.persistence
+            .as_ref()
+            .map(|persistence| persistence.begin_write())
+    }
+
+    /// Appends the entries making up a write to the write-ahead log, if the database is persisted. Writes are logged
+    /// before they're applied in memory, and aren't applied if logging fails, so every acknowledged write survives a
+    /// restart.
+    fn log(&self, entries: &[WalEntry]) -> io::Result<()> {
+        let Some(persistence) = &self.persistence else {
+            return Ok(());
+        };
+        persistence
+            .append(entries)
+            .inspect_err(|e| eprintln!("failed to append to write-ahead log: {e}"))
+    }
+
+    /// Gets the events published as receipts are stored.
+    pub fn events(&self) -> &EventBuffer {
+        &self.events
+    }
+
+    /// Gets the rulesets receipts are awarded points under.
+    pub fn rulesets(&self) -> &RulesetRegistry {
+        &self.rulesets
+    }
+
+    /// Awards points for the receipt under the ruleset in effect on its purchase date, plus the bonuses of the
+    /// campaigns running on that date.
+    fn award(&self, record: &ReceiptRecord) -> PointsAward {
+        let mut award = self
+            .rulesets
+            .for_date(record.receipt.purchase_date)
+            .award(record);
+        let campaigns = self.campaigns.read().unwrap_or_else(|e| e.into_inner());
+        award.bonuses = campaigns::bonuses(&campaigns, record, award.points);
+        award.points = award.bonuses.iter().fold(award.points, |points, bonus| {
+            points.saturating_add(bonus.points)
+        });
+        award
+    }
+
+    /// Gets the counts of receipts evicted from the database.
+    pub fn evictions(&self) -> &EvictionMetrics {
+        &self.evictions
+    }
+
+    /// Determines whether the receipt has outlived its time to live.
+    fn is_expired(&self, record: &ReceiptRecord, now: OffsetDateTime) -> bool {
+        self.eviction
+            .ttl_seconds
+            .is_some_and(|ttl| record.stored_at + Duration::seconds(ttl as i64) <= now)
+    }
+
     /// Stores the data for a receipt in the database, returning its database ID.
-    /// If the receipt cannot be stored, returns None.
-    pub async fn store_receipt(&self, receipt: Receipt) -> Option<Uuid> {
+    /// If the receipt cannot be stored, returns None. If a receipt for the same transaction has already been stored,
+    /// returns the ID of the existing receipt instead of storing a duplicate.
+    /// The receipt is assigned a canonical retailer if its retailer is known, and is owned by the given member. The
+    /// points awarded for the receipt are credited to its owner's ledger, unless its risk score holds it for review.
+    /// Fails if the receipt can't be persisted, in which case it isn't stored.
+    pub async fn store_receipt(
+        &self,
+        receipt: Receipt,
+        owner_id: Option<Uuid>,
+    ) -> io::Result<Option<Uuid>> {
+        Ok(self
+            .store_new_receipt(receipt, owner_id)
+            .await?
+            .map(Stored::id))
+    }
+
+    /// Stores the data for a receipt in the database like [`Connection::store_receipt`], telling a newly stored
+    /// receipt apart from a duplicate of one already stored. A receipt for a transaction already stored under another
+    /// owner is a conflict rather than a duplicate, so one member's receipt is never mistaken for another's.
+    pub async fn store_new_receipt(
+        &self,
+        receipt: Receipt,
+        owner_id: Option<Uuid>,
+    ) -> io::Result<Option<Stored>> {
         if !receipt.is_acceptable() {
+            return Ok(None);
+        }
+        let retailer = self.retailers.resolve(&receipt.retailer);
+        let now = OffsetDateTime::now_utc();
+        let risk = fraud::assess(&receipt, &self.fraud.weights);
+        let status = if self
+            .fraud
+            .hold_threshold
+            .is_some_and(|threshold| risk.score >= threshold)
+        {
+            ReceiptStatus::Pending
+        } else {
+            ReceiptStatus::Approved
+        };
+        let mut receipt = ReceiptRecord {
+            retailer_id: retailer.map(|r| r.id.clone()),
+            retailer_name: retailer.map(|r| r.name.clone()),
+            owner_id,
+            stored_at: now,
+            award: PointsAward::default(),
+            risk,
+            status,
+            review: None,
+            adjustments: Vec::new(),
+            receipt,
+        };
+        receipt.award = self.award(&receipt);
+        let key = receipt.transaction_key();
+
+        // receipts for the same transaction share a shard, so duplicates can be found under a single lock
+        let id = key
+            .as_ref()
+            .map_or_else(Uuid::new_v4, |key| shards::id_for_key(&key.encode()));
+        {
+            let _write = self.begin_write();
+            let mut table = self.receipts.write(id);
+            self.evict_expired(&mut table, now);
+            if let Some(&id) = key.as_ref().and_then(|key| table.transactions.get(key)) {
+                return Ok(Some(match table.records.get(&id) {
+                    Some(existing) if existing.owner_id != owner_id => Stored::Conflict(id),
+                    _ => Stored::Duplicate(id),
+                }));
+            }
+            let entry = WalEntry::StoreReceipt(Box::new(StoredReceipt {
+                id,
+                record: receipt.clone(),
+            }));
+            // the receipt and its earn are logged together, so a receipt is never persisted without its points
+            match owner_id.filter(|_| status == ReceiptStatus::Approved) {
+                Some(owner_id) => {
+                    let earn = self.earn(owner_id, id, &receipt, now);
+                    self.update_ledger(owner_id, vec![entry], |ledger| {
+                        ledger.record(earn);
+                        Ok::<_, io::Error>(())
+                    })?;
+                }
+                None => self.log(&[entry])?,
+            }
+            let sequence = self.next_sequence.fetch_add(1, Ordering::Relaxed);
+            if table.insert(sequence, id, receipt.clone()) {
+                self.receipt_total.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.evict_over_capacity();
+        self.events.publish(ReceiptEvent {
+            receipt_id: id,
+            retailer: receipt.retailer_name().to_owned(),
+            points: receipt.points(),
+        });
+        self.notify_stored(id, &receipt);
+        Ok(Some(Stored::New(id)))
+    }
+
+    /// Constructs the transaction crediting a receipt's awarded points to its owner.
+    fn earn(
+        &self,
+        owner_id: Uuid,
+        receipt_id: Uuid,
+        record: &ReceiptRecord,
+        now: OffsetDateTime,
+    ) -> Transaction {
+        let kind = TransactionKind::Earn {
+            receipt_id,
+            expires_at: self.points_expiry.map(|expiry| now + expiry),
+        };
+        let points = i64::try_from(record.award.points).unwrap_or(i64::MAX);
+        Transaction::new(owner_id, points, kind)
+    }
+
+    /// Evicts a receipt from its shard, remembering its ID so reads report it as expired. Returns whether there was a
+    /// receipt to evict.
+    fn evict(&self, table: &mut ReceiptTable, id: Uuid) -> bool {
+        if !table.evict(id) {
+            return false;
+        }
+        self.receipt_total.fetch_sub(1, Ordering::Relaxed);
+        self.remember_evicted(id);
+        true
+    }
+
+    /// Remembers the ID of an evicted receipt, forgetting the oldest remembered ID if there are too many.
+    fn remember_evicted(&self, id: Uuid) {
+        let capacity = self.eviction.evicted_id_capacity;
+        if capacity == 0 {
+            return;
+        }
+        let mut evicted = self.evicted.lock().unwrap_or_else(|e| e.into_inner());
+        if !evicted.ids.insert(id) {
+            return;
+        }
+        if evicted.order.len() >= capacity {
+            if let Some(oldest) = evicted.order.pop_front() {
+                evicted.ids.remove(&oldest);
+            }
+        }
+        evicted.order.push_back(id);
+    }
+
+    /// Evicts the shard's receipts that have outlived their time to live.
+    fn evict_expired(&self, table: &mut ReceiptTable, now: OffsetDateTime) {
+        // receipts are stored in order, so expired receipts are at the front of the queue
+        while let Some((_, id)) = table.oldest() {
+            if !self.is_expired(&table.records[&id], now) {
+                break;
+            }
+            self.evict(table, id);
+            self.evictions.expired.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Evicts the oldest receipts across every shard until the database is within its capacity. Shards are locked one
+    /// at a time, so this must be called without holding any of them.
+    fn evict_over_capacity(&self) {
+        let Some(capacity) = self.eviction.capacity else {
+            return;
+        };
+        if self.receipt_total.load(Ordering::Relaxed) <= capacity {
+            return;
+        }
+        let _evicting = self
+            .capacity_eviction
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        while self.receipt_total.load(Ordering::Relaxed) > capacity {
+            // each shard's oldest receipt is at the front of its queue, and the oldest of those is the oldest overall
+            let oldest = self
+                .receipts
+                .write_each()
+                .filter_map(|mut table| table.oldest())
+                .min();
+            let Some((_, id)) = oldest else {
+                break;
+            };
+            if self.evict(&mut self.receipts.write(id), id) {
+                self.evictions.capacity.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Loads a receipt by ID from the database. Returns None if there is no receipt for the ID, or if the receipt
+    /// has been evicted.
+    pub async fn load_receipt(&self, id: Uuid) -> Option<ReceiptRecord> {
+        let now = OffsetDateTime::now_utc();
+        {
+            // cloning the underlying receipt here because in a real database we'd be constructing a new value.
+            let table = self.receipts.read(id);
+            let record = table.records.get(&id)?;
+            if !self.is_expired(record, now) {
+                return Some(record.clone());
+            }
+        }
+        if self.evict(&mut self.receipts.write(id), id) {
+            self.evictions.expired.fetch_add(1, Ordering::Relaxed);
+        }
+        None
+    }
+
+    /// Loads the points for a receipt by ID, including any adjustments. Receipts stored before points were awarded on
+    /// storage are awarded points now. Returns None if there is no receipt for the ID, or if the receipt has been
+    /// evicted.
+    pub async fn load_points(&self, id: Uuid) -> Option<u64> {
+        let now = OffsetDateTime::now_utc();
+        {
+            let table = self.receipts.read(id);
+            let record = table.records.get(&id)?;
+            if !self.is_expired(record, now) && record.award.is_awarded() {
+                return Some(record.points());
+            }
+        }
+
+        // either the receipt expired or it hasn't been awarded points, and both need a write
+        let mut table = self.receipts.write(id);
+        if self.is_expired(table.records.get(&id)?, now) {
+            if self.evict(&mut table, id) {
+                self.evictions.expired.fetch_add(1, Ordering::Relaxed);
+            }
             return None;
         }
+        let record = table.records.get_mut(&id)?;
+        if !record.award.is_awarded() {
+            record.award = self.award(record);
+        }
+        Some(record.points())
+    }
+
+    /// Determines whether the receipt with the given ID was evicted from the database.
+    pub async fn receipt_evicted(&self, id: Uuid) -> bool {
+        self.evicted
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .ids
+            .contains(&id)
+    }
+
+    /// Gets the number of receipts in the database.
+    pub async fn receipt_count(&self) -> usize {
+        self.receipts
+            .read_each()
+            .map(|table| table.records.len())
+            .sum()
+    }
+
+    /// Loads every receipt owned by the given member, with the points awarded for each.
+    pub async fn load_receipts_for_owner(&self, owner_id: Uuid) -> Vec<ReceiptRecord> {
+        let now = OffsetDateTime::now_utc();
+        let mut records = Vec::new();
+        for table in self.receipts.read_each() {
+            records.extend(
+                table
+                    .records
+                    .values()
+                    .filter(|record| {
+                        record.owner_id == Some(owner_id) && !self.is_expired(record, now)
+                    })
+                    .map(|record| {
+                        let mut record = record.clone();
+                        if !record.award.is_awarded() {
+                            record.award = self.award(&record);
+                        }
+                        record
+                    }),
+            );
+        }
+        records
+    }
+
+    /// Loads the IDs of every receipt in the database, oldest first.
+    pub async fn load_receipt_ids(&self) -> Vec<Uuid> {
+        let now = OffsetDateTime::now_utc();
+        let mut ids = Vec::new();
+        for table in self.receipts.read_each() {
+            ids.extend(
+                table
+                    .records
+                    .iter()
+                    .filter(|(_, record)| !self.is_expired(record, now))
+                    .map(|(&id, record)| (record.stored_at, id)),
+            );
+        }
+        ids.sort_unstable();
+        ids.into_iter().map(|(_, id)| id).collect()
+    }
+
+    /// Loads the receipts with the given IDs, with the points awarded for each, in the order given. Receipts evicted
+    /// since their IDs were loaded are skipped.
+    pub async fn load_receipts(&self, ids: &[Uuid]) -> Vec<(Uuid, ReceiptRecord)> {
+        let now = OffsetDateTime::now_utc();
+        ids.iter()
+            .filter_map(|&id| {
+                let table = self.receipts.read(id);
+                let record = table.records.get(&id)?;
+                if self.is_expired(record, now) {
+                    return None;
+                }
+                let mut record = record.clone();
+                if !record.award.is_awarded() {
+                    record.award = self.award(&record);
+                }
+                Some((id, record))
+            })
+            .collect()
+    }
+
+    /// Stores a new member in the database, returning their database ID.
+    /// If the member cannot be stored, returns None. Fails if the member can't be persisted.
+    pub async fn store_user(&self, user: User) -> io::Result<Option<Uuid>> {
+        if !user.is_acceptable() {
+            return Ok(None);
+        }
+        let id = Uuid::new_v4();
+        let _write = self.begin_write();
+        let mut table = self.users.write(id);
+        self.log(&[WalEntry::StoreUser(StoredUser {
+            id,
+            user: user.clone(),
+        })])?;
+        table.insert(id, user);
+        Ok(Some(id))
+    }
+
+    /// Loads a member by ID from the database. Returns None if there is no member for the ID.
+    pub async fn load_user(&self, id: Uuid) -> Option<User> {
+        self.users.read(id).get(&id).cloned()
+    }
+
+    /// Stores a new campaign in the database, returning its database ID. Campaigns only award bonuses to receipts
+    /// stored after them. If the campaign cannot be stored, returns None. Fails if the campaign can't be persisted.
+    pub async fn store_campaign(&self, campaign: Campaign) -> io::Result<Option<Uuid>> {
+        if !campaign.is_acceptable() {
+            return Ok(None);
+        }
         let id = Uuid::new_v4();
-        self.receipts.write().unwrap().insert(id, receipt);
-        Some(id)
+        let _write = self.begin_write();
+        let mut campaigns = self.campaigns.write().unwrap_or_else(|e| e.into_inner());
+        self.log(&[WalEntry::StoreCampaign(StoredCampaign {
+            id,
+            campaign: campaign.clone(),
+        })])?;
+        campaigns.insert(id, campaign);
+        Ok(Some(id))
+    }
+
+    /// Replaces the campaign with the given ID. Returns false if there is no campaign for the ID, or if the new
+    /// campaign cannot be stored. Fails if the new campaign can't be persisted.
+    pub async fn replace_campaign(&self, id: Uuid, campaign: Campaign) -> io::Result<bool> {
+        if !campaign.is_acceptable() {
+            return Ok(false);
+        }
+        let _write = self.begin_write();
+        let mut campaigns = self.campaigns.write().unwrap_or_else(|e| e.into_inner());
+        let Some(existing) = campaigns.get_mut(&id) else {
+            return Ok(false);
+        };
+        self.log(&[WalEntry::StoreCampaign(StoredCampaign {
+            id,
+            campaign: campaign.clone(),
+        })])?;
+        *existing = campaign;
+        Ok(true)
+    }
+
+    /// Deletes the campaign with the given ID. Returns false if there is no campaign for the ID. Fails if the
+    /// deletion can't be persisted.
+    pub async fn delete_campaign(&self, id: Uuid) -> io::Result<bool> {
+        let _write = self.begin_write();
+        let mut campaigns = self.campaigns.write().unwrap_or_else(|e| e.into_inner());
+        if !campaigns.contains_key(&id) {
+            return Ok(false);
+        }
+        self.log(&[WalEntry::DeleteCampaign { id }])?;
+        campaigns.remove(&id);
+        Ok(true)
+    }
+
+    /// Loads a campaign by ID from the database. Returns None if there is no campaign for the ID.
+    pub async fn load_campaign(&self, id: Uuid) -> Option<Campaign> {
+        self.campaigns
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&id)
+            .cloned()
+    }
+
+    /// Loads every campaign in the database, ordered by ID.
+    pub async fn load_campaigns(&self) -> Vec<(Uuid, Campaign)> {
+        let mut campaigns: Vec<_> = self
+            .campaigns
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|(&id, campaign)| (id, campaign.clone()))
+            .collect();
+        campaigns.sort_by_key(|&(id, _)| id);
+        campaigns
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+    use crate::{
+        campaigns::Reward,
+        data::{Item, Price},
+        fraud::RiskReason,
+        ledger::{LedgerError, ReasonCode},
+        points::{Rules, Ruleset},
+        retailers::Retailer,
+        review::ReviewError,
+    };
+
+    fn receipt(transaction_number: &str) -> Receipt {
+        Receipt {
+            retailer: "Target".to_owned(),
+            purchase_date: time::macros::date!(2022 - 01 - 02),
+            purchase_time: time::macros::time!(13:13),
+            items: vec![Item {
+                short_description: "Pepsi - 12-oz".to_owned(),
+                price: Price {
+                    dollars: 1,
+                    cents: 25,
+                },
+                quantity: None,
+                unit_price: None,
+                sku: None,
+                upc: None,
+                category: None,
+            }],
+            total: Price {
+                dollars: 1,
+                cents: 25,
+            },
+            store: None,
+            register_number: None,
+            transaction_number: Some(transaction_number.to_owned()),
+            payment_method: None,
+            subtotal: None,
+            taxes: Vec::new(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn expired_receipts() {
+        let connection = Connection::new().with_eviction(StoreConfig {
+            ttl_seconds: Some(0),
+            ..Default::default()
+        });
+        let id = connection
+            .store_receipt(receipt("1"), None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(connection.load_receipt(id).await, None);
+        assert!(connection.receipt_evicted(id).await);
+        assert_eq!(connection.evictions().expired.load(Ordering::Relaxed), 1);
+
+        // the transaction index no longer refers to the expired receipt
+        let new_id = connection
+            .store_receipt(receipt("1"), None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_ne!(id, new_id);
+    }
+
+    #[actix_web::test]
+    async fn duplicate_spellings() {
+        let connection = Connection::with_retailers(RetailerRegistry::new(
+            vec![Retailer {
+                id: "target".to_owned(),
+                name: "Target".to_owned(),
+                aliases: vec!["Tgt".to_owned()],
+            }],
+            0.8,
+        ));
+        let id = connection
+            .store_receipt(receipt("1"), None)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // the same transaction under another spelling of the retailer is a duplicate
+        let mut respelled = receipt("1");
+        respelled.retailer = "TGT Store #12".to_owned();
+        assert_eq!(
+            connection.store_receipt(respelled, None).await.unwrap(),
+            Some(id)
+        );
+
+        // unknown retailers are only matched by their normalized name
+        let mut unknown = receipt("1");
+        unknown.retailer = "Forever 21".to_owned();
+        let forever_id = connection
+            .store_receipt(unknown.clone(), None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_ne!(forever_id, id);
+        unknown.retailer = "FOREVER 21".to_owned();
+        assert_eq!(
+            connection
+                .store_receipt(unknown.clone(), None)
+                .await
+                .unwrap(),
+            Some(forever_id)
+        );
+        unknown.retailer = "Forever 22".to_owned();
+        assert_ne!(
+            connection.store_receipt(unknown, None).await.unwrap(),
+            Some(forever_id)
+        );
+    }
+
+    #[actix_web::test]
+    async fn capacity() {
+        let connection = Connection::new().with_eviction(StoreConfig {
+            capacity: Some(2),
+            ..Default::default()
+        });
+        let first = connection
+            .store_receipt(receipt("1"), None)
+            .await
+            .unwrap()
+            .unwrap();
+        connection
+            .store_receipt(receipt("2"), None)
+            .await
+            .unwrap()
+            .unwrap();
+        connection
+            .store_receipt(receipt("3"), None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(connection.receipt_count().await, 2);
+        assert!(connection.receipt_evicted(first).await);
+        assert_eq!(connection.evictions().capacity.load(Ordering::Relaxed), 1);
+    }
+
+    #[actix_web::test]
+    async fn awarded_points() {
+        let standard = Ruleset {
+            effective_until: Some(time::macros::date!(2022 - 12 - 31)),
+            ..Ruleset::standard()
+        };
+        let doubled = Ruleset {
+            version: 2,
+            name: "doubled".to_owned(),
+            effective_from: Some(time::macros::date!(2023 - 01 - 01)),
+            effective_until: None,
+            rules: Rules {
+                quarter_total_points: 50,
+                ..Rules::default()
+            },
+        };
+        let connection =
+            Connection::new().with_rulesets(RulesetRegistry::new(vec![standard, doubled]).unwrap());
+
+        // receipts are awarded points under the ruleset in effect on their purchase date
+        let id = connection
+            .store_receipt(receipt("1"), None)
+            .await
+            .unwrap()
+            .unwrap();
+        let record = connection.load_receipt(id).await.unwrap();
+        assert_eq!(
+            record.award,
+            PointsAward {
+                points: 31,
+                ruleset_version: 1,
+                bonuses: Vec::new(),
+            }
+        );
+        let mut later = receipt("2");
+        later.purchase_date = time::macros::date!(2023 - 01 - 02);
+        let later_id = connection
+            .store_receipt(later, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(connection.load_points(later_id).await, Some(56));
+
+        // receipts stored before points were awarded on storage are awarded points when read
+        connection
+            .receipts
+            .write(id)
+            .records
+            .get_mut(&id)
+            .unwrap()
+            .award = PointsAward::default();
+        assert_eq!(connection.load_points(id).await, Some(31));
+        assert_eq!(connection.load_receipt(id).await, Some(record));
     }
 
-    /// Loads a receipt by ID from the database. Returns None if there is no receipt for the ID.
-    pub async fn load_receipt(&self, id: Uuid) -> Option<Receipt> {
-        // cloning the underlying receipt here because in a real database we'd be constructing a new value.
-        self.receipts.read().unwrap().get(&id).cloned()
+    #[actix_web::test]
+    async fn clamped_adjustments() {
+        let connection = Connection::new();
+        let user = User {
+            name: "Pat".to_owned(),
+            email: None,
+        };
+        let user_id = connection.store_user(user).await.unwrap().unwrap();
+        let id = connection
+            .store_receipt(receipt("1"), Some(user_id))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(connection.load_points(id).await, Some(31));
+
+        // the ledger is only charged the points the receipt had left, and credited only the points it regains
+        for (points, expected) in [(-100, 0), (50, 0), (30, 11)] {
+            connection
+                .adjust_receipt(id, points, ReasonCode::Correction, None, "staff".to_owned())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(connection.load_points(id).await, Some(expected));
+            let receipts: u64 = connection
+                .load_receipts_for_owner(user_id)
+                .await
+                .iter()
+                .map(ReceiptRecord::points)
+                .sum();
+            assert_eq!(
+                connection.load_ledger(user_id).await.balance(),
+                receipts as i64
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn reviewed_receipts() {
+        let connection = Connection::new().with_fraud(&FraudConfig {
+            hold_threshold: Some(20),
+            ..Default::default()
+        });
+        let user = User {
+            name: "Pat".to_owned(),
+            email: None,
+        };
+        let user_id = connection.store_user(user).await.unwrap().unwrap();
+
+        // the total is a multiple of $0.25, which is enough to reach the threshold
+        let id = connection
+            .store_receipt(receipt("1"), Some(user_id))
+            .await
+            .unwrap()
+            .unwrap();
+        let record = connection.load_receipt(id).await.unwrap();
+        assert_eq!(record.risk.reasons, vec![RiskReason::QuarterTotal]);
+        assert_eq!(record.status, ReceiptStatus::Pending);
+        assert_eq!(connection.load_points(id).await, Some(0));
+        assert_eq!(connection.load_ledger(user_id).await.balance(), 0);
+        assert!(connection.check_ledger().await.is_consistent());
+        assert_eq!(connection.load_pending_receipts().await, vec![(id, record)]);
+
+        // adjustments made while pending are credited on approval, along with the receipt's points
+        connection
+            .adjust_receipt(id, 5, ReasonCode::Goodwill, None, "staff".to_owned())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(connection.load_ledger(user_id).await.balance(), 0);
+        let record = connection
+            .approve_receipt(id, None, "staff".to_owned())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.status, ReceiptStatus::Approved);
+        assert_eq!(connection.load_points(id).await, Some(36));
+        assert_eq!(connection.load_ledger(user_id).await.balance(), 36);
+        assert!(connection.check_ledger().await.is_consistent());
+        assert!(connection.load_pending_receipts().await.is_empty());
+        assert_eq!(
+            connection
+                .reject_receipt(id, None, "staff".to_owned())
+                .await,
+            Err(ReviewError::NotPending {
+                status: ReceiptStatus::Approved
+            })
+        );
+
+        let mut unsuspicious = receipt("2");
+        unsuspicious.total.cents = 26;
+        unsuspicious.items[0].price.cents = 26;
+        let id = connection
+            .store_receipt(unsuspicious, Some(user_id))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            connection.load_receipt(id).await.unwrap().status,
+            ReceiptStatus::Approved
+        );
+        assert_eq!(connection.load_ledger(user_id).await.balance(), 36 + 6);
+
+        // revocations made while pending are charged on approval only up to the receipt's points
+        let id = connection
+            .store_receipt(receipt("3"), Some(user_id))
+            .await
+            .unwrap()
+            .unwrap();
+        for points in [-100, 50] {
+            connection
+                .adjust_receipt(id, points, ReasonCode::Fraud, None, "staff".to_owned())
+                .await
+                .unwrap()
+                .unwrap();
+        }
+        connection
+            .approve_receipt(id, None, "staff".to_owned())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(connection.load_points(id).await, Some(0));
+        assert_eq!(connection.load_ledger(user_id).await.balance(), 36 + 6);
+    }
+
+    #[actix_web::test]
+    async fn persistence() {
+        let config = PersistenceConfig {
+            directory: Some(std::env::temp_dir().join(format!("serve-ex-{}", Uuid::new_v4()))),
+            ..Default::default()
+        };
+        let open = || Connection::new().with_persistence(&config).unwrap();
+
+        let connection = open();
+        let receipt_id = connection
+            .store_receipt(receipt("1"), None)
+            .await
+            .unwrap()
+            .unwrap();
+        let user = User {
+            name: "Pat".to_owned(),
+            email: None,
+        };
+        let user_id = connection.store_user(user.clone()).await.unwrap().unwrap();
+        connection
+            .adjust_receipt(
+                receipt_id,
+                -3,
+                ReasonCode::Correction,
+                None,
+                "staff".to_owned(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        let record = connection.load_receipt(receipt_id).await.unwrap();
+        let campaign = Campaign {
+            name: "Double points".to_owned(),
+            starts_on: time::macros::date!(2022 - 01 - 01),
+            ends_on: time::macros::date!(2022 - 01 - 31),
+            retailer: None,
+            item_description: None,
+            reward: Reward::Multiplier { multiplier: 2.0 },
+            stackable: false,
+            cap: None,
+        };
+        let kept_id = connection
+            .store_campaign(campaign.clone())
+            .await
+            .unwrap()
+            .unwrap();
+        let deleted_id = connection
+            .store_campaign(campaign.clone())
+            .await
+            .unwrap()
+            .unwrap();
+        connection.delete_campaign(deleted_id).await.unwrap();
+        connection
+            .adjust_points(user_id, 5, "goodwill".to_owned(), "staff".to_owned())
+            .await
+            .unwrap();
+
+        // the directory can't be opened twice at once
+        assert!(Connection::new().with_persistence(&config).is_err());
+        drop(connection);
+
+        // writes are replayed from the log
+        let connection = open();
+        assert_eq!(
+            connection.load_receipt(receipt_id).await,
+            Some(record.clone())
+        );
+        assert_eq!(connection.load_user(user_id).await, Some(user.clone()));
+        assert_eq!(
+            connection.load_campaigns().await,
+            vec![(kept_id, campaign.clone())]
+        );
+        let audit_log = connection.load_audit_log().await;
+        assert_eq!(audit_log.len(), 2);
+
+        // writes are loaded from the snapshot, and the log is cleared
+        connection.snapshot().await.unwrap();
+        let second_id = connection
+            .store_receipt(receipt("2"), None)
+            .await
+            .unwrap()
+            .unwrap();
+        drop(connection);
+        let connection = open();
+        assert_eq!(connection.load_receipt(receipt_id).await, Some(record));
+        assert_eq!(connection.load_user(user_id).await, Some(user));
+        assert_eq!(connection.load_campaign(kept_id).await, Some(campaign));
+        assert_eq!(connection.load_ledger(user_id).await.balance(), 5);
+        assert_eq!(connection.load_audit_log().await, audit_log);
+        assert!(connection.load_receipt(second_id).await.is_some());
+        assert_eq!(connection.receipt_count().await, 2);
+
+        // restored receipts are still deduplicated
+        let duplicate_id = connection
+            .store_receipt(receipt("1"), None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(duplicate_id, receipt_id);
+
+        std::fs::remove_dir_all(config.directory.unwrap()).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn unlogged_writes() {
+        let config = PersistenceConfig {
+            directory: Some(std::env::temp_dir().join(format!("serve-ex-{}", Uuid::new_v4()))),
+            ..Default::default()
+        };
+        let open = || Connection::new().with_persistence(&config).unwrap();
+
+        let connection = open();
+        let user = User {
+            name: "Pat".to_owned(),
+            email: None,
+        };
+        let user_id = connection.store_user(user).await.unwrap().unwrap();
+        let receipt_id = connection
+            .store_receipt(receipt("1"), Some(user_id))
+            .await
+            .unwrap()
+            .unwrap();
+        let balance = connection.load_ledger(user_id).await.balance();
+
+        // writes that can't be logged aren't applied
+        connection
+            .persistence
+            .as_ref()
+            .unwrap()
+            .fail_appends()
+            .unwrap();
+        assert!(connection
+            .store_receipt(receipt("2"), Some(user_id))
+            .await
+            .is_err());
+        assert_eq!(connection.receipt_count().await, 1);
+        assert_eq!(
+            connection
+                .adjust_receipt(
+                    receipt_id,
+                    5,
+                    ReasonCode::Goodwill,
+                    None,
+                    "staff".to_owned()
+                )
+                .await,
+            Err(LedgerError::Unavailable)
+        );
+        assert_eq!(
+            connection
+                .adjust_points(user_id, 5, "goodwill".to_owned(), "staff".to_owned())
+                .await,
+            Err(LedgerError::Unavailable)
+        );
+        assert!(connection
+            .load_receipt(receipt_id)
+            .await
+            .unwrap()
+            .adjustments
+            .is_empty());
+        assert_eq!(connection.load_ledger(user_id).await.balance(), balance);
+        assert!(connection.load_audit_log().await.is_empty());
+
+        // the log still holds every write that was applied
+        drop(connection);
+        let connection = open();
+        assert_eq!(connection.receipt_count().await, 1);
+        assert_eq!(connection.load_ledger(user_id).await.balance(), balance);
+
+        std::fs::remove_dir_all(config.directory.unwrap()).unwrap();
     }
-}
\ No newline at end of file
+
+    #[actix_web::test]
+    async fn evicted_receipts_persistence() {
+        let config = PersistenceConfig {
+            directory: Some(std::env::temp_dir().join(format!("serve-ex-{}", Uuid::new_v4()))),
+            ..Default::default()
+        };
+        let open = || {
+            Connection::new()
+                .with_eviction(StoreConfig {
+                    capacity: Some(1),
+                    ..Default::default()
+                })
+                .with_persistence(&config)
+                .unwrap()
+        };
+
+        let connection = open();
+        let first_id = connection
+            .store_receipt(receipt("1"), None)
+            .await
+            .unwrap()
+            .unwrap();
+        let second_id = connection
+            .store_receipt(receipt("2"), None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(connection.receipt_evicted(first_id).await);
+
+        // receipts evicted before a snapshot are remembered from it
+        connection.snapshot().await.unwrap();
+        drop(connection);
+        let connection = open();
+        assert!(connection.receipt_evicted(first_id).await);
+
+        // and receipts evicted after it are evicted again as the log is replayed
+        connection
+            .store_receipt(receipt("3"), None)
+            .await
+            .unwrap()
+            .unwrap();
+        drop(connection);
+        let connection = open();
+        assert!(connection.receipt_evicted(first_id).await);
+        assert!(connection.receipt_evicted(second_id).await);
+        assert_eq!(connection.receipt_count().await, 1);
+
+        std::fs::remove_dir_all(config.directory.unwrap()).unwrap();
+    }
+}
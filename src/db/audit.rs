@@ -0,0 +1,124 @@
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::{Connection, ReceiptRecord, WalEntry};
+use crate::{
+    audit::{AuditAction, AuditEntry},
+    ledger::{LedgerError, ReasonCode, ReceiptAdjustment, Transaction, TransactionKind},
+    review::ReceiptStatus,
+};
+
+/// Constructs the transaction crediting the receipt's adjustment at `index` to its owner, if it changes the receipt's
+/// points.
+pub(super) fn adjustment_credit(
+    owner_id: Uuid,
+    receipt_id: Uuid,
+    record: &ReceiptRecord,
+    index: usize,
+) -> Option<Transaction> {
+    let points = record.credited_points(index);
+    if points == 0 {
+        return None;
+    }
+    let reason = format!(
+        "receipt {receipt_id}: {}",
+        record.adjustments[index].reason.name()
+    );
+    Some(Transaction::new(
+        owner_id,
+        points,
+        TransactionKind::Adjust { reason },
+    ))
+}
+
+/// Implementation of manual points adjustments and the audit log.
+impl Connection {
+    /// Appends an entry to the audit log. The entry must already be logged, along with the write it audits.
+    pub(super) fn record_audit(&self, entry: AuditEntry) {
+        self.audit_log
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(entry);
+    }
+
+    /// Loads the audit log, oldest first.
+    pub async fn load_audit_log(&self) -> Vec<AuditEntry> {
+        self.audit_log
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Adjusts the points for a receipt by ID on behalf of the given actor, returning the adjustment. The change the
+    /// adjustment makes to the receipt's points is also credited to the receipt owner's ledger if the receipt is
+    /// approved. Returns None if there is no receipt for the ID, or if the receipt has been evicted.
+    pub async fn adjust_receipt(
+        &self,
+        receipt_id: Uuid,
+        points: i64,
+        reason: ReasonCode,
+        note: Option<String>,
+        actor: String,
+    ) -> Result<Option<ReceiptAdjustment>, LedgerError> {
+        if points == 0 {
+            return Err(LedgerError::ZeroPoints);
+        }
+        let now = OffsetDateTime::now_utc();
+        let adjustment = ReceiptAdjustment {
+            id: Uuid::new_v4(),
+            points,
+            reason,
+            note,
+            actor,
+            made_at: now,
+        };
+        let entry = AuditEntry::new(
+            adjustment.actor.clone(),
+            AuditAction::AdjustReceipt {
+                receipt_id,
+                adjustment_id: adjustment.id,
+                points,
+                reason,
+            },
+        );
+        let _write = self.begin_write();
+        {
+            let mut table = self.receipts.write(receipt_id);
+            let Some(record) = table.records.get_mut(&receipt_id) else {
+                return Ok(None);
+            };
+            if self.is_expired(record, now) {
+                return Ok(None);
+            }
+            let entries = vec![
+                WalEntry::AdjustReceipt {
+                    receipt_id,
+                    adjustment: adjustment.clone(),
+                },
+                WalEntry::RecordAudit(entry.clone()),
+            ];
+            let mut adjusted = record.clone();
+            adjusted.adjustments.push(adjustment.clone());
+            // receipts pending review have their adjustments credited when they're approved
+            let transaction = adjusted
+                .owner_id
+                .filter(|_| adjusted.status == ReceiptStatus::Approved)
+                .and_then(|owner_id| {
+                    let index = adjusted.adjustments.len() - 1;
+                    adjustment_credit(owner_id, receipt_id, &adjusted, index)
+                });
+            match transaction {
+                Some(transaction) => {
+                    self.update_ledger(transaction.user_id, entries, |ledger| {
+                        ledger.record(transaction);
+                        Ok::<_, LedgerError>(())
+                    })?;
+                }
+                None => self.log(&entries)?,
+            }
+            *record = adjusted;
+        }
+        self.record_audit(entry);
+        Ok(Some(adjustment))
+    }
+}
@@ -0,0 +1,217 @@
+use std::{collections::HashSet, io};
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::{Connection, WalEntry};
+use crate::{
+    audit::{AuditAction, AuditEntry},
+    ledger::{Ledger, LedgerError, Transaction, TransactionKind},
+    review::ReceiptStatus,
+};
+
+/// An earn transaction whose points differ from the points calculated for its receipt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EarnMismatch {
+    pub transaction_id: Uuid,
+    pub receipt_id: Uuid,
+    pub ledger_points: i64,
+    pub calculated_points: u64,
+}
+
+/// The result of checking that the ledger agrees with the stored receipts.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LedgerCheck {
+    /// The number of earn transactions whose points were recalculated.
+    pub earns_checked: usize,
+
+    /// The number of earn transactions that couldn't be checked, because their receipt was evicted or their
+    /// ruleset is no longer configured.
+    pub earns_unchecked: usize,
+
+    /// Earn transactions whose points differ from their receipt's recalculated points.
+    pub mismatches: Vec<EarnMismatch>,
+
+    /// Owned receipts with no earn transaction.
+    pub missing_earns: Vec<Uuid>,
+}
+
+impl LedgerCheck {
+    /// Determines whether the ledger agrees with the stored receipts.
+    pub fn is_consistent(&self) -> bool {
+        self.mismatches.is_empty() && self.missing_earns.is_empty()
+    }
+}
+
+/// Implementation of the points ledger.
+impl Connection {
+    /// Applies an update to a member's ledger, first expiring any points due to expire. The transactions recorded are
+    /// logged along with `entries`, the rest of the write, before the update is applied. If the update fails, only the
+    /// expirations are applied, and updates must not record transactions before failing. If logging fails, nothing
+    /// is applied. Callers must hold `begin_write`.
+    pub(super) fn update_ledger<R, E: From<io::Error>>(
+        &self,
+        user_id: Uuid,
+        mut entries: Vec<WalEntry>,
+        update: impl FnOnce(&mut Ledger) -> Result<R, E>,
+    ) -> Result<R, E> {
+        let mut table = self.ledgers.write(user_id);
+        let ledger = table.entry(user_id).or_default();
+        // the update is applied to a copy, which replaces the ledger once it's logged
+        let mut updated = ledger.clone();
+        let start = updated.transactions().len();
+        updated.expire(user_id, OffsetDateTime::now_utc());
+        let result = update(&mut updated);
+        if result.is_err() {
+            entries.clear();
+        }
+        entries.extend(
+            updated.transactions()[start..]
+                .iter()
+                .cloned()
+                .map(WalEntry::RecordTransaction),
+        );
+        if !entries.is_empty() {
+            self.log(&entries)?;
+            *ledger = updated;
+        }
+        result
+    }
+
+    /// Loads a member's ledger, after expiring any points due to expire. If the expirations can't be persisted, the
+    /// ledger is loaded as if they had been, and they're recorded by a later load.
+    pub async fn load_ledger(&self, user_id: Uuid) -> Ledger {
+        let mut loaded = Ledger::default();
+        let _write = self.begin_write();
+        // nothing is acknowledged by a load, so it succeeds even if the expirations aren't recorded
+        let _ = self.update_ledger(user_id, Vec::new(), |ledger| {
+            loaded = ledger.clone();
+            Ok::<_, io::Error>(())
+        });
+        loaded
+    }
+
+    /// Redeems points from a member's balance, returning the redemption transaction.
+    pub async fn redeem_points(
+        &self,
+        user_id: Uuid,
+        points: u64,
+        description: String,
+    ) -> Result<Transaction, LedgerError> {
+        let points = i64::try_from(points).unwrap_or(i64::MAX);
+        let _write = self.begin_write();
+        self.update_ledger(user_id, Vec::new(), |ledger| {
+            let balance = ledger.balance();
+            if points == 0 {
+                return Err(LedgerError::ZeroPoints);
+            }
+            if points > balance {
+                return Err(LedgerError::InsufficientBalance { balance });
+            }
+            let transaction =
+                Transaction::new(user_id, -points, TransactionKind::Redeem { description });
+            ledger.record(transaction.clone());
+            Ok(transaction)
+        })
+    }
+
+    /// Grants or revokes points from a member's balance on behalf of the given actor, returning the adjustment
+    /// transaction. Revoking points may leave the balance negative.
+    pub async fn adjust_points(
+        &self,
+        user_id: Uuid,
+        points: i64,
+        reason: String,
+        actor: String,
+    ) -> Result<Transaction, LedgerError> {
+        if points == 0 {
+            return Err(LedgerError::ZeroPoints);
+        }
+        let kind = TransactionKind::Adjust {
+            reason: reason.clone(),
+        };
+        let transaction = Transaction::new(user_id, points, kind);
+        let entry = AuditEntry::new(
+            actor,
+            AuditAction::AdjustBalance {
+                user_id,
+                transaction_id: transaction.id,
+                points,
+                reason,
+            },
+        );
+        let _write = self.begin_write();
+        self.update_ledger(
+            user_id,
+            vec![WalEntry::RecordAudit(entry.clone())],
+            |ledger| {
+                ledger.record(transaction.clone());
+                Ok::<_, LedgerError>(())
+            },
+        )?;
+        self.record_audit(entry);
+        Ok(transaction)
+    }
+
+    /// Checks that every earn transaction matches the points calculated for its receipt, and that every owned receipt
+    /// approved has an earn transaction.
+    pub async fn check_ledger(&self) -> LedgerCheck {
+        let mut earns = Vec::new();
+        for table in self.ledgers.read_each() {
+            for transaction in table.values().flat_map(Ledger::transactions) {
+                if let TransactionKind::Earn { receipt_id, .. } = transaction.kind {
+                    earns.push((transaction.id, receipt_id, transaction.points));
+                }
+            }
+        }
+
+        let mut check = LedgerCheck::default();
+        let earned: HashSet<_> = earns.iter().map(|&(_, receipt_id, _)| receipt_id).collect();
+        for (transaction_id, receipt_id, ledger_points) in earns {
+            let Some(record) = self.load_receipt(receipt_id).await else {
+                check.earns_unchecked += 1;
+                continue;
+            };
+            let Some(ruleset) = self.rulesets.get(record.award.ruleset_version) else {
+                check.earns_unchecked += 1;
+                continue;
+            };
+            // campaigns may have changed since, so the bonuses recorded on the receipt are trusted
+            let calculated_points = record
+                .award
+                .bonuses
+                .iter()
+                .fold(ruleset.calculate_points(&record), |points, bonus| {
+                    points.saturating_add(bonus.points)
+                });
+            check.earns_checked += 1;
+            if i64::try_from(calculated_points).ok() != Some(ledger_points) {
+                check.mismatches.push(EarnMismatch {
+                    transaction_id,
+                    receipt_id,
+                    ledger_points,
+                    calculated_points,
+                });
+            }
+        }
+
+        for table in self.receipts.read_each() {
+            check.missing_earns.extend(
+                table
+                    .records
+                    .iter()
+                    .filter(|(id, record)| {
+                        record.owner_id.is_some()
+                            && record.status == ReceiptStatus::Approved
+                            && !earned.contains(id)
+                    })
+                    .map(|(&id, _)| id),
+            );
+        }
+        check.missing_earns.sort();
+        check
+    }
+}
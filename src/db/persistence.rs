@@ -0,0 +1,318 @@
+//! Persistence for the in-memory database. The database is periodically written to a snapshot file, and every write
+//! between snapshots is appended to a write-ahead log. On startup, the snapshot is loaded and the log replayed on top
+//! of it, so writes survive a restart or crash.
+//!
+//! Both files are JSON. The snapshot is a single versioned document, and the log holds one entry per line.
+//!
+//! A lock file in the directory is held exclusively while the database is open, so the server and the import command
+//! can't both write to the same files.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, RwLock, RwLockReadGuard},
+};
+
+use fs4::{FileExt, TryLockError};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::ReceiptRecord;
+use crate::{
+    audit::AuditEntry,
+    campaigns::Campaign,
+    data::User,
+    ledger::{ReceiptAdjustment, Transaction},
+    review::Review,
+    webhooks::Subscription,
+};
+
+/// The version of the snapshot format written by this application.
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// The name of the snapshot file within the data directory.
+const SNAPSHOT_FILE: &str = "snapshot.json";
+
+/// The name of the write-ahead log within the data directory.
+const WAL_FILE: &str = "wal.ndjson";
+
+/// The name of the lock file within the data directory.
+const LOCK_FILE: &str = "lock";
+
+/// The contents of the database at a point in time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Snapshot {
+    /// The version of the snapshot format.
+    pub version: u32,
+
+    /// The receipts in the database, oldest first.
+    pub receipts: Vec<StoredReceipt>,
+
+    /// The members in the database.
+    pub users: Vec<StoredUser>,
+
+    /// The promotional campaigns in the database.
+    #[serde(default)]
+    pub campaigns: Vec<StoredCampaign>,
+
+    /// Every member's ledger transactions, oldest first.
+    #[serde(default)]
+    pub transactions: Vec<Transaction>,
+
+    /// The audit log, oldest first.
+    #[serde(default)]
+    pub audit_log: Vec<AuditEntry>,
+
+    /// The webhook subscriptions in the database.
+    #[serde(default)]
+    pub subscriptions: Vec<StoredSubscription>,
+    /// The IDs of the most recently evicted receipts, oldest first.
+    #[serde(default)]
+    pub evicted: Vec<Uuid>,
+}
+
+impl Snapshot {
+    /// Constructs a snapshot in the current format.
+    pub fn new(
+        receipts: Vec<StoredReceipt>,
+        users: Vec<StoredUser>,
+        campaigns: Vec<StoredCampaign>,
+        transactions: Vec<Transaction>,
+        audit_log: Vec<AuditEntry>,
+        subscriptions: Vec<StoredSubscription>,
+        evicted: Vec<Uuid>,
+    ) -> Self {
+        Self {
+            version: SNAPSHOT_VERSION,
+            receipts,
+            users,
+            campaigns,
+            transactions,
+            audit_log,
+            subscriptions,
+            evicted,
+        }
+    }
+}
+
+/// A receipt and its ID.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredReceipt {
+    pub id: Uuid,
+    pub record: ReceiptRecord,
+}
+
+/// A member and their ID.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredUser {
+    pub id: Uuid,
+    pub user: User,
+}
+
+/// A campaign and its ID.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredCampaign {
+    pub id: Uuid,
+    pub campaign: Campaign,
+}
+
+/// A webhook subscription and its ID.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredSubscription {
+    pub id: Uuid,
+    pub subscription: Subscription,
+}
+
+/// A write to the database, as recorded in the write-ahead log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "camelCase")]
+pub enum WalEntry {
+    StoreReceipt(Box<StoredReceipt>),
+    StoreUser(StoredUser),
+    /// Stores a new campaign or replaces an existing one.
+    StoreCampaign(StoredCampaign),
+    DeleteCampaign {
+        id: Uuid,
+    },
+    RecordTransaction(Transaction),
+    AdjustReceipt {
+        receipt_id: Uuid,
+        adjustment: ReceiptAdjustment,
+    },
+    RecordAudit(AuditEntry),
+    ReviewReceipt {
+        receipt_id: Uuid,
+        review: Review,
+    },
+    StoreSubscription(StoredSubscription),
+    DeleteSubscription {
+        id: Uuid,
+    },
+}
+
+/// The files the database is persisted to.
+#[derive(Debug)]
+pub struct Persistence {
+    /// The directory holding the snapshot and write-ahead log.
+    directory: PathBuf,
+
+    /// The write-ahead log, open for appending.
+    wal: Mutex<File>,
+
+    /// Held for reading by each write, from before it locks any table until it's applied, and for writing while a
+    /// snapshot is taken. Each write is then either wholly in a snapshot or wholly in the log written after it.
+    writes: RwLock<()>,
+
+    /// Whether to flush each write-ahead log entry to disk before acknowledging the write.
+    sync_writes: bool,
+
+    /// The lock file, locked exclusively until it's closed. The operating system closes it when the process exits,
+    /// even if it crashes, so a stale lock never keeps the directory from being opened.
+    _lock: File,
+}
+
+impl Persistence {
+    /// Opens the persisted database in the directory, creating the directory if needed. Returns the persistence
+    /// handle along with the latest snapshot, if any, and the log entries written since. Fails if the directory is
+    /// already open, in this process or another.
+    pub fn open(
+        directory: &Path,
+        sync_writes: bool,
+    ) -> io::Result<(Self, Option<Snapshot>, Vec<WalEntry>)> {
+        fs::create_dir_all(directory)?;
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(directory.join(LOCK_FILE))?;
+        // called through the trait, since `File` has an inherent method of the same name on newer toolchains
+        match FileExt::try_lock(&lock) {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    format!("the database in {} is already open", directory.display()),
+                ));
+            }
+            Err(TryLockError::Error(e)) => return Err(e),
+        }
+
+        let snapshot = match fs::read(directory.join(SNAPSHOT_FILE)) {
+            Ok(contents) => {
+                let snapshot: Snapshot =
+                    serde_json::from_slice(&contents).map_err(io::Error::other)?;
+                if snapshot.version != SNAPSHOT_VERSION {
+                    return Err(io::Error::other(format!(
+                        "unsupported snapshot version {}",
+                        snapshot.version
+                    )));
+                }
+                Some(snapshot)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+
+        let wal_path = directory.join(WAL_FILE);
+        let entries = match File::open(&wal_path) {
+            Ok(file) => read_entries(file)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let wal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&wal_path)?;
+
+        let persistence = Self {
+            directory: directory.to_owned(),
+            wal: Mutex::new(wal),
+            writes: RwLock::new(()),
+            sync_writes,
+            _lock: lock,
+        };
+        Ok((persistence, snapshot, entries))
+    }
+
+    /// Appends the entries making up a write to the write-ahead log. If the append fails, the log is truncated back
+    /// to where it was, so a failed write isn't replayed and doesn't leave a torn line before later entries.
+    pub fn append(&self, entries: &[WalEntry]) -> io::Result<()> {
+        let mut lines = Vec::new();
+        for entry in entries {
+            serde_json::to_writer(&mut lines, entry).map_err(io::Error::other)?;
+            lines.push(b'\n');
+        }
+        let mut wal = self.wal.lock().unwrap_or_else(|e| e.into_inner());
+        let len = wal.metadata()?.len();
+        let result = wal.write_all(&lines).and_then(|()| {
+            if self.sync_writes {
+                wal.sync_data()?;
+            }
+            Ok(())
+        });
+        if result.is_err() {
+            // if even this fails, replay reports the torn entry as corruption rather than silently skipping writes
+            let _ = wal.set_len(len);
+        }
+        result
+    }
+
+    /// Blocks snapshots until the returned guard is dropped. Writes must hold this before locking any table, so
+    /// snapshots never wait on a table held by a write waiting on the log.
+    pub fn begin_write(&self) -> RwLockReadGuard<'_, ()> {
+        self.writes.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Reopens the write-ahead log read-only, so every later append fails.
+    #[cfg(test)]
+    pub fn fail_appends(&self) -> io::Result<()> {
+        *self.wal.lock().unwrap_or_else(|e| e.into_inner()) =
+            File::open(self.directory.join(WAL_FILE))?;
+        Ok(())
+    }
+
+    /// Writes a snapshot, then clears the write-ahead log. `capture` is called to get the contents of the snapshot
+    /// while writes are blocked, so every write is either in the snapshot or in the log afterwards.
+    pub fn write_snapshot(&self, capture: impl FnOnce() -> Snapshot) -> io::Result<()> {
+        let _writes = self.writes.write().unwrap_or_else(|e| e.into_inner());
+        let wal = self.wal.lock().unwrap_or_else(|e| e.into_inner());
+        let snapshot = capture();
+
+        // write to a temporary file first so a crash mid-write doesn't corrupt the previous snapshot
+        let temp_path = self.directory.join(format!("{SNAPSHOT_FILE}.tmp"));
+        let mut temp = File::create(&temp_path)?;
+        serde_json::to_writer(&mut temp, &snapshot).map_err(io::Error::other)?;
+        temp.sync_all()?;
+        fs::rename(&temp_path, self.directory.join(SNAPSHOT_FILE))?;
+
+        wal.set_len(0)?;
+        wal.sync_all()
+    }
+}
+
+/// Reads every entry from a write-ahead log. A torn final line, left by a crash mid-append, is ignored.
+fn read_entries(file: File) -> io::Result<Vec<WalEntry>> {
+    let lines = BufReader::new(file)
+        .lines()
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut entries = Vec::with_capacity(lines.len());
+    for (i, line) in lines.iter().enumerate() {
+        match serde_json::from_str(line) {
+            Ok(entry) => entries.push(entry),
+            Err(_) if i + 1 == lines.len() => break,
+            Err(e) => {
+                return Err(io::Error::other(format!(
+                    "corrupt log entry {}: {e}",
+                    i + 1
+                )))
+            }
+        }
+    }
+    Ok(entries)
+}
@@ -0,0 +1,144 @@
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::{audit::adjustment_credit, Connection, ReceiptRecord, WalEntry};
+use crate::{
+    audit::{AuditAction, AuditEntry},
+    review::{ReceiptStatus, Review, ReviewError},
+    webhooks::EventType,
+};
+
+/// Implementation of the review queue.
+impl Connection {
+    /// Loads every receipt pending review, oldest first.
+    pub async fn load_pending_receipts(&self) -> Vec<(Uuid, ReceiptRecord)> {
+        let now = OffsetDateTime::now_utc();
+        let mut pending = Vec::new();
+        for table in self.receipts.read_each() {
+            pending.extend(
+                table
+                    .records
+                    .iter()
+                    .filter(|(_, record)| {
+                        record.status == ReceiptStatus::Pending && !self.is_expired(record, now)
+                    })
+                    .map(|(&id, record)| (id, record.clone())),
+            );
+        }
+        pending.sort_by_key(|(_, record)| record.stored_at);
+        pending
+    }
+
+    /// Loads the review status of a receipt by ID. Returns None if there is no receipt for the ID, or if the receipt
+    /// has been evicted.
+    pub async fn load_receipt_status(&self, id: Uuid) -> Option<ReceiptStatus> {
+        let table = self.receipts.read(id);
+        let record = table.records.get(&id)?;
+        (!self.is_expired(record, OffsetDateTime::now_utc())).then_some(record.status)
+    }
+
+    /// Approves a receipt pending review on behalf of the given reviewer, returning the updated receipt. Its points,
+    /// including any adjustments made while it was pending, are credited to its owner's ledger. Returns None if there is no
+    /// receipt for the ID, or if the receipt has been evicted.
+    pub async fn approve_receipt(
+        &self,
+        id: Uuid,
+        note: Option<String>,
+        reviewer: String,
+    ) -> Result<Option<ReceiptRecord>, ReviewError> {
+        self.review_receipt(id, ReceiptStatus::Approved, note, reviewer)
+    }
+
+    /// Rejects a receipt pending review on behalf of the given reviewer, returning the updated receipt. Returns None
+    /// if there is no receipt for the ID, or if the receipt has been evicted.
+    pub async fn reject_receipt(
+        &self,
+        id: Uuid,
+        note: Option<String>,
+        reviewer: String,
+    ) -> Result<Option<ReceiptRecord>, ReviewError> {
+        self.review_receipt(id, ReceiptStatus::Rejected, note, reviewer)
+    }
+
+    /// Moves a receipt pending review to the given status.
+    fn review_receipt(
+        &self,
+        id: Uuid,
+        status: ReceiptStatus,
+        note: Option<String>,
+        reviewer: String,
+    ) -> Result<Option<ReceiptRecord>, ReviewError> {
+        let now = OffsetDateTime::now_utc();
+        let review = Review {
+            status,
+            note,
+            reviewer,
+            reviewed_at: now,
+        };
+        let entry = AuditEntry::new(
+            review.reviewer.clone(),
+            AuditAction::ReviewReceipt {
+                receipt_id: id,
+                status,
+            },
+        );
+        let _write = self.begin_write();
+        let record = {
+            let mut table = self.receipts.write(id);
+            let Some(record) = table.records.get_mut(&id) else {
+                return Ok(None);
+            };
+            if self.is_expired(record, now) {
+                return Ok(None);
+            }
+            if record.status != ReceiptStatus::Pending {
+                return Err(ReviewError::NotPending {
+                    status: record.status,
+                });
+            }
+            let entries = vec![
+                WalEntry::ReviewReceipt {
+                    receipt_id: id,
+                    review: review.clone(),
+                },
+                WalEntry::RecordAudit(entry.clone()),
+            ];
+            match record
+                .owner_id
+                .filter(|_| status == ReceiptStatus::Approved)
+            {
+                Some(owner_id) => {
+                    let earn = self.earn(owner_id, id, record, now);
+                    self.update_ledger(owner_id, entries, |ledger| {
+                        ledger.record(earn);
+                        for index in 0..record.adjustments.len() {
+                            if let Some(transaction) =
+                                adjustment_credit(owner_id, id, record, index)
+                            {
+                                ledger.record(transaction);
+                            }
+                        }
+                        Ok::<_, ReviewError>(())
+                    })?;
+                }
+                None => self.log(&entries)?,
+            }
+            record.status = status;
+            record.review = Some(review.clone());
+            record.clone()
+        };
+        self.record_audit(entry);
+        match status {
+            ReceiptStatus::Approved => self.notify_awarded(id, &record),
+            _ => self.notify(
+                EventType::ReceiptRejected,
+                serde_json::json!({
+                    "receiptId": id,
+                    "ownerId": record.owner_id,
+                    "note": review.note,
+                }),
+            ),
+        }
+        Ok(Some(record))
+    }
+}
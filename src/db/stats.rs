@@ -0,0 +1,47 @@
+use time::OffsetDateTime;
+
+use super::Connection;
+use crate::{
+    review::ReceiptStatus,
+    stats::{DateRange, Sample},
+};
+
+/// Implementation of reporting statistics.
+impl Connection {
+    /// Loads a sample of every approved receipt purchased within the given range, to aggregate statistics over.
+    /// Receipts pending review or rejected are left out, since their points haven't been awarded. Receipts stored
+    /// before points were awarded on storage are awarded points for the sample, but not updated.
+    pub async fn load_stats_samples(&self, range: DateRange) -> Vec<Sample> {
+        let now = OffsetDateTime::now_utc();
+        let mut samples = Vec::new();
+        for table in self.receipts.read_each() {
+            samples.extend(
+                table
+                    .records
+                    .values()
+                    .filter(|record| {
+                        record.status == ReceiptStatus::Approved
+                            && range.contains(record.receipt.purchase_date)
+                            && !self.is_expired(record, now)
+                    })
+                    .map(|record| {
+                        let points = if record.award.is_awarded() {
+                            record.points()
+                        } else {
+                            let mut record = record.clone();
+                            record.award = self.award(&record);
+                            record.points()
+                        };
+                        Sample {
+                            retailer: record.retailer_name().to_owned(),
+                            purchase_date: record.receipt.purchase_date,
+                            purchase_time: record.receipt.purchase_time,
+                            total: record.receipt.total,
+                            points,
+                        }
+                    }),
+            );
+        }
+        samples
+    }
+}
@@ -0,0 +1,126 @@
+use std::{io, sync::Arc};
+
+use uuid::Uuid;
+
+use super::{Connection, ReceiptRecord, StoredSubscription, WalEntry};
+use crate::{
+    review::ReceiptStatus,
+    webhooks::{DeliveryAttempt, Event, EventType, Subscription},
+};
+
+/// Implementation of webhook subscriptions.
+impl Connection {
+    /// Stores a new webhook subscription in the database, returning its database ID. If the subscription cannot be
+    /// stored, returns None. Fails if the subscription can't be persisted.
+    pub async fn store_subscription(&self, subscription: Subscription) -> io::Result<Option<Uuid>> {
+        if !subscription.is_acceptable() {
+            return Ok(None);
+        }
+        let id = Uuid::new_v4();
+        let _write = self.begin_write();
+        let mut subscriptions = self
+            .subscriptions
+            .write()
+            .unwrap_or_else(|e| e.into_inner());
+        self.log(&[WalEntry::StoreSubscription(StoredSubscription {
+            id,
+            subscription: subscription.clone(),
+        })])?;
+        subscriptions.insert(id, subscription);
+        Ok(Some(id))
+    }
+
+    /// Deletes the webhook subscription with the given ID. Returns false if there is no subscription for the ID.
+    /// Fails if the deletion can't be persisted.
+    pub async fn delete_subscription(&self, id: Uuid) -> io::Result<bool> {
+        let _write = self.begin_write();
+        let mut subscriptions = self
+            .subscriptions
+            .write()
+            .unwrap_or_else(|e| e.into_inner());
+        if !subscriptions.contains_key(&id) {
+            return Ok(false);
+        }
+        self.log(&[WalEntry::DeleteSubscription { id }])?;
+        subscriptions.remove(&id);
+        Ok(true)
+    }
+
+    /// Loads every webhook subscription, ordered by ID.
+    pub async fn load_subscriptions(&self) -> Vec<(Uuid, Subscription)> {
+        let mut subscriptions: Vec<_> = self
+            .subscriptions
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|(&id, subscription)| (id, subscription.clone()))
+            .collect();
+        subscriptions.sort_by_key(|&(id, _)| id);
+        subscriptions
+    }
+
+    /// Loads the most recent attempts to deliver webhook notifications, oldest first.
+    pub async fn load_delivery_log(&self) -> Vec<DeliveryAttempt> {
+        self.deliveries.attempts()
+    }
+
+    /// Waits for the webhook notifications already dispatched to be delivered, and stops dispatching new ones.
+    /// Deliveries still in flight when the process exits are lost, so this is called before exiting. Blocks the calling
+    /// thread until the deliveries finish.
+    pub fn drain_webhooks(&self) {
+        self.dispatcher.drain();
+    }
+
+    /// Notifies subscribers that a receipt was stored, and if it was approved, that its points were awarded.
+    pub(super) fn notify_stored(&self, id: Uuid, record: &ReceiptRecord) {
+        self.notify(
+            EventType::ReceiptProcessed,
+            serde_json::json!({
+                "receiptId": id,
+                "retailer": record.retailer_name(),
+                "ownerId": record.owner_id,
+                "status": record.status,
+            }),
+        );
+        if record.status == ReceiptStatus::Approved {
+            self.notify_awarded(id, record);
+        }
+    }
+
+    /// Notifies subscribers that a receipt's points were awarded.
+    pub(super) fn notify_awarded(&self, id: Uuid, record: &ReceiptRecord) {
+        self.notify(
+            EventType::PointsAwarded,
+            serde_json::json!({
+                "receiptId": id,
+                "ownerId": record.owner_id,
+                "points": record.points(),
+            }),
+        );
+    }
+
+    /// Notifies every subscriber to the event's type of the event. Notifications are delivered in the background.
+    pub(super) fn notify(&self, event_type: EventType, data: serde_json::Value) {
+        let subscribers: Vec<_> = self
+            .subscriptions
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .filter(|(_, subscription)| subscription.events.contains(&event_type))
+            .map(|(&id, subscription)| (id, subscription.clone()))
+            .collect();
+        if subscribers.is_empty() {
+            return;
+        }
+        let event = Event::new(event_type, data);
+        for (id, subscription) in subscribers {
+            self.dispatcher.dispatch(
+                id,
+                subscription,
+                event.clone(),
+                Arc::clone(&self.webhook_config),
+                Arc::clone(&self.deliveries),
+            );
+        }
+    }
+}
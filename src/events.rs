@@ -0,0 +1,213 @@
+//! Contains the stream of events sent to live dashboards. An event is published whenever a new receipt is stored, and
+//! numbered in order. The most recent events are kept in a bounded buffer, so clients that reconnect can resume from
+//! the last event they saw.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// A new receipt, as sent to clients.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReceiptEvent {
+    pub receipt_id: Uuid,
+
+    /// The canonical name of the retailer the receipt is from, or the name on the receipt if the retailer isn't known.
+    pub retailer: String,
+
+    /// The points for the receipt. Receipts held for review are worth no points.
+    pub points: u64,
+}
+
+/// An event and its number. Events are numbered in the order they were published, starting from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NumberedEvent {
+    pub id: u64,
+    pub event: Arc<ReceiptEvent>,
+}
+
+impl NumberedEvent {
+    /// Formats the event as a server-sent event.
+    pub fn to_sse(&self) -> String {
+        let data = serde_json::to_string(&*self.event).expect("events should serialize");
+        format!("id: {}\nevent: receipt\ndata: {data}\n\n", self.id)
+    }
+}
+
+/// What a client receives on subscribing, before any new events.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Backlog {
+    /// The buffered events published after the client's last event, if any.
+    Missed(Vec<NumberedEvent>),
+
+    /// Some events published after the client's last event are no longer buffered, so the client should start over
+    /// from the most recent event, whose number is given.
+    Reset(u64),
+}
+
+impl Backlog {
+    /// Formats the backlog as server-sent events.
+    pub fn to_sse(&self) -> String {
+        match self {
+            Self::Missed(events) => events.iter().map(NumberedEvent::to_sse).collect(),
+            Self::Reset(last_event_id) => {
+                format!("id: {last_event_id}\nevent: reset\ndata: {{}}\n\n")
+            }
+        }
+    }
+}
+
+/// The events kept for clients to resume from.
+#[derive(Debug)]
+struct Buffer {
+    /// The number of the next event published.
+    next_id: u64,
+
+    /// The most recent events, oldest first.
+    events: VecDeque<NumberedEvent>,
+}
+
+/// Publishes events to clients, keeping the most recent in a bounded buffer.
+#[derive(Debug)]
+pub struct EventBuffer {
+    buffer: Mutex<Buffer>,
+
+    /// The number of events kept.
+    capacity: usize,
+
+    /// Sends events to connected clients.
+    sender: broadcast::Sender<NumberedEvent>,
+}
+
+impl Default for EventBuffer {
+    fn default() -> Self {
+        Self::new(1000)
+    }
+}
+
+impl EventBuffer {
+    /// Constructs an empty buffer keeping the given number of events.
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity.max(1));
+        Self {
+            buffer: Mutex::new(Buffer {
+                next_id: 1,
+                events: VecDeque::new(),
+            }),
+            capacity,
+            sender,
+        }
+    }
+
+    /// Publishes an event to connected clients, returning its number.
+    pub fn publish(&self, event: ReceiptEvent) -> u64 {
+        let mut buffer = self.buffer.lock().unwrap_or_else(|e| e.into_inner());
+        let event = NumberedEvent {
+            id: buffer.next_id,
+            event: Arc::new(event),
+        };
+        buffer.next_id += 1;
+        buffer.events.push_back(event.clone());
+        while buffer.events.len() > self.capacity {
+            buffer.events.pop_front();
+        }
+        // sending only fails if no clients are connected, which is fine
+        let _ = self.sender.send(event.clone());
+        event.id
+    }
+
+    /// Subscribes to events published from now on, returning the buffered events published after the given event, if
+    /// any. Together they're every event after it, without gaps. If some of the events after it are no longer buffered,
+    /// or it was never published, the client is told to reset instead.
+    pub fn subscribe(
+        &self,
+        last_event_id: Option<u64>,
+    ) -> (Backlog, broadcast::Receiver<NumberedEvent>) {
+        // subscribing under the lock means no event can be published between reading the buffer and subscribing
+        let buffer = self.buffer.lock().unwrap_or_else(|e| e.into_inner());
+        let latest = buffer.next_id - 1;
+        let oldest_buffered = buffer.next_id - buffer.events.len() as u64;
+        let backlog = match last_event_id {
+            Some(last_event_id)
+                if last_event_id > latest || last_event_id + 1 < oldest_buffered =>
+            {
+                Backlog::Reset(latest)
+            }
+            Some(last_event_id) => Backlog::Missed(
+                buffer
+                    .events
+                    .iter()
+                    .filter(|event| event.id > last_event_id)
+                    .cloned()
+                    .collect(),
+            ),
+            None => Backlog::Missed(Vec::new()),
+        };
+        (backlog, self.sender.subscribe())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(points: u64) -> ReceiptEvent {
+        ReceiptEvent {
+            receipt_id: Uuid::new_v4(),
+            retailer: "Target".to_owned(),
+            points,
+        }
+    }
+
+    #[test]
+    fn resume() {
+        let events = EventBuffer::new(2);
+        for points in 1..=3 {
+            assert_eq!(events.publish(event(points)), points);
+        }
+
+        let ids = |backlog: Backlog| match backlog {
+            Backlog::Missed(events) => events.iter().map(|event| event.id).collect::<Vec<_>>(),
+            Backlog::Reset(_) => panic!("expected the missed events"),
+        };
+        assert_eq!(ids(events.subscribe(Some(1)).0), vec![2, 3]);
+        assert_eq!(ids(events.subscribe(Some(2)).0), vec![3]);
+        assert_eq!(ids(events.subscribe(Some(3)).0), Vec::<u64>::new());
+
+        // clients that missed events no longer buffered, or resume from events never published, start over
+        assert_eq!(events.subscribe(Some(0)).0, Backlog::Reset(3));
+        assert_eq!(events.subscribe(Some(4)).0, Backlog::Reset(3));
+
+        // new clients only get new events
+        let (backlog, mut receiver) = events.subscribe(None);
+        assert_eq!(backlog, Backlog::Missed(Vec::new()));
+        events.publish(event(4));
+        assert_eq!(receiver.try_recv().unwrap().id, 4);
+    }
+
+    #[test]
+    fn format() {
+        let event = NumberedEvent {
+            id: 7,
+            event: Arc::new(ReceiptEvent {
+                receipt_id: Uuid::nil(),
+                retailer: "Target".to_owned(),
+                points: 31,
+            }),
+        };
+        assert_eq!(
+            event.to_sse(),
+            "id: 7\nevent: receipt\ndata: \
+             {\"receiptId\":\"00000000-0000-0000-0000-000000000000\",\"retailer\":\"Target\",\"points\":31}\n\n"
+        );
+        assert_eq!(
+            Backlog::Reset(7).to_sse(),
+            "id: 7\nevent: reset\ndata: {}\n\n"
+        );
+    }
+}
@@ -0,0 +1,294 @@
+//! Contains the export of stored receipts for reconciliation, as CSV or newline-delimited JSON. Exports have a row per
+//! receipt or a row per item, with each receipt's computed points, and format prices, dates and times the same way as
+//! the API. Receipts are encoded in chunks, so exports can be streamed without holding the whole store in memory.
+
+use serde::{Deserialize, Serialize};
+use time::{Date, Time};
+use uuid::Uuid;
+
+use crate::{
+    data::{serialization, Price},
+    db::ReceiptRecord,
+    review::ReceiptStatus,
+};
+
+/// The formats receipts can be exported in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Format {
+    /// Comma-separated values, with a header row.
+    Csv,
+    /// A JSON object per line.
+    Ndjson,
+}
+
+impl Format {
+    /// Gets the media type of exports in this format.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    /// Gets the file extension of exports in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Ndjson => "ndjson",
+        }
+    }
+}
+
+/// What each row of an export describes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Rows {
+    #[default]
+    Receipt,
+    /// An item, along with the receipt it's on.
+    Item,
+}
+
+/// A receipt, as exported.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReceiptRow<'a> {
+    receipt_id: Uuid,
+    retailer: &'a str,
+    #[serde(with = "serialization::date")]
+    purchase_date: Date,
+    #[serde(with = "serialization::time")]
+    purchase_time: Time,
+    total: Price,
+    item_count: usize,
+    status: ReceiptStatus,
+    points: u64,
+}
+
+/// An item on a receipt, as exported. The receipt's points are repeated on each of its items.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ItemRow<'a> {
+    receipt_id: Uuid,
+    retailer: &'a str,
+    #[serde(with = "serialization::date")]
+    purchase_date: Date,
+    #[serde(with = "serialization::time")]
+    purchase_time: Time,
+    total: Price,
+    store_id: Option<&'a str>,
+    register_number: Option<&'a str>,
+    transaction_number: Option<&'a str>,
+    status: ReceiptStatus,
+    points: u64,
+    short_description: &'a str,
+    price: Price,
+    quantity: Option<u32>,
+    unit_price: Option<Price>,
+    sku: Option<&'a str>,
+    upc: Option<&'a str>,
+    category: Option<&'a str>,
+}
+
+/// Encodes receipts into an export, a chunk at a time.
+#[derive(Debug, Clone)]
+pub struct Exporter {
+    format: Format,
+    rows: Rows,
+
+    /// Whether the CSV header row has been written, which happens with the first row.
+    wrote_header: bool,
+}
+
+impl Exporter {
+    /// Constructs an exporter for an export in the given format, with the given rows.
+    pub fn new(format: Format, rows: Rows) -> Self {
+        Self {
+            format,
+            rows,
+            wrote_header: false,
+        }
+    }
+
+    /// Encodes the next chunk of receipts, which must have been awarded points.
+    pub fn encode(&mut self, records: &[(Uuid, ReceiptRecord)]) -> Vec<u8> {
+        let mut chunk = Chunk::new(self.format, !self.wrote_header);
+        for (id, record) in records {
+            let points = record.points();
+            match self.rows {
+                Rows::Receipt => chunk.write(&ReceiptRow {
+                    receipt_id: *id,
+                    retailer: record.retailer_name(),
+                    purchase_date: record.receipt.purchase_date,
+                    purchase_time: record.receipt.purchase_time,
+                    total: record.receipt.total,
+                    item_count: record.receipt.items.len(),
+                    status: record.status,
+                    points,
+                }),
+                Rows::Item => {
+                    for item in &record.receipt.items {
+                        chunk.write(&ItemRow {
+                            receipt_id: *id,
+                            retailer: record.retailer_name(),
+                            purchase_date: record.receipt.purchase_date,
+                            purchase_time: record.receipt.purchase_time,
+                            total: record.receipt.total,
+                            store_id: record
+                                .receipt
+                                .store
+                                .as_ref()
+                                .and_then(|store| store.store_id.as_deref()),
+                            register_number: record.receipt.register_number.as_deref(),
+                            transaction_number: record.receipt.transaction_number.as_deref(),
+                            status: record.status,
+                            points,
+                            short_description: &item.short_description,
+                            price: item.price,
+                            quantity: item.quantity,
+                            unit_price: item.unit_price,
+                            sku: item.sku.as_deref(),
+                            upc: item.upc.as_deref(),
+                            category: item.category.as_deref(),
+                        })
+                    }
+                }
+            }
+        }
+        let (bytes, wrote_rows) = chunk.finish();
+        self.wrote_header |= wrote_rows;
+        bytes
+    }
+}
+
+/// A chunk of an export being written.
+enum Chunk {
+    Csv {
+        writer: Box<csv::Writer<Vec<u8>>>,
+        wrote_rows: bool,
+    },
+    Ndjson(Vec<u8>),
+}
+
+impl Chunk {
+    /// Starts a chunk in the given format. CSV chunks start with a header row if asked and if they have any rows.
+    fn new(format: Format, header: bool) -> Self {
+        match format {
+            Format::Csv => Self::Csv {
+                writer: Box::new(
+                    csv::WriterBuilder::new()
+                        .has_headers(header)
+                        .from_writer(Vec::new()),
+                ),
+                wrote_rows: false,
+            },
+            Format::Ndjson => Self::Ndjson(Vec::new()),
+        }
+    }
+
+    /// Writes a row.
+    fn write(&mut self, row: &impl Serialize) {
+        match self {
+            Self::Csv { writer, wrote_rows } => {
+                writer.serialize(row).expect("rows should serialize");
+                *wrote_rows = true;
+            }
+            Self::Ndjson(bytes) => {
+                serde_json::to_writer(&mut *bytes, row).expect("rows should serialize");
+                bytes.push(b'\n');
+            }
+        }
+    }
+
+    /// Finishes the chunk, returning its bytes and whether any rows were written.
+    fn finish(self) -> (Vec<u8>, bool) {
+        match self {
+            Self::Csv { writer, wrote_rows } => (
+                writer
+                    .into_inner()
+                    .expect("writing to memory should not fail"),
+                wrote_rows,
+            ),
+            Self::Ndjson(bytes) => {
+                let wrote_rows = !bytes.is_empty();
+                (bytes, wrote_rows)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::OffsetDateTime;
+
+    use super::*;
+
+    fn record() -> (Uuid, ReceiptRecord) {
+        let receipt = serde_json::from_value(serde_json::json!({
+            "retailer": "Target",
+            "purchaseDate": "2022-01-02",
+            "purchaseTime": "13:13",
+            "total": "2.50",
+            "items": [
+                { "shortDescription": "Pepsi - 12-oz", "price": "1.25" },
+                { "shortDescription": "Dasani", "price": "1.25", "quantity": 1, "upc": "049000042566" }
+            ]
+        }))
+        .unwrap();
+        (
+            Uuid::nil(),
+            ReceiptRecord {
+                receipt,
+                retailer_id: None,
+                retailer_name: None,
+                owner_id: None,
+                stored_at: OffsetDateTime::now_utc(),
+                award: Default::default(),
+                risk: Default::default(),
+                status: Default::default(),
+                review: None,
+                adjustments: Vec::new(),
+            },
+        )
+    }
+
+    #[test]
+    fn csv_chunks() {
+        let mut exporter = Exporter::new(Format::Csv, Rows::Receipt);
+        // the header is written with the first row, even if earlier chunks were empty
+        assert!(exporter.encode(&[]).is_empty());
+        assert_eq!(
+            String::from_utf8(exporter.encode(&[record()])).unwrap(),
+            "receiptId,retailer,purchaseDate,purchaseTime,total,itemCount,status,points\n\
+             00000000-0000-0000-0000-000000000000,Target,2022-01-02,13:13,2.50,2,approved,0\n"
+        );
+        assert_eq!(
+            String::from_utf8(exporter.encode(&[record()])).unwrap(),
+            "00000000-0000-0000-0000-000000000000,Target,2022-01-02,13:13,2.50,2,approved,0\n"
+        );
+    }
+
+    #[test]
+    fn item_rows() {
+        let mut exporter = Exporter::new(Format::Csv, Rows::Item);
+        let csv = String::from_utf8(exporter.encode(&[record()])).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with(",shortDescription,price,quantity,unitPrice,sku,upc,category"));
+        assert!(lines[1].ends_with(",Pepsi - 12-oz,1.25,,,,,"));
+        assert!(lines[2].ends_with(",Dasani,1.25,1,,,049000042566,"));
+
+        let mut exporter = Exporter::new(Format::Ndjson, Rows::Item);
+        let ndjson = String::from_utf8(exporter.encode(&[record()])).unwrap();
+        let rows: Vec<serde_json::Value> = ndjson
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1]["shortDescription"], "Dasani");
+        assert_eq!(rows[1]["price"], "1.25");
+        assert_eq!(rows[1]["purchaseTime"], "13:13");
+        assert_eq!(rows[1]["unitPrice"], serde_json::Value::Null);
+    }
+}
@@ -0,0 +1,165 @@
+//! Contains fraud scoring for submitted receipts. Each receipt is checked for patterns suggesting it was made up to
+//! game the points rules, such as a total that's a multiple of $0.25 alongside item descriptions whose lengths all
+//! earn points. Each pattern found adds its weight to the receipt's risk score, and receipts scoring at or above the
+//! configured threshold are held for review instead of being awarded points.
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::data::Receipt;
+
+/// A pattern suggesting a receipt may be fraudulent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RiskReason {
+    /// The total is a multiple of $0.25.
+    QuarterTotal,
+    /// Two or more items have the same description and price.
+    IdenticalItems,
+    /// The day of the purchase date is odd.
+    OddDay,
+    /// There are several items, and every item's trimmed description is a multiple of three characters long.
+    DescriptionLengths,
+}
+
+/// The weight each pattern adds to a receipt's risk score.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct RiskWeights {
+    pub quarter_total: u32,
+    pub identical_items: u32,
+    pub odd_day: u32,
+    pub description_lengths: u32,
+}
+
+impl Default for RiskWeights {
+    fn default() -> Self {
+        Self {
+            quarter_total: 20,
+            identical_items: 30,
+            odd_day: 10,
+            description_lengths: 40,
+        }
+    }
+}
+
+impl RiskWeights {
+    /// Gets the weight of the given pattern.
+    pub fn weight(&self, reason: RiskReason) -> u32 {
+        match reason {
+            RiskReason::QuarterTotal => self.quarter_total,
+            RiskReason::IdenticalItems => self.identical_items,
+            RiskReason::OddDay => self.odd_day,
+            RiskReason::DescriptionLengths => self.description_lengths,
+        }
+    }
+}
+
+/// The risk that a receipt is fraudulent.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RiskAssessment {
+    /// The total weight of the patterns found, from 0 to 100.
+    pub score: u32,
+
+    /// The patterns found.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reasons: Vec<RiskReason>,
+}
+
+/// Scores the risk that a receipt is fraudulent.
+pub fn assess(receipt: &Receipt, weights: &RiskWeights) -> RiskAssessment {
+    let mut reasons = Vec::new();
+
+    if receipt.total.cents % 25 == 0 {
+        reasons.push(RiskReason::QuarterTotal);
+    }
+
+    let mut seen = HashSet::new();
+    if !receipt.items.iter().all(|item| {
+        seen.insert((
+            item.short_description.trim(),
+            item.price.dollars,
+            item.price.cents,
+        ))
+    }) {
+        reasons.push(RiskReason::IdenticalItems);
+    }
+
+    if receipt.purchase_date.day() % 2 != 0 {
+        reasons.push(RiskReason::OddDay);
+    }
+
+    if receipt.items.len() > 1
+        && receipt
+            .items
+            .iter()
+            .all(|item| item.short_description.trim().len() % 3 == 0)
+    {
+        reasons.push(RiskReason::DescriptionLengths);
+    }
+
+    let score = reasons
+        .iter()
+        .fold(0u32, |score, &reason| {
+            score.saturating_add(weights.weight(reason))
+        })
+        .min(100);
+    RiskAssessment { score, reasons }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receipt(date: &str, total: &str, descriptions: &[&str]) -> Receipt {
+        let items: Vec<_> = descriptions
+            .iter()
+            .map(|description| serde_json::json!({ "shortDescription": description, "price": "1.00" }))
+            .collect();
+        serde_json::from_value(serde_json::json!({
+            "retailer": "Target",
+            "purchaseDate": date,
+            "purchaseTime": "13:13",
+            "total": total,
+            "items": items
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn clean_receipt() {
+        let receipt = receipt("2022-01-02", "2.01", &["Pepsi", "Gatorade"]);
+        assert_eq!(
+            assess(&receipt, &RiskWeights::default()),
+            RiskAssessment::default()
+        );
+    }
+
+    #[test]
+    fn suspicious_receipt() {
+        let receipt = receipt(
+            "2022-01-01",
+            "3.00",
+            &["Mountain Dew", "Mountain Dew  ", "Doritos Cool"],
+        );
+        let assessment = assess(&receipt, &RiskWeights::default());
+        assert_eq!(
+            assessment.reasons,
+            vec![
+                RiskReason::QuarterTotal,
+                RiskReason::IdenticalItems,
+                RiskReason::OddDay,
+                RiskReason::DescriptionLengths,
+            ]
+        );
+        assert_eq!(assessment.score, 100);
+
+        let weights = RiskWeights {
+            description_lengths: 0,
+            ..Default::default()
+        };
+        assert_eq!(assess(&receipt, &weights).score, 60);
+    }
+}
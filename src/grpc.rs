@@ -0,0 +1,740 @@
+//! Contains the gRPC interface, served alongside the JSON API on its own port. It shares the JSON API's database,
+//! points calculation and authentication: clients present an `x-api-key` or `authorization` metadata entry just as
+//! they would the HTTP headers. Messages are defined in `proto/receipts.proto`.
+
+use std::{io, net::SocketAddr, sync::Arc};
+
+use time::{Date, Month, Time};
+use tonic::{
+    metadata::{MetadataMap, MetadataValue},
+    service::{interceptor::InterceptedService, Interceptor},
+    transport::{server::TcpIncoming, Server},
+    Request, Response, Status,
+};
+use uuid::Uuid;
+
+use crate::{
+    auth::{AuthError, Authenticator, Principal, Scope},
+    config::ReceiptConfig,
+    data::{Item, PaymentMethod, Price, Receipt, StoreLocation, TaxLine},
+    db::{Connection, ReceiptRecord, Stored},
+    rate_limit::RateLimiter,
+    review::ReceiptStatus,
+    routes::{check_limits, CONFLICTING_TRANSACTION, UNAVAILABLE},
+};
+
+/// The messages and service generated from `proto/receipts.proto`.
+pub mod proto {
+    tonic::include_proto!("receipts.v1");
+}
+
+use proto::receipts_server::{Receipts, ReceiptsServer};
+
+/// The metadata key carrying an API key, matching the JSON API's header.
+const API_KEY_KEY: &str = "x-api-key";
+
+/// The metadata key carrying a bearer token, matching the JSON API's header.
+const AUTHORIZATION_KEY: &str = "authorization";
+
+/// The route every gRPC call is rate limited under, in the rate limit configuration. The calls share a bucket.
+pub const RATE_LIMIT_ROUTE: &str = "POST /receipts.v1.Receipts/{method}";
+
+/// Converts a failure to authenticate or authorize into the equivalent gRPC status.
+fn auth_status(error: AuthError) -> Status {
+    match error {
+        AuthError::MissingScope(_) | AuthError::Forbidden => {
+            Status::permission_denied(error.to_string())
+        }
+        _ => Status::unauthenticated(error.to_string()),
+    }
+}
+
+/// Unwraps a message field, which protobuf always makes optional.
+fn required<T>(field: Option<T>, name: &str) -> Result<T, Status> {
+    field.ok_or_else(|| Status::invalid_argument(format!("missing field `{name}`")))
+}
+
+/// Parses a receipt ID.
+fn parse_id(id: &str) -> Result<Uuid, Status> {
+    Uuid::parse_str(id)
+        .map_err(|_| Status::invalid_argument(format!("invalid receipt ID \"{id}\"")))
+}
+
+impl From<Price> for proto::Price {
+    fn from(price: Price) -> Self {
+        Self {
+            dollars: price.dollars,
+            cents: price.cents.into(),
+        }
+    }
+}
+
+impl TryFrom<proto::Price> for Price {
+    type Error = Status;
+
+    fn try_from(price: proto::Price) -> Result<Self, Status> {
+        match u8::try_from(price.cents) {
+            Ok(cents) if cents < 100 => Ok(Self {
+                dollars: price.dollars,
+                cents,
+            }),
+            _ => Err(Status::invalid_argument("cents must be less than 100")),
+        }
+    }
+}
+
+impl From<Date> for proto::Date {
+    fn from(date: Date) -> Self {
+        Self {
+            year: date.year(),
+            month: u8::from(date.month()).into(),
+            day: date.day().into(),
+        }
+    }
+}
+
+impl TryFrom<proto::Date> for Date {
+    type Error = Status;
+
+    fn try_from(date: proto::Date) -> Result<Self, Status> {
+        let month = u8::try_from(date.month)
+            .ok()
+            .and_then(|month| Month::try_from(month).ok());
+        let day = u8::try_from(date.day).ok();
+        month
+            .zip(day)
+            .and_then(|(month, day)| Date::from_calendar_date(date.year, month, day).ok())
+            .ok_or_else(|| Status::invalid_argument("invalid date"))
+    }
+}
+
+impl From<Time> for proto::TimeOfDay {
+    fn from(time: Time) -> Self {
+        Self {
+            hour: time.hour().into(),
+            minute: time.minute().into(),
+        }
+    }
+}
+
+impl TryFrom<proto::TimeOfDay> for Time {
+    type Error = Status;
+
+    fn try_from(time: proto::TimeOfDay) -> Result<Self, Status> {
+        u8::try_from(time.hour)
+            .ok()
+            .zip(u8::try_from(time.minute).ok())
+            .and_then(|(hour, minute)| Time::from_hms(hour, minute, 0).ok())
+            .ok_or_else(|| Status::invalid_argument("invalid time"))
+    }
+}
+
+impl From<Option<PaymentMethod>> for proto::PaymentMethod {
+    fn from(method: Option<PaymentMethod>) -> Self {
+        match method {
+            None => Self::Unspecified,
+            Some(PaymentMethod::Cash) => Self::Cash,
+            Some(PaymentMethod::Credit) => Self::Credit,
+            Some(PaymentMethod::Debit) => Self::Debit,
+            Some(PaymentMethod::GiftCard) => Self::GiftCard,
+            Some(PaymentMethod::Mobile) => Self::Mobile,
+            Some(PaymentMethod::Check) => Self::Check,
+            Some(PaymentMethod::Other) => Self::Other,
+        }
+    }
+}
+
+impl From<proto::PaymentMethod> for Option<PaymentMethod> {
+    fn from(method: proto::PaymentMethod) -> Self {
+        match method {
+            proto::PaymentMethod::Unspecified => None,
+            proto::PaymentMethod::Cash => Some(PaymentMethod::Cash),
+            proto::PaymentMethod::Credit => Some(PaymentMethod::Credit),
+            proto::PaymentMethod::Debit => Some(PaymentMethod::Debit),
+            proto::PaymentMethod::GiftCard => Some(PaymentMethod::GiftCard),
+            proto::PaymentMethod::Mobile => Some(PaymentMethod::Mobile),
+            proto::PaymentMethod::Check => Some(PaymentMethod::Check),
+            proto::PaymentMethod::Other => Some(PaymentMethod::Other),
+        }
+    }
+}
+
+impl From<ReceiptStatus> for proto::ReceiptStatus {
+    fn from(status: ReceiptStatus) -> Self {
+        match status {
+            ReceiptStatus::Pending => Self::Pending,
+            ReceiptStatus::Approved => Self::Approved,
+            ReceiptStatus::Rejected => Self::Rejected,
+        }
+    }
+}
+
+impl From<Item> for proto::Item {
+    fn from(item: Item) -> Self {
+        Self {
+            short_description: item.short_description,
+            price: Some(item.price.into()),
+            quantity: item.quantity,
+            unit_price: item.unit_price.map(Into::into),
+            sku: item.sku,
+            upc: item.upc,
+            category: item.category,
+        }
+    }
+}
+
+impl TryFrom<proto::Item> for Item {
+    type Error = Status;
+
+    fn try_from(item: proto::Item) -> Result<Self, Status> {
+        Ok(Self {
+            short_description: item.short_description,
+            price: required(item.price, "price")?.try_into()?,
+            quantity: item.quantity,
+            unit_price: item.unit_price.map(TryInto::try_into).transpose()?,
+            sku: item.sku,
+            upc: item.upc,
+            category: item.category,
+        })
+    }
+}
+
+impl From<Receipt> for proto::Receipt {
+    fn from(receipt: Receipt) -> Self {
+        Self {
+            retailer: receipt.retailer,
+            purchase_date: Some(receipt.purchase_date.into()),
+            purchase_time: Some(receipt.purchase_time.into()),
+            items: receipt.items.into_iter().map(Into::into).collect(),
+            total: Some(receipt.total.into()),
+            store: receipt.store.map(|store| proto::StoreLocation {
+                store_id: store.store_id,
+                address: store.address,
+            }),
+            register_number: receipt.register_number,
+            transaction_number: receipt.transaction_number,
+            payment_method: proto::PaymentMethod::from(receipt.payment_method).into(),
+            subtotal: receipt.subtotal.map(Into::into),
+            taxes: receipt
+                .taxes
+                .into_iter()
+                .map(|tax| proto::TaxLine {
+                    description: tax.description,
+                    amount: Some(tax.amount.into()),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::Receipt> for Receipt {
+    type Error = Status;
+
+    fn try_from(receipt: proto::Receipt) -> Result<Self, Status> {
+        let payment_method = receipt.payment_method().into();
+        Ok(Self {
+            retailer: receipt.retailer,
+            purchase_date: required(receipt.purchase_date, "purchase_date")?.try_into()?,
+            purchase_time: required(receipt.purchase_time, "purchase_time")?.try_into()?,
+            items: receipt
+                .items
+                .into_iter()
+                .map(TryInto::try_into)
+                .collect::<Result<_, _>>()?,
+            total: required(receipt.total, "total")?.try_into()?,
+            store: receipt.store.map(|store| StoreLocation {
+                store_id: store.store_id,
+                address: store.address,
+            }),
+            register_number: receipt.register_number,
+            transaction_number: receipt.transaction_number,
+            payment_method,
+            subtotal: receipt.subtotal.map(TryInto::try_into).transpose()?,
+            taxes: receipt
+                .taxes
+                .into_iter()
+                .map(|tax| {
+                    Ok(TaxLine {
+                        description: tax.description,
+                        amount: required(tax.amount, "amount")?.try_into()?,
+                    })
+                })
+                .collect::<Result<_, Status>>()?,
+        })
+    }
+}
+
+/// Identifies the client making a request from its metadata.
+fn authenticate_metadata(
+    authenticator: &Authenticator,
+    metadata: &MetadataMap,
+) -> Result<Principal, Status> {
+    authenticator
+        .authenticate_credentials(
+            metadata.get(API_KEY_KEY).map(|key| key.as_bytes()),
+            metadata
+                .get(AUTHORIZATION_KEY)
+                .map(|authorization| authorization.as_bytes()),
+        )
+        .map_err(auth_status)
+}
+
+/// Binds the listener the gRPC interface is served on, on the given port on localhost. Must be called from within a
+/// runtime.
+pub fn bind(port: u16) -> io::Result<TcpIncoming> {
+    TcpIncoming::bind(SocketAddr::from(([127, 0, 0, 1], port)))
+}
+
+/// Limits the rate of gRPC calls each client may make, sharing the JSON API's rate limiter. Clients are identified the
+/// same way as by the JSON API, so a client's calls are counted however it authenticates.
+#[derive(Clone)]
+pub struct RateLimitInterceptor {
+    authenticator: Arc<Authenticator>,
+    limiter: Arc<RateLimiter>,
+}
+
+impl Interceptor for RateLimitInterceptor {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let principal = authenticate_metadata(&self.authenticator, request.metadata())?;
+        let ip = request.remote_addr().map(|addr| addr.ip());
+        match self.limiter.acquire(RATE_LIMIT_ROUTE, &principal, ip) {
+            Ok(()) => Ok(request),
+            Err(retry_after) => {
+                let mut status = Status::resource_exhausted(format!(
+                    "rate limit exceeded, retry after {retry_after} seconds"
+                ));
+                status
+                    .metadata_mut()
+                    .insert("retry-after", MetadataValue::from(retry_after));
+                Err(status)
+            }
+        }
+    }
+}
+
+/// The gRPC service, sharing the JSON API's database.
+#[derive(Clone)]
+pub struct ReceiptService {
+    connection: Connection,
+    authenticator: Arc<Authenticator>,
+    receipts: Arc<ReceiptConfig>,
+}
+
+impl ReceiptService {
+    /// Constructs the service.
+    pub fn new(
+        connection: Connection,
+        authenticator: Authenticator,
+        receipts: ReceiptConfig,
+    ) -> Self {
+        Self {
+            connection,
+            authenticator: Arc::new(authenticator),
+            receipts: Arc::new(receipts),
+        }
+    }
+
+    /// Serves the service on the listener until the server fails. Calls are rate limited by the given limiter, and
+    /// messages may be no larger than receipt request bodies.
+    pub async fn serve(
+        self,
+        incoming: TcpIncoming,
+        limiter: Arc<RateLimiter>,
+    ) -> Result<(), tonic::transport::Error> {
+        let interceptor = RateLimitInterceptor {
+            authenticator: Arc::clone(&self.authenticator),
+            limiter,
+        };
+        let max_message_bytes = self.receipts.max_body_bytes;
+        Server::builder()
+            .add_service(InterceptedService::new(
+                ReceiptsServer::new(self).max_decoding_message_size(max_message_bytes),
+                interceptor,
+            ))
+            .serve_with_incoming(incoming)
+            .await
+    }
+
+    /// Identifies the client making the request, and checks it has the given scope.
+    fn authenticate<T>(&self, request: &Request<T>, scope: Scope) -> Result<Principal, Status> {
+        let principal = authenticate_metadata(&self.authenticator, request.metadata())?;
+        principal.require_scope(scope).map_err(auth_status)?;
+        Ok(principal)
+    }
+
+    /// Checks that the client may read the receipt: receipts with an owner may only be read by the owner, or clients that
+    /// may read every member.
+    fn authorize_reader(principal: &Principal, record: &ReceiptRecord) -> Result<(), Status> {
+        if record.owner_id.is_some() && principal.user_id != record.owner_id {
+            principal
+                .require_scope(Scope::UsersRead)
+                .map_err(|_| auth_status(AuthError::Forbidden))?;
+        }
+        Ok(())
+    }
+
+    /// Builds the status for a receipt that could not be loaded. There's no gRPC equivalent of 410 Gone, so evicted
+    /// receipts are not found either, with a message saying why.
+    async fn missing_receipt(&self, id: Uuid) -> Status {
+        if self.connection.receipt_evicted(id).await {
+            Status::not_found("receipt has expired")
+        } else {
+            Status::not_found("receipt not found")
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Receipts for ReceiptService {
+    async fn process_receipt(
+        &self,
+        request: Request<proto::ProcessReceiptRequest>,
+    ) -> Result<Response<proto::ProcessReceiptResponse>, Status> {
+        let principal = self.authenticate(&request, Scope::ReceiptsWrite)?;
+        let owner_id = principal.user_id;
+        if let Some(owner_id) = owner_id {
+            if self.connection.load_user(owner_id).await.is_none() {
+                return Err(Status::unauthenticated("unknown user"));
+            }
+        }
+        let receipt: Receipt = required(request.into_inner().receipt, "receipt")?.try_into()?;
+        let value = serde_json::to_value(&receipt).expect("receipts should serialize");
+        check_limits(&value, &self.receipts)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+        let stored = self
+            .connection
+            .store_new_receipt(receipt, owner_id)
+            .await
+            .map_err(|_| Status::unavailable(UNAVAILABLE))?
+            .ok_or_else(|| Status::invalid_argument("receipt is not acceptable"))?;
+        if let Stored::Conflict(_) = stored {
+            return Err(Status::already_exists(CONFLICTING_TRANSACTION));
+        }
+        Ok(Response::new(proto::ProcessReceiptResponse {
+            id: stored.id().to_string(),
+            duplicate: matches!(stored, Stored::Duplicate(_)),
+        }))
+    }
+
+    async fn get_points(
+        &self,
+        request: Request<proto::GetPointsRequest>,
+    ) -> Result<Response<proto::GetPointsResponse>, Status> {
+        let principal = self.authenticate(&request, Scope::ReceiptsRead)?;
+        let id = parse_id(&request.get_ref().id)?;
+        let Some(record) = self.connection.load_receipt(id).await else {
+            return Err(self.missing_receipt(id).await);
+        };
+        Self::authorize_reader(&principal, &record)?;
+        let Some(points) = self.connection.load_points(id).await else {
+            return Err(self.missing_receipt(id).await);
+        };
+        Ok(Response::new(proto::GetPointsResponse {
+            points,
+            status: proto::ReceiptStatus::from(record.status).into(),
+        }))
+    }
+
+    async fn get_receipt(
+        &self,
+        request: Request<proto::GetReceiptRequest>,
+    ) -> Result<Response<proto::GetReceiptResponse>, Status> {
+        let principal = self.authenticate(&request, Scope::ReceiptsRead)?;
+        let id = parse_id(&request.get_ref().id)?;
+        let Some(record) = self.connection.load_receipt(id).await else {
+            return Err(self.missing_receipt(id).await);
+        };
+        Self::authorize_reader(&principal, &record)?;
+        let Some(points) = self.connection.load_points(id).await else {
+            return Err(self.missing_receipt(id).await);
+        };
+        Ok(Response::new(proto::GetReceiptResponse {
+            receipt: Some(record.receipt.into()),
+            retailer_id: record.retailer_id,
+            retailer_name: record.retailer_name,
+            owner_id: record.owner_id.map(|id| id.to_string()),
+            status: proto::ReceiptStatus::from(record.status).into(),
+            points,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::{Digest, Sha256};
+    use tonic::Code;
+
+    use super::*;
+    use crate::{
+        config::{ApiKeyConfig, AuthConfig, LimitConfig, RateLimitConfig},
+        data::User,
+    };
+
+    fn receipt() -> Receipt {
+        serde_json::from_value(serde_json::json!({
+            "retailer": "M&M Corner Market",
+            "purchaseDate": "2022-03-20",
+            "purchaseTime": "14:33",
+            "total": "9.00",
+            "items": [
+                { "shortDescription": "Gatorade", "price": "9.00", "quantity": 4, "unitPrice": "2.25" }
+            ],
+            "store": { "storeId": "1234" },
+            "paymentMethod": "giftCard",
+            "subtotal": "8.50",
+            "taxes": [{ "description": "Sales tax", "amount": "0.50" }]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let message = proto::Receipt::from(receipt());
+        assert_eq!(
+            message.total,
+            Some(proto::Price {
+                dollars: 9,
+                cents: 0
+            })
+        );
+        assert_eq!(
+            message.purchase_date,
+            Some(proto::Date {
+                year: 2022,
+                month: 3,
+                day: 20
+            })
+        );
+        assert_eq!(message.payment_method(), proto::PaymentMethod::GiftCard);
+        assert_eq!(Receipt::try_from(message).unwrap(), receipt());
+    }
+
+    #[test]
+    fn invalid_messages() {
+        let mut message = proto::Receipt::from(receipt());
+        message.total = Some(proto::Price {
+            dollars: 1,
+            cents: 100,
+        });
+        assert_eq!(
+            Receipt::try_from(message).unwrap_err().code(),
+            Code::InvalidArgument
+        );
+
+        let mut message = proto::Receipt::from(receipt());
+        message.purchase_date = Some(proto::Date {
+            year: 2022,
+            month: 2,
+            day: 30,
+        });
+        assert_eq!(
+            Receipt::try_from(message).unwrap_err().code(),
+            Code::InvalidArgument
+        );
+
+        let mut message = proto::Receipt::from(receipt());
+        message.purchase_time = None;
+        assert_eq!(
+            Receipt::try_from(message).unwrap_err().message(),
+            "missing field `purchase_time`"
+        );
+    }
+
+    #[actix_web::test]
+    async fn service() {
+        let connection = Connection::new();
+        let service = ReceiptService::new(
+            connection.clone(),
+            Authenticator::from_config(&AuthConfig {
+                anonymous_scopes: vec![Scope::ReceiptsRead, Scope::ReceiptsWrite],
+                ..Default::default()
+            })
+            .unwrap(),
+            ReceiptConfig::default(),
+        );
+
+        let response = service
+            .process_receipt(Request::new(proto::ProcessReceiptRequest {
+                receipt: Some(receipt().into()),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let id = response.id;
+
+        // points are calculated the same way as for the JSON API
+        let points = service
+            .get_points(Request::new(proto::GetPointsRequest { id: id.clone() }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(points.points, 109);
+        assert_eq!(points.status(), proto::ReceiptStatus::Approved);
+        assert_eq!(
+            connection.load_points(parse_id(&id).unwrap()).await,
+            Some(109)
+        );
+
+        let stored = service
+            .get_receipt(Request::new(proto::GetReceiptRequest { id }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            Receipt::try_from(stored.receipt.unwrap()).unwrap(),
+            receipt()
+        );
+
+        let missing = service
+            .get_points(Request::new(proto::GetPointsRequest {
+                id: Uuid::new_v4().to_string(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), Code::NotFound);
+
+        let mut unacceptable = receipt();
+        unacceptable.retailer = "M&M Corner Market!".to_owned();
+        let status = service
+            .process_receipt(Request::new(proto::ProcessReceiptRequest {
+                receipt: Some(unacceptable.into()),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        // credentials are checked as they are for the JSON API
+        let mut request = Request::new(proto::GetPointsRequest {
+            id: Uuid::new_v4().to_string(),
+        });
+        request
+            .metadata_mut()
+            .insert(API_KEY_KEY, "unknown".parse().unwrap());
+        assert_eq!(
+            service.get_points(request).await.unwrap_err().code(),
+            Code::Unauthenticated
+        );
+    }
+
+    /// Builds a request for the message, authenticated with the API key if given.
+    fn request_with_key<T>(message: T, key: Option<&str>) -> Request<T> {
+        let mut request = Request::new(message);
+        if let Some(key) = key {
+            request
+                .metadata_mut()
+                .insert(API_KEY_KEY, key.parse().unwrap());
+        }
+        request
+    }
+
+    #[actix_web::test]
+    async fn receipt_owners() {
+        let connection = Connection::new();
+        let mut api_keys = Vec::new();
+        for name in ["pat", "sam"] {
+            let user_id = connection
+                .store_user(User {
+                    name: name.to_owned(),
+                    email: None,
+                })
+                .await
+                .unwrap()
+                .unwrap();
+            api_keys.push(ApiKeyConfig {
+                name: name.to_owned(),
+                key_hash: hex::encode(Sha256::digest(name)),
+                scopes: vec![Scope::ReceiptsRead, Scope::ReceiptsWrite],
+                user_id: Some(user_id),
+            });
+        }
+        let service = ReceiptService::new(
+            connection,
+            Authenticator::from_config(&AuthConfig {
+                anonymous_scopes: vec![Scope::ReceiptsRead, Scope::UsersRead],
+                api_keys,
+                ..Default::default()
+            })
+            .unwrap(),
+            ReceiptConfig::default(),
+        );
+
+        // a member submits a receipt
+        let id = service
+            .process_receipt(request_with_key(
+                proto::ProcessReceiptRequest {
+                    receipt: Some(receipt().into()),
+                },
+                Some("pat"),
+            ))
+            .await
+            .unwrap()
+            .into_inner()
+            .id;
+
+        // the member and clients that may read every member can read the receipt and its points, but other members
+        // can't
+        for (key, code) in [
+            (Some("pat"), Code::Ok),
+            (None, Code::Ok),
+            (Some("sam"), Code::PermissionDenied),
+        ] {
+            let points = service
+                .get_points(request_with_key(
+                    proto::GetPointsRequest { id: id.clone() },
+                    key,
+                ))
+                .await;
+            assert_eq!(points.err().map_or(Code::Ok, |e| e.code()), code, "{key:?}");
+            let receipt = service
+                .get_receipt(request_with_key(
+                    proto::GetReceiptRequest { id: id.clone() },
+                    key,
+                ))
+                .await;
+            assert_eq!(
+                receipt.err().map_or(Code::Ok, |e| e.code()),
+                code,
+                "{key:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rate_limited() {
+        let mut interceptor = RateLimitInterceptor {
+            authenticator: Arc::new(
+                Authenticator::from_config(&AuthConfig {
+                    anonymous_scopes: vec![Scope::ReceiptsRead],
+                    ..Default::default()
+                })
+                .unwrap(),
+            ),
+            limiter: Arc::new(RateLimiter::from_config(&RateLimitConfig {
+                routes: [(
+                    RATE_LIMIT_ROUTE.to_owned(),
+                    LimitConfig {
+                        capacity: 1,
+                        refill_per_second: 0.5,
+                    },
+                )]
+                .into(),
+            })),
+        };
+
+        assert!(interceptor.call(Request::new(())).is_ok());
+        let status = interceptor.call(Request::new(())).unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert_eq!(status.metadata().get("retry-after").unwrap(), "2");
+
+        // clients presenting unknown credentials are turned away before they're counted
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert(API_KEY_KEY, "unknown".parse().unwrap());
+        assert_eq!(
+            interceptor.call(request).unwrap_err().code(),
+            Code::Unauthenticated
+        );
+    }
+}
@@ -0,0 +1,482 @@
+//! Contains the bulk import of receipts from CSV files. Each row holds an item along with the fields of the receipt
+//! it's on, read from columns named by a configurable mapping. Rows are grouped into receipts, parsed the same way as
+//! submitted receipts, and stored if acceptable. The import's report lists the receipts stored, those already stored,
+//! and the rows rejected, with the reason for each.
+
+use std::{collections::HashMap, fmt, io};
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use csv::StringRecord;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+use crate::{
+    config::{ColumnMapping, ReceiptConfig},
+    data::Item,
+    db::{Connection, Stored},
+    routes::{parse_receipt_value, ErrorResponse, UNAVAILABLE},
+};
+
+/// The reason given for rows rejected because another row of their receipt was.
+const SIBLING_REJECTED: &str = "another row of the receipt was rejected";
+
+/// The reason given for rows whose receipt fields differ from those on the first row of their receipt.
+const CONFLICTING_RECEIPT: &str = "receipt fields differ from the receipt's first row";
+
+/// A receipt stored by an import, or already stored before it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportedReceipt {
+    pub receipt_id: Uuid,
+
+    /// The line numbers of the rows holding the receipt's items.
+    pub rows: Vec<u64>,
+}
+
+/// A row rejected by an import.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RejectedRow {
+    /// The line number of the row. The header is line 1.
+    pub row: u64,
+
+    pub reason: String,
+}
+
+/// The outcome of an import.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    /// The receipts stored, in the order they first appear in the file.
+    pub accepted: Vec<ImportedReceipt>,
+
+    /// The receipts that were already stored, by an earlier import or otherwise, in the order they first appear in the
+    /// file.
+    pub duplicates: Vec<ImportedReceipt>,
+
+    /// The rows rejected, in the order they appear in the file.
+    pub rejected: Vec<RejectedRow>,
+}
+
+/// Reasons a file can't be imported at all.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportError {
+    /// The header row could not be read.
+    Unreadable(String),
+    /// The file has no column for a required field.
+    MissingColumn(String),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unreadable(msg) => write!(f, "CSV header could not be read: {msg}"),
+            Self::MissingColumn(column) => write!(f, "CSV is missing the \"{column}\" column"),
+        }
+    }
+}
+
+impl ResponseError for ImportError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ErrorResponse {
+            error: self.to_string(),
+        })
+    }
+}
+
+/// The indexes of the mapped columns in a file.
+#[derive(Debug)]
+struct Columns {
+    receipt_id: Option<usize>,
+
+    /// The receipt fields, by JSON field name.
+    receipt: Vec<(&'static str, usize)>,
+
+    /// The optional receipt fields identifying the transaction, by JSON field name.
+    transaction: Vec<(&'static str, usize)>,
+
+    /// The item fields other than quantity, by JSON field name.
+    item: Vec<(&'static str, usize)>,
+
+    quantity: Option<usize>,
+}
+
+impl Columns {
+    /// Finds the mapped columns in the header row.
+    fn resolve(headers: &StringRecord, mapping: &ColumnMapping) -> Result<Self, ImportError> {
+        let find = |column: &str| headers.iter().position(|header| header == column);
+        let required = |column: &str| {
+            find(column).ok_or_else(|| ImportError::MissingColumn(column.to_owned()))
+        };
+        let receipt = vec![
+            ("retailer", required(&mapping.retailer)?),
+            ("purchaseDate", required(&mapping.purchase_date)?),
+            ("purchaseTime", required(&mapping.purchase_time)?),
+            ("total", required(&mapping.total)?),
+        ];
+        let transaction = [
+            ("storeId", &mapping.store_id),
+            ("registerNumber", &mapping.register_number),
+            ("transactionNumber", &mapping.transaction_number),
+        ]
+        .into_iter()
+        .filter_map(|(field, column)| find(column).map(|index| (field, index)))
+        .collect();
+        let mut item = vec![
+            ("shortDescription", required(&mapping.short_description)?),
+            ("price", required(&mapping.price)?),
+        ];
+        for (field, column) in [
+            ("unitPrice", &mapping.unit_price),
+            ("sku", &mapping.sku),
+            ("upc", &mapping.upc),
+            ("category", &mapping.category),
+        ] {
+            item.extend(find(column).map(|index| (field, index)));
+        }
+        Ok(Self {
+            receipt_id: find(&mapping.receipt_id),
+            receipt,
+            transaction,
+            item,
+            quantity: find(&mapping.quantity),
+        })
+    }
+
+    /// Gets the key of the receipt a row's item is on: its receipt ID if the file has them, otherwise its receipt
+    /// fields.
+    fn key(&self, record: &StringRecord) -> Vec<String> {
+        let cell = |index| record.get(index).unwrap_or_default().to_owned();
+        match self.receipt_id {
+            Some(index) => vec![cell(index)],
+            None => self.receipt.iter().map(|&(_, index)| cell(index)).collect(),
+        }
+    }
+
+    /// Reads the receipt fields from a row, as JSON, leaving out empty optional fields.
+    fn receipt(&self, record: &StringRecord) -> Map<String, Value> {
+        let mut receipt: Map<String, Value> = self
+            .receipt
+            .iter()
+            .map(|&(field, index)| {
+                let cell = record.get(index).unwrap_or_default();
+                (field.to_owned(), Value::from(cell))
+            })
+            .collect();
+        for &(field, index) in &self.transaction {
+            let cell = record.get(index).unwrap_or_default();
+            if cell.is_empty() {
+                continue;
+            }
+            match field {
+                "storeId" => {
+                    receipt.insert("store".to_owned(), serde_json::json!({ "storeId": cell }))
+                }
+                _ => receipt.insert(field.to_owned(), Value::from(cell)),
+            };
+        }
+        receipt
+    }
+
+    /// Reads the item from a row, leaving out empty optional fields.
+    fn item(&self, record: &StringRecord) -> Result<Value, String> {
+        let mut item: Map<String, Value> = self
+            .item
+            .iter()
+            .filter_map(|&(field, index)| {
+                let cell = record.get(index).unwrap_or_default();
+                let optional = !matches!(field, "shortDescription" | "price");
+                (!optional || !cell.is_empty()).then(|| (field.to_owned(), Value::from(cell)))
+            })
+            .collect();
+        if let Some(cell) = self.quantity.and_then(|index| record.get(index)) {
+            if !cell.is_empty() {
+                let quantity: u32 = cell
+                    .parse()
+                    .map_err(|_| format!("invalid quantity \"{cell}\""))?;
+                item.insert("quantity".to_owned(), quantity.into());
+            }
+        }
+        let item = Value::Object(item);
+        serde_json::from_value::<Item>(item.clone()).map_err(|e| format!("malformed item: {e}"))?;
+        Ok(item)
+    }
+}
+
+/// The rows of a receipt being read.
+#[derive(Debug)]
+struct Group {
+    rows: Vec<u64>,
+
+    /// The receipt fields, from the receipt's first row.
+    receipt: Map<String, Value>,
+
+    items: Vec<Value>,
+
+    /// The rows whose items were rejected. If there are any, the whole receipt is.
+    rejected_rows: Vec<u64>,
+}
+
+/// Imports receipts from a CSV file into the database, returning a report of the receipts stored, the receipts already
+/// stored and the rows rejected. Receipts are held to the same limits as submitted receipts. Imported receipts aren't
+/// owned by any member.
+pub async fn import(
+    connection: &Connection,
+    file: impl io::Read,
+    mapping: &ColumnMapping,
+    receipts: &ReceiptConfig,
+) -> Result<ImportReport, ImportError> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(file);
+    let headers = reader
+        .headers()
+        .map_err(|e| ImportError::Unreadable(e.to_string()))?
+        .clone();
+    let columns = Columns::resolve(&headers, mapping)?;
+
+    // group the rows into receipts, in the order they first appear
+    let mut report = ImportReport::default();
+    let mut groups: Vec<Group> = Vec::new();
+    let mut group_indexes: HashMap<Vec<String>, usize> = HashMap::new();
+    for (index, result) in reader.records().enumerate() {
+        let record = match result {
+            Ok(record) => record,
+            Err(e) => {
+                let row = e
+                    .position()
+                    .map_or(index as u64 + 2, |position| position.line());
+                report.rejected.push(RejectedRow {
+                    row,
+                    reason: e.to_string(),
+                });
+                continue;
+            }
+        };
+        let row = record
+            .position()
+            .map_or(index as u64 + 2, |position| position.line());
+        let group_index = *group_indexes
+            .entry(columns.key(&record))
+            .or_insert_with(|| {
+                groups.push(Group {
+                    rows: Vec::new(),
+                    receipt: columns.receipt(&record),
+                    items: Vec::new(),
+                    rejected_rows: Vec::new(),
+                });
+                groups.len() - 1
+            });
+        let group = &mut groups[group_index];
+        group.rows.push(row);
+        if columns.receipt(&record) != group.receipt {
+            group.rejected_rows.push(row);
+            report.rejected.push(RejectedRow {
+                row,
+                reason: CONFLICTING_RECEIPT.to_owned(),
+            });
+            continue;
+        }
+        match columns.item(&record) {
+            Ok(item) => group.items.push(item),
+            Err(reason) => {
+                group.rejected_rows.push(row);
+                report.rejected.push(RejectedRow { row, reason });
+            }
+        }
+    }
+
+    for mut group in groups {
+        let reason = if !group.rejected_rows.is_empty() {
+            SIBLING_REJECTED.to_owned()
+        } else {
+            group
+                .receipt
+                .insert("items".to_owned(), Value::Array(group.items));
+            match parse_receipt_value(Value::Object(group.receipt), receipts) {
+                Ok(receipt) => match connection.store_new_receipt(receipt, None).await {
+                    Ok(Some(stored)) => {
+                        let imported = ImportedReceipt {
+                            receipt_id: stored.id(),
+                            rows: group.rows,
+                        };
+                        match stored {
+                            Stored::New(_) => report.accepted.push(imported),
+                            Stored::Duplicate(_) | Stored::Conflict(_) => {
+                                report.duplicates.push(imported)
+                            }
+                        }
+                        continue;
+                    }
+                    Ok(None) => "receipt is not acceptable".to_owned(),
+                    Err(_) => UNAVAILABLE.to_owned(),
+                },
+                Err(e) => e.to_string(),
+            }
+        };
+        report.rejected.extend(
+            group
+                .rows
+                .into_iter()
+                .filter(|row| !group.rejected_rows.contains(row))
+                .map(|row| RejectedRow {
+                    row,
+                    reason: reason.clone(),
+                }),
+        );
+    }
+    report.rejected.sort_by_key(|row| row.row);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn import_rows() {
+        let connection = Connection::new();
+        let csv = "\
+receipt,retailer,purchaseDate,purchaseTime,total,transactionNumber,shortDescription,price,quantity
+1,Target,2022-01-01,13:01,6.49,1001,Mountain Dew 12PK,6.49,
+2,M&M Corner Market,2022-03-20,14:33,9.00,2001,Gatorade,4.50,2
+2,M&M Corner Market,2022-03-20,14:33,9.00,2001,Gatorade,4.50,2
+3,Target,2022-01-02,13:13,2.50,1002,Pepsi - 12-oz,1.25,
+3,Target,2022-01-02,13:13,2.50,1002,Dasani,1.25,many
+4,Walgreens,2022-13-01,08:13,2.65,,Pepsi - 12-oz,2.65,
+5,Target!,2022-01-02,13:13,1.25,,Pepsi - 12-oz,1.25,
+6,Walgreens,2022-01-02,08:13,1.25,3001,Pepsi - 12-oz,1.25,
+6,Walgreens,2022-01-02,08:13,1.25,3002,Dasani,1.25,
+";
+        let mapping = ColumnMapping {
+            receipt_id: "receipt".to_owned(),
+            ..Default::default()
+        };
+        let report = import(
+            &connection,
+            csv.as_bytes(),
+            &mapping,
+            &ReceiptConfig::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            report
+                .accepted
+                .iter()
+                .map(|receipt| receipt.rows.clone())
+                .collect::<Vec<_>>(),
+            vec![vec![2], vec![3, 4]]
+        );
+        let points = connection
+            .load_points(report.accepted[1].receipt_id)
+            .await
+            .unwrap();
+        assert_eq!(points, 109);
+
+        let rows: Vec<_> = report.rejected.iter().map(|row| row.row).collect();
+        assert_eq!(rows, vec![5, 6, 7, 8, 9, 10]);
+        assert_eq!(report.rejected[0].reason, SIBLING_REJECTED);
+        assert_eq!(report.rejected[1].reason, "invalid quantity \"many\"");
+        assert!(report.rejected[2]
+            .reason
+            .starts_with("malformed receipt: invalid date"));
+        assert_eq!(report.rejected[3].reason, "receipt is not acceptable");
+        assert_eq!(report.rejected[4].reason, SIBLING_REJECTED);
+        assert_eq!(report.rejected[5].reason, CONFLICTING_RECEIPT);
+        assert!(report.duplicates.is_empty());
+
+        // importing the same receipts again reports them as duplicates
+        let again = import(
+            &connection,
+            csv.as_bytes(),
+            &mapping,
+            &ReceiptConfig::default(),
+        )
+        .await
+        .unwrap();
+        assert!(again.accepted.is_empty());
+        assert_eq!(again.duplicates, report.accepted);
+    }
+
+    #[actix_web::test]
+    async fn import_limits() {
+        let connection = Connection::new();
+        let csv = "receiptId,retailer,purchaseDate,purchaseTime,total,shortDescription,price
+1,Target,2022-01-02,13:13,2.50,Pepsi - 12-oz,1.25
+1,Target,2022-01-02,13:13,2.50,Dasani,1.25
+2,Walgreens,2022-01-02,08:13,1.25,Pepsi - 12-oz,1.25
+";
+        let receipts = ReceiptConfig {
+            max_items: 1,
+            max_string_length: 10,
+            ..Default::default()
+        };
+        let report = import(
+            &connection,
+            csv.as_bytes(),
+            &ColumnMapping::default(),
+            &receipts,
+        )
+        .await
+        .unwrap();
+        assert!(report.accepted.is_empty());
+        assert_eq!(
+            report
+                .rejected
+                .iter()
+                .map(|row| (row.row, row.reason.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (2, "receipt has 2 items, but at most 1 are allowed"),
+                (3, "receipt has 2 items, but at most 1 are allowed"),
+                (4, "`items[0].shortDescription` exceeds 10 characters"),
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn group_by_receipt_fields() {
+        let connection = Connection::new();
+        let csv = "\
+Store,Date,Time,Total,Description,Amount
+Target,2022-01-02,13:13,2.50,Pepsi - 12-oz,1.25
+Target,2022-01-02,13:13,2.50,Dasani,1.25
+";
+        let mapping = ColumnMapping {
+            retailer: "Store".to_owned(),
+            purchase_date: "Date".to_owned(),
+            purchase_time: "Time".to_owned(),
+            total: "Total".to_owned(),
+            short_description: "Description".to_owned(),
+            price: "Amount".to_owned(),
+            ..Default::default()
+        };
+        let report = import(
+            &connection,
+            csv.as_bytes(),
+            &mapping,
+            &ReceiptConfig::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.accepted.len(), 1);
+        assert_eq!(report.accepted[0].rows, vec![2, 3]);
+        assert!(report.rejected.is_empty());
+
+        assert_eq!(
+            import(
+                &connection,
+                csv.as_bytes(),
+                &ColumnMapping::default(),
+                &ReceiptConfig::default()
+            )
+            .await,
+            Err(ImportError::MissingColumn("retailer".to_owned()))
+        );
+    }
+}
@@ -0,0 +1,266 @@
+//! Contains asynchronous receipt processing. Clients submitting a receipt asynchronously get a job ID straight away,
+//! and a pool of background workers validates, stores and scores the receipt. Clients poll the job for its status
+//! and, once it finishes, the ID of the stored receipt or the reason it failed.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    sync::{Arc, RwLock},
+    thread,
+};
+
+use actix_web::{http::StatusCode, rt::System, web::Bytes, HttpResponse, ResponseError};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio::sync::{mpsc, Mutex};
+use uuid::Uuid;
+
+use crate::{
+    config::{JobConfig, ReceiptConfig},
+    db::{Connection, Stored},
+    routes::{parse_receipt, Encoding, ErrorResponse, CONFLICTING_TRANSACTION, UNAVAILABLE},
+};
+
+/// Where a job is in processing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(
+    tag = "status",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum JobStatus {
+    /// The job is waiting for a worker.
+    Queued,
+    /// A worker is processing the job.
+    Running,
+    /// The receipt was stored.
+    Succeeded { receipt_id: Uuid },
+    /// The receipt couldn't be stored.
+    Failed { error: String },
+}
+
+impl JobStatus {
+    /// Determines whether the job has finished, successfully or not.
+    pub fn is_finished(&self) -> bool {
+        matches!(self, Self::Succeeded { .. } | Self::Failed { .. })
+    }
+}
+
+/// A receipt submitted for asynchronous processing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Job {
+    pub id: Uuid,
+
+    #[serde(flatten)]
+    pub status: JobStatus,
+
+    #[serde(with = "time::serde::rfc3339")]
+    pub submitted_at: OffsetDateTime,
+
+    /// When the job's status last changed.
+    #[serde(with = "time::serde::rfc3339")]
+    pub updated_at: OffsetDateTime,
+
+    /// The member the receipt is owned by, if any. Only they, and clients that may read every member, may read the
+    /// job.
+    #[serde(skip)]
+    pub owner_id: Option<Uuid>,
+}
+
+/// Reasons a job can be refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobError {
+    /// Too many jobs are waiting for a worker.
+    QueueFull,
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::QueueFull => write!(f, "too many receipts are waiting to be processed"),
+        }
+    }
+}
+
+impl ResponseError for JobError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::QueueFull => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ErrorResponse {
+            error: self.to_string(),
+        })
+    }
+}
+
+/// A receipt waiting for a worker.
+#[derive(Debug)]
+struct Submission {
+    job_id: Uuid,
+
+    /// The request body the receipt is parsed from.
+    body: Bytes,
+
+    /// The encoding of the request body.
+    encoding: Encoding,
+
+    /// The member the receipt is owned by, if any.
+    owner_id: Option<Uuid>,
+}
+
+/// The jobs, along with the order finished jobs are forgotten in.
+#[derive(Debug, Default)]
+struct JobTable {
+    jobs: HashMap<Uuid, Job>,
+
+    /// The finished jobs, oldest first.
+    finished: VecDeque<Uuid>,
+}
+
+/// Submits receipts to the background workers, and tracks their jobs.
+#[derive(Debug, Clone)]
+pub struct JobQueue {
+    jobs: Arc<RwLock<JobTable>>,
+
+    /// Sends receipts to the workers.
+    sender: mpsc::Sender<Submission>,
+
+    /// The number of finished jobs remembered.
+    retained_jobs: usize,
+}
+
+impl JobQueue {
+    /// Starts the configured number of workers, each running its own actix system on its own thread, processing
+    /// receipts into the given database.
+    pub fn start(connection: Connection, receipts: ReceiptConfig, config: &JobConfig) -> Self {
+        let (sender, receiver) = mpsc::channel(config.queue_capacity.max(1));
+        let queue = Self {
+            jobs: Default::default(),
+            sender,
+            retained_jobs: config.retained_jobs,
+        };
+        let receiver = Arc::new(Mutex::new(receiver));
+        let receipts = Arc::new(receipts);
+        for _ in 0..config.workers.max(1) {
+            let queue = queue.clone();
+            let connection = connection.clone();
+            let receiver = Arc::clone(&receiver);
+            let receipts = Arc::clone(&receipts);
+            // workers run for the life of the process, since each holds a sender keeping the channel open
+            thread::spawn(move || {
+                System::new().block_on(async move {
+                    // the lock is held until a receipt arrives, so each receipt goes to exactly one worker
+                    while let Some(submission) = receiver.lock().await.recv().await {
+                        queue.process(submission, &connection, &receipts).await;
+                    }
+                })
+            });
+        }
+        queue
+    }
+
+    /// Submits a receipt request body in the given encoding for processing, returning the job ID.
+    pub fn submit(
+        &self,
+        body: Bytes,
+        encoding: Encoding,
+        owner_id: Option<Uuid>,
+    ) -> Result<Uuid, JobError> {
+        let now = OffsetDateTime::now_utc();
+        let job = Job {
+            id: Uuid::new_v4(),
+            status: JobStatus::Queued,
+            submitted_at: now,
+            updated_at: now,
+            owner_id,
+        };
+        let job_id = job.id;
+        self.jobs
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .jobs
+            .insert(job_id, job);
+        let submission = Submission {
+            job_id,
+            body,
+            encoding,
+            owner_id,
+        };
+        if self.sender.try_send(submission).is_err() {
+            self.jobs
+                .write()
+                .unwrap_or_else(|e| e.into_inner())
+                .jobs
+                .remove(&job_id);
+            return Err(JobError::QueueFull);
+        }
+        Ok(job_id)
+    }
+
+    /// Loads a job by ID. Returns None if there is no job for the ID, or if it finished long enough ago to be
+    /// forgotten.
+    pub fn load(&self, id: Uuid) -> Option<Job> {
+        self.jobs
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .jobs
+            .get(&id)
+            .cloned()
+    }
+
+    /// Updates a job's status, forgetting the oldest finished jobs beyond the number retained.
+    fn update(&self, id: Uuid, status: JobStatus) {
+        let mut table = self.jobs.write().unwrap_or_else(|e| e.into_inner());
+        let finished = status.is_finished();
+        let Some(job) = table.jobs.get_mut(&id) else {
+            return;
+        };
+        job.status = status;
+        job.updated_at = OffsetDateTime::now_utc();
+        if finished {
+            table.finished.push_back(id);
+            while table.finished.len() > self.retained_jobs {
+                if let Some(id) = table.finished.pop_front() {
+                    table.jobs.remove(&id);
+                }
+            }
+        }
+    }
+
+    /// Validates, stores and scores a submitted receipt.
+    async fn process(
+        &self,
+        submission: Submission,
+        connection: &Connection,
+        receipts: &ReceiptConfig,
+    ) {
+        self.update(submission.job_id, JobStatus::Running);
+        let status = match parse_receipt(&submission.body, submission.encoding, receipts) {
+            Ok(receipt) => match connection
+                .store_new_receipt(receipt, submission.owner_id)
+                .await
+            {
+                Ok(Some(Stored::New(receipt_id) | Stored::Duplicate(receipt_id))) => {
+                    JobStatus::Succeeded { receipt_id }
+                }
+                Ok(Some(Stored::Conflict(_))) => JobStatus::Failed {
+                    error: CONFLICTING_TRANSACTION.to_owned(),
+                },
+                Ok(None) => JobStatus::Failed {
+                    error: "receipt is not acceptable".to_owned(),
+                },
+                Err(_) => JobStatus::Failed {
+                    error: UNAVAILABLE.to_owned(),
+                },
+            },
+            Err(e) => JobStatus::Failed {
+                error: e.to_string(),
+            },
+        };
+        self.update(submission.job_id, status);
+    }
+}
@@ -0,0 +1,323 @@
+//! Contains the points ledger. Each member has an append-only log of transactions: points earned from their
+//! receipts, redeemed, adjusted by staff, and expired. A member's balance is the sum of their transactions.
+//!
+//! Points are spent first in, first out: redemptions and negative adjustments take points from the oldest credits
+//! with points left. Earned points left unspent when they expire are removed by an expiration transaction.
+
+use std::{fmt, io};
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::routes::{ErrorResponse, UNAVAILABLE};
+
+/// What a transaction records.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum TransactionKind {
+    /// Points earned from a receipt.
+    Earn {
+        receipt_id: Uuid,
+        /// When the points expire, if they do.
+        #[serde(default, with = "time::serde::rfc3339::option")]
+        expires_at: Option<OffsetDateTime>,
+    },
+    /// Points spent by the member.
+    Redeem { description: String },
+    /// Points granted or revoked by staff.
+    Adjust { reason: String },
+    /// Unspent points removed from an expired earn transaction.
+    Expire { earn_id: Uuid },
+}
+
+/// A transaction in a member's ledger.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Transaction {
+    pub id: Uuid,
+    pub user_id: Uuid,
+
+    /// The change to the member's balance, positive for credits and negative for debits.
+    pub points: i64,
+
+    #[serde(with = "time::serde::rfc3339")]
+    pub recorded_at: OffsetDateTime,
+
+    #[serde(flatten)]
+    pub kind: TransactionKind,
+}
+
+impl Transaction {
+    /// Constructs a new transaction recorded now.
+    pub fn new(user_id: Uuid, points: i64, kind: TransactionKind) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            user_id,
+            points,
+            recorded_at: OffsetDateTime::now_utc(),
+            kind,
+        }
+    }
+}
+
+/// Why staff adjusted the points on a receipt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ReasonCode {
+    /// Points granted to make up for a poor experience.
+    Goodwill,
+    /// Points revoked from a fraudulent receipt.
+    Fraud,
+    /// Points corrected after a mistake.
+    Correction,
+    Other,
+}
+
+impl ReasonCode {
+    /// Gets the name of the reason code, as used in JSON.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Goodwill => "goodwill",
+            Self::Fraud => "fraud",
+            Self::Correction => "correction",
+            Self::Other => "other",
+        }
+    }
+}
+
+/// A change staff made to the points on a receipt.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReceiptAdjustment {
+    pub id: Uuid,
+
+    /// The points added to the receipt, or removed if negative.
+    pub points: i64,
+
+    pub reason: ReasonCode,
+
+    /// Free-form detail about the adjustment.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+
+    /// The subject of the principal who made the adjustment.
+    pub actor: String,
+
+    #[serde(with = "time::serde::rfc3339")]
+    pub made_at: OffsetDateTime,
+}
+
+/// Reasons a ledger transaction can be refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedgerError {
+    /// The transaction would not change the balance.
+    ZeroPoints,
+    /// The member doesn't have enough points for the redemption.
+    InsufficientBalance { balance: i64 },
+    /// The transaction couldn't be persisted, so it wasn't recorded.
+    Unavailable,
+}
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ZeroPoints => write!(f, "transaction must be for a nonzero number of points"),
+            Self::InsufficientBalance { balance } => {
+                write!(f, "insufficient balance of {balance} points")
+            }
+            Self::Unavailable => write!(f, "{UNAVAILABLE}"),
+        }
+    }
+}
+
+impl From<io::Error> for LedgerError {
+    /// Reports a transaction that couldn't be persisted. The cause is logged when the write fails.
+    fn from(_: io::Error) -> Self {
+        Self::Unavailable
+    }
+}
+
+impl ResponseError for LedgerError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::ZeroPoints => StatusCode::BAD_REQUEST,
+            Self::InsufficientBalance { .. } => StatusCode::CONFLICT,
+            Self::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ErrorResponse {
+            error: self.to_string(),
+        })
+    }
+}
+
+/// Credited points not yet spent or expired.
+#[derive(Debug, Clone, Copy)]
+struct Lot {
+    transaction_id: Uuid,
+    remaining: i64,
+    expires_at: Option<OffsetDateTime>,
+}
+
+/// One member's transactions, oldest first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ledger {
+    transactions: Vec<Transaction>,
+}
+
+impl Ledger {
+    /// Gets the transactions, oldest first.
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.transactions
+    }
+
+    /// Gets the member's balance.
+    pub fn balance(&self) -> i64 {
+        self.transactions.iter().fold(0i64, |balance, transaction| {
+            balance.saturating_add(transaction.points)
+        })
+    }
+
+    /// Appends a transaction.
+    pub fn record(&mut self, transaction: Transaction) {
+        self.transactions.push(transaction);
+    }
+
+    /// Records expirations for earned points that have expired unspent by the given time, returning the new
+    /// transactions.
+    pub fn expire(&mut self, user_id: Uuid, now: OffsetDateTime) -> Vec<Transaction> {
+        let expirations: Vec<_> = self
+            .lots()
+            .into_iter()
+            .filter(|lot| lot.remaining > 0 && lot.expires_at.is_some_and(|at| at <= now))
+            .map(|lot| Transaction {
+                recorded_at: now,
+                ..Transaction::new(
+                    user_id,
+                    -lot.remaining,
+                    TransactionKind::Expire {
+                        earn_id: lot.transaction_id,
+                    },
+                )
+            })
+            .collect();
+        self.transactions.extend(expirations.iter().cloned());
+        expirations
+    }
+
+    /// Gets the credits with points remaining after every debit so far, oldest first.
+    fn lots(&self) -> Vec<Lot> {
+        let mut lots: Vec<Lot> = Vec::new();
+        for transaction in &self.transactions {
+            match &transaction.kind {
+                TransactionKind::Expire { earn_id } => {
+                    if let Some(lot) = lots.iter_mut().find(|lot| lot.transaction_id == *earn_id) {
+                        lot.remaining += transaction.points;
+                    }
+                }
+                kind if transaction.points > 0 => lots.push(Lot {
+                    transaction_id: transaction.id,
+                    remaining: transaction.points,
+                    expires_at: match kind {
+                        TransactionKind::Earn { expires_at, .. } => *expires_at,
+                        _ => None,
+                    },
+                }),
+                _ => {
+                    let mut debit = -transaction.points;
+                    for lot in lots.iter_mut().filter(|lot| lot.remaining > 0) {
+                        let taken = debit.min(lot.remaining);
+                        lot.remaining -= taken;
+                        debit -= taken;
+                        if debit == 0 {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        lots
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn expiration() {
+        let user_id = Uuid::new_v4();
+        let start = OffsetDateTime::now_utc();
+        let earn = |points, expires_at| {
+            Transaction::new(
+                user_id,
+                points,
+                TransactionKind::Earn {
+                    receipt_id: Uuid::new_v4(),
+                    expires_at: Some(expires_at),
+                },
+            )
+        };
+        let mut ledger = Ledger::default();
+        let first = earn(30, start + Duration::days(30));
+        ledger.record(first.clone());
+        ledger.record(earn(50, start + Duration::days(60)));
+        ledger.record(Transaction::new(
+            user_id,
+            -20,
+            TransactionKind::Redeem {
+                description: "Coffee".to_owned(),
+            },
+        ));
+        assert_eq!(ledger.balance(), 60);
+
+        // nothing has expired yet
+        assert!(ledger.expire(user_id, start).is_empty());
+
+        // the redemption spent the oldest points first, so 10 are left to expire
+        let expired = ledger.expire(user_id, start + Duration::days(30));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].points, -10);
+        assert_eq!(
+            expired[0].kind,
+            TransactionKind::Expire { earn_id: first.id }
+        );
+        assert_eq!(ledger.balance(), 50);
+        // expired points are only expired once
+        assert!(ledger
+            .expire(user_id, start + Duration::days(31))
+            .is_empty());
+
+        let expired = ledger.expire(user_id, start + Duration::days(60));
+        assert_eq!(expired[0].points, -50);
+        assert_eq!(ledger.balance(), 0);
+    }
+
+    #[test]
+    fn serialize_transaction() {
+        let transaction = Transaction::new(
+            Uuid::new_v4(),
+            -20,
+            TransactionKind::Redeem {
+                description: "Coffee".to_owned(),
+            },
+        );
+        let json = serde_json::to_value(&transaction).unwrap();
+        assert_eq!(json["type"], "redeem");
+        assert_eq!(json["description"], "Coffee");
+        assert_eq!(
+            serde_json::from_value::<Transaction>(json).unwrap(),
+            transaction
+        );
+    }
+}
@@ -0,0 +1,33 @@
+//! A web backend that awards members points for their receipts. The server binary lives in `main.rs`; the modules
+//! here are exposed as a library so they can be benchmarked.
+
+use config::Config;
+use db::Connection;
+
+pub mod audit;
+pub mod auth;
+pub mod campaigns;
+pub mod config;
+pub mod data;
+pub mod db;
+pub mod events;
+pub mod export;
+pub mod fraud;
+pub mod grpc;
+pub mod import;
+pub mod jobs;
+pub mod ledger;
+pub mod points;
+pub mod rate_limit;
+pub mod retailers;
+pub mod review;
+pub mod routes;
+pub mod stats;
+pub mod webhooks;
+
+/// State for this application. Holds a handle to the "database connection" and the application configuration.
+#[derive(Debug)]
+pub struct AppState {
+    pub connection: Connection,
+    pub config: Config,
+}
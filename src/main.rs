@@ -1,34 +1,139 @@
-use std::io;
+use std::{env, fs::File, io, sync::Arc, time::Duration};
 
 use actix_web::{web, App, HttpServer};
-use db::Connection;
-
-mod data;
-mod db;
-mod routes;
-
-/// State for this application. Holds a handle to the "database connection".
-#[derive(Debug)]
-struct AppState {
-    connection: Connection,
-}
+use futures_util::future::{self, Either};
+use serve_ex::{
+    auth::{Authentication, Authenticator},
+    config::Config,
+    db::Connection,
+    grpc::{self, ReceiptService},
+    import,
+    jobs::JobQueue,
+    points::RulesetRegistry,
+    rate_limit::{RateLimit, RateLimiter},
+    retailers::RetailerRegistry,
+    routes, AppState,
+};
 
 #[actix_web::main]
 async fn main() -> io::Result<()> {
+    let config = Config::load()?;
+
     // for simplicity, we'll create a "connection" to our "database" here
-    let db_conn = Connection::new();
+    let db_conn = match &config.retailers.registry {
+        Some(path) => Connection::with_retailers(RetailerRegistry::load(
+            path,
+            config.retailers.fuzzy_threshold,
+        )?),
+        None => Connection::new(),
+    }
+    .with_rulesets(match &config.points.rulesets {
+        Some(path) => RulesetRegistry::load(path)?,
+        None => RulesetRegistry::default(),
+    })
+    .with_eviction(config.store.clone())
+    .with_ledger(&config.ledger)
+    .with_fraud(&config.fraud)
+    .with_webhooks(&config.webhooks)
+    .with_events(&config.events)
+    .with_persistence(&config.persistence)?;
+
+    // `serve-ex import <file>` imports receipts from a CSV file instead of serving
+    let mut args = env::args().skip(1);
+    if let Some(command) = args.next() {
+        if command != "import" {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown command \"{command}\""),
+            ));
+        }
+        let Some(path) = args.next() else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "usage: serve-ex import <file>",
+            ));
+        };
+        let report = import::import(
+            &db_conn,
+            File::open(path)?,
+            &config.import.columns,
+            &config.receipts,
+        )
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).expect("reports should serialize")
+        );
+        // webhook notifications for imported receipts would be lost on exit
+        db_conn.drain_webhooks();
+        return db_conn.snapshot().await;
+    }
+
+    let authentication = Authentication::new(Authenticator::from_config(&config.auth)?);
+    let limiter = Arc::new(RateLimiter::from_config(&config.rate_limits));
+    let rate_limit = RateLimit::new(Arc::clone(&limiter));
+    let jobs = JobQueue::start(db_conn.clone(), config.receipts.clone(), &config.jobs);
+
+    // the gRPC interface is bound before anything is served, so startup fails if its port is unavailable
+    let grpc = match config.grpc.port {
+        Some(port) => {
+            let incoming = grpc::bind(port)?;
+            let service = ReceiptService::new(
+                db_conn.clone(),
+                Authenticator::from_config(&config.auth)?,
+                config.receipts.clone(),
+            );
+            Some(service.serve(incoming, limiter))
+        }
+        None => None,
+    };
+
+    // snapshot periodically so the write-ahead log doesn't grow without bound
+    if config.persistence.directory.is_some() {
+        let db_conn = db_conn.clone();
+        let period = Duration::from_secs(config.persistence.snapshot_interval_seconds.max(1));
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(period);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if let Err(e) = db_conn.snapshot().await {
+                    eprintln!("failed to write snapshot: {e}");
+                }
+            }
+        });
+    }
 
     // construct and run a basic HTTP server with our endpoints
     // you'll need to have localhost:8080 available
-    HttpServer::new(move || {
+    let snapshot_conn = db_conn.clone();
+    let server = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(AppState {
                 connection: db_conn.clone(),
+                config: config.clone(),
             }))
-            .service(routes::get_points)
-            .service(routes::process_receipt)
+            .app_data(web::Data::new(jobs.clone()))
+            // middleware wrapped last runs first, so clients are authenticated before being rate limited
+            .wrap(rate_limit.clone())
+            .wrap(authentication.clone())
+            .configure(routes::configure)
     })
     .bind(("127.0.0.1", 8080))?
-    .run()
-    .await
+    .run();
+
+    // serve the gRPC interface alongside the JSON API, stopping if either fails
+    match grpc {
+        Some(grpc) => match future::select(server, Box::pin(grpc)).await {
+            Either::Left((result, _)) => result?,
+            Either::Right((result, _)) => result.map_err(io::Error::other)?,
+        },
+        None => server.await?,
+    }
+
+    // finish delivering webhook notifications, which would be lost on exit, then take a final snapshot on shutdown so
+    // the next startup needn't replay the log
+    snapshot_conn.drain_webhooks();
+    snapshot_conn.snapshot().await
 }
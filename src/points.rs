@@ -0,0 +1,338 @@
+//! Contains the rules awarding points for receipts. Rules are grouped into named, versioned rulesets, each in effect
+//! over a range of purchase dates. A receipt is awarded points once, when it is stored, under the ruleset in effect on
+//! its purchase date, so later changes to the rules don't change the points of receipts already stored.
+
+use std::{fs, io, num::Saturating, path::Path};
+
+use serde::{Deserialize, Serialize};
+use time::{macros::time, Date, Time};
+
+use crate::{
+    campaigns::CampaignBonus,
+    data::{serialization, Item},
+    db::ReceiptRecord,
+};
+
+/// Points awarded for a receipt.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PointsAward {
+    /// The points awarded, including campaign bonuses.
+    pub points: u64,
+
+    /// The version of the ruleset that awarded the points. Version 0 means the points were never awarded.
+    pub ruleset_version: u32,
+
+    /// The bonus points awarded by campaigns, on top of the ruleset's points.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bonuses: Vec<CampaignBonus>,
+}
+
+impl PointsAward {
+    /// Determines whether points have been awarded.
+    pub fn is_awarded(&self) -> bool {
+        self.ruleset_version != 0
+    }
+}
+
+/// The amounts awarded by each rule.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct Rules {
+    /// Points for each letter or numeral in the retailer name.
+    pub retailer_character_points: u64,
+
+    /// Points if the total is a whole number of dollars.
+    pub round_total_points: u64,
+
+    /// Points if the total is a multiple of $0.25.
+    pub quarter_total_points: u64,
+
+    /// Points for every two items.
+    pub item_pair_points: u64,
+
+    /// Items whose trimmed description length is a multiple of this earn points based on their price.
+    pub description_length_multiple: usize,
+
+    /// The fraction of the price, rounded up, awarded for items with a qualifying description.
+    pub description_price_multiplier: f64,
+
+    /// Points if the day of the purchase date is odd.
+    pub odd_day_points: u64,
+
+    /// Points if the purchase was made strictly between `afternoon_start` and `afternoon_end`.
+    pub afternoon_points: u64,
+
+    /// The start of the afternoon window.
+    #[serde(with = "serialization::time")]
+    pub afternoon_start: Time,
+
+    /// The end of the afternoon window.
+    #[serde(with = "serialization::time")]
+    pub afternoon_end: Time,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            retailer_character_points: 1,
+            round_total_points: 50,
+            quarter_total_points: 25,
+            item_pair_points: 5,
+            description_length_multiple: 3,
+            description_price_multiplier: 0.2,
+            odd_day_points: 6,
+            afternoon_points: 10,
+            afternoon_start: time!(14:00),
+            afternoon_end: time!(16:00),
+        }
+    }
+}
+
+/// A named, versioned set of rules, in effect for receipts purchased within a range of dates.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Ruleset {
+    /// The version of the ruleset, unique among rulesets. Version 0 is reserved.
+    pub version: u32,
+
+    /// A human-readable name for the ruleset.
+    pub name: String,
+
+    /// The first purchase date the ruleset is in effect for. If absent, the ruleset has no start.
+    #[serde(default, with = "serialization::date::option")]
+    pub effective_from: Option<Date>,
+
+    /// The last purchase date the ruleset is in effect for. If absent, the ruleset has no end.
+    #[serde(default, with = "serialization::date::option")]
+    pub effective_until: Option<Date>,
+
+    /// The amounts awarded by each rule.
+    #[serde(default)]
+    pub rules: Rules,
+}
+
+impl Ruleset {
+    /// Constructs the ruleset points were originally awarded under.
+    pub fn standard() -> Self {
+        Self {
+            version: 1,
+            name: "standard".to_owned(),
+            effective_from: None,
+            effective_until: None,
+            rules: Rules::default(),
+        }
+    }
+
+    /// Determines whether the ruleset is in effect for receipts purchased on the date.
+    pub fn is_effective(&self, date: Date) -> bool {
+        self.effective_from.map_or(true, |from| from <= date)
+            && self.effective_until.map_or(true, |until| date <= until)
+    }
+
+    /// Awards points for the receipt under this ruleset, without any campaign bonuses.
+    pub fn award(&self, record: &ReceiptRecord) -> PointsAward {
+        PointsAward {
+            points: self.calculate_points(record),
+            ruleset_version: self.version,
+            bonuses: Vec::new(),
+        }
+    }
+
+    /// Calculates points for the receipt, as follows:
+    /// - points for each letter or numeral in the retailer name. The canonical retailer name is used when the retailer
+    ///   is known, so spelling variants of a name earn the same points.
+    /// - points if the total has .00 cents
+    /// - points if the total is a multiple of .25 cents
+    /// - points for every two items. Items with a quantity count once per unit.
+    /// - if the (trimmed) length of an item description is a multiple of the configured length, add points according
+    ///   to ceil(price * multiplier). Items with a unit price earn these points per unit.
+    /// - points if the day of the purchase date is odd
+    /// - points if the time of the purchase is within the afternoon window (exclusive)
+    ///
+    /// The amounts are given by the ruleset's [`Rules`].
+    pub fn calculate_points(&self, record: &ReceiptRecord) -> u64 {
+        let rules = &self.rules;
+        let receipt = &record.receipt;
+
+        // overflow is unlikely, but if it happens we just saturate
+        let mut total_pts = Saturating(0u64);
+
+        let letter_digit_count: u64 = record
+            .retailer_name()
+            .chars()
+            .filter(|c| c.is_alphabetic() || c.is_numeric())
+            .count()
+            .try_into()
+            .expect("length should fit in 64 bits");
+        total_pts += rules
+            .retailer_character_points
+            .saturating_mul(letter_digit_count);
+
+        if receipt.total.cents == 0 {
+            total_pts += rules.round_total_points;
+        }
+
+        if receipt.total.cents % 25 == 0 {
+            total_pts += rules.quarter_total_points;
+        }
+
+        let item_count: u64 = receipt.items.iter().map(Item::units).sum();
+        total_pts += rules.item_pair_points.saturating_mul(item_count / 2);
+
+        for item in receipt.items.iter() {
+            let length = item.short_description.trim().len();
+            if rules.description_length_multiple != 0
+                && length % rules.description_length_multiple == 0
+            {
+                let (price, units) = match item.unit_price {
+                    Some(unit_price) => (unit_price, item.units()),
+                    None => (item.price, 1),
+                };
+                let float_price = price.dollars as f64 + f64::from(price.cents) / 100.0;
+                let price_pts = (float_price * rules.description_price_multiplier).ceil() as u64;
+                total_pts += price_pts.saturating_mul(units);
+            }
+        }
+
+        if receipt.purchase_date.day() % 2 != 0 {
+            total_pts += rules.odd_day_points;
+        }
+
+        if receipt.purchase_time > rules.afternoon_start
+            && receipt.purchase_time < rules.afternoon_end
+        {
+            total_pts += rules.afternoon_points;
+        }
+
+        total_pts.0
+    }
+}
+
+/// The rulesets points may be awarded under.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RulesetRegistry {
+    /// The rulesets, ordered by version.
+    rulesets: Vec<Ruleset>,
+}
+
+impl Default for RulesetRegistry {
+    /// Constructs a registry holding only the standard ruleset.
+    fn default() -> Self {
+        Self {
+            rulesets: vec![Ruleset::standard()],
+        }
+    }
+}
+
+impl RulesetRegistry {
+    /// Constructs a registry of the given rulesets. There must be at least one ruleset, and versions must be nonzero
+    /// and unique.
+    pub fn new(mut rulesets: Vec<Ruleset>) -> io::Result<Self> {
+        rulesets.sort_by_key(|ruleset| ruleset.version);
+        if rulesets.is_empty() {
+            return Err(io::Error::other("at least one ruleset is required"));
+        }
+        if rulesets[0].version == 0 {
+            return Err(io::Error::other("ruleset version 0 is reserved"));
+        }
+        if let Some(pair) = rulesets
+            .windows(2)
+            .find(|pair| pair[0].version == pair[1].version)
+        {
+            return Err(io::Error::other(format!(
+                "duplicate ruleset version {}",
+                pair[0].version
+            )));
+        }
+        Ok(Self { rulesets })
+    }
+
+    /// Loads a registry from a JSON file containing a list of rulesets.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let contents = fs::read(path)?;
+        let rulesets = serde_json::from_slice(&contents).map_err(io::Error::other)?;
+        Self::new(rulesets)
+    }
+
+    /// Gets the ruleset with the given version.
+    pub fn get(&self, version: u32) -> Option<&Ruleset> {
+        self.rulesets
+            .binary_search_by_key(&version, |ruleset| ruleset.version)
+            .ok()
+            .map(|i| &self.rulesets[i])
+    }
+
+    /// Gets the ruleset in effect for receipts purchased on the date. If several are in effect, the latest version
+    /// is used. If none are, the latest version overall is used.
+    pub fn for_date(&self, date: Date) -> &Ruleset {
+        self.rulesets
+            .iter()
+            .rev()
+            .find(|ruleset| ruleset.is_effective(date))
+            .or(self.rulesets.last())
+            .expect("registry should have at least one ruleset")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::date;
+
+    use super::*;
+
+    fn ruleset(version: u32, from: Option<Date>, until: Option<Date>) -> Ruleset {
+        Ruleset {
+            version,
+            name: format!("v{version}"),
+            effective_from: from,
+            effective_until: until,
+            rules: Rules::default(),
+        }
+    }
+
+    #[test]
+    fn ruleset_for_date() {
+        let registry = RulesetRegistry::new(vec![
+            ruleset(2, Some(date!(2023 - 01 - 01)), Some(date!(2023 - 12 - 31))),
+            ruleset(1, None, Some(date!(2023 - 06 - 30))),
+            ruleset(3, Some(date!(2025 - 01 - 01)), None),
+        ])
+        .unwrap();
+        assert_eq!(registry.for_date(date!(2022 - 05 - 01)).version, 1);
+        // the latest version in effect wins
+        assert_eq!(registry.for_date(date!(2023 - 03 - 01)).version, 2);
+        assert_eq!(registry.for_date(date!(2023 - 12 - 31)).version, 2);
+        // with no ruleset in effect, the latest is used
+        assert_eq!(registry.for_date(date!(2024 - 06 - 01)).version, 3);
+        assert_eq!(registry.get(2).unwrap().name, "v2");
+        assert_eq!(registry.get(4), None);
+    }
+
+    #[test]
+    fn invalid_registry() {
+        assert!(RulesetRegistry::new(Vec::new()).is_err());
+        assert!(RulesetRegistry::new(vec![ruleset(0, None, None)]).is_err());
+        assert!(
+            RulesetRegistry::new(vec![ruleset(1, None, None), ruleset(1, None, None)]).is_err()
+        );
+    }
+
+    #[test]
+    fn deserialize_ruleset() {
+        let ruleset: Ruleset = serde_json::from_str(
+            r#"{
+                "version": 2,
+                "name": "double afternoons",
+                "effectiveFrom": "2024-01-01",
+                "rules": { "afternoonPoints": 20, "afternoonEnd": "17:00" }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(ruleset.effective_from, Some(date!(2024 - 01 - 01)));
+        assert_eq!(ruleset.effective_until, None);
+        assert_eq!(ruleset.rules.afternoon_points, 20);
+        assert_eq!(ruleset.rules.afternoon_end, time!(17:00));
+        assert_eq!(ruleset.rules.round_total_points, 50);
+    }
+}
@@ -0,0 +1,333 @@
+//! Contains per-client rate limiting. The [`RateLimit`] middleware keeps a token bucket for each client on each limited
+//! route. Each request takes a token from its bucket, and buckets refill at a constant rate up to their capacity.
+//! Requests finding their bucket empty are rejected with 429 Too Many Requests.
+//!
+//! Clients are identified by their authenticated principal, or by IP address if they are anonymous, so the
+//! [`Authentication`](crate::auth::Authentication) middleware must run before this one. The same [`RateLimiter`] can
+//! be shared with other interfaces, so their limits apply across them.
+
+use std::{
+    collections::HashMap,
+    future::{ready, Ready},
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{self, HeaderName, HeaderValue},
+    HttpMessage, HttpResponse,
+};
+use futures_util::future::{Either, LocalBoxFuture};
+
+use crate::{
+    auth::Principal,
+    config::{LimitConfig, RateLimitConfig},
+    routes::ErrorResponse,
+};
+
+/// The header holding the capacity of the client's bucket.
+const LIMIT_HEADER: HeaderName = HeaderName::from_static("x-ratelimit-limit");
+
+/// The header holding the number of requests the client may make before being limited.
+const REMAINING_HEADER: HeaderName = HeaderName::from_static("x-ratelimit-remaining");
+
+/// How often buckets that have refilled are discarded.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A token bucket for one client on one route.
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    /// The number of tokens in the bucket as of `updated`.
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Gets the number of tokens in the bucket at the given time.
+    fn tokens_at(&self, now: Instant, limit: &LimitConfig) -> f64 {
+        let refilled = now.duration_since(self.updated).as_secs_f64() * limit.refill_per_second;
+        (self.tokens + refilled).min(f64::from(limit.capacity))
+    }
+}
+
+/// The result of a request taking a token.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Decision {
+    /// The request may proceed, and the client has this many requests remaining.
+    Allowed { remaining: u32 },
+    /// The request is limited, and the client must wait this long before retrying.
+    Limited { retry_after: Duration },
+}
+
+/// Identifies a client by its principal, or by IP address if it is anonymous, since anonymous clients all share a
+/// principal.
+fn client(principal: Option<&Principal>, ip: Option<IpAddr>) -> String {
+    match (principal, ip) {
+        (Some(principal), _) if !principal.is_anonymous() => {
+            format!("principal:{}", principal.subject)
+        }
+        (_, Some(ip)) => format!("ip:{ip}"),
+        (_, None) => "ip:unknown".to_owned(),
+    }
+}
+
+/// Gets the whole seconds a limited client should wait before retrying, rounded up so clients retrying after that
+/// long will have a token.
+fn retry_after_seconds(retry_after: Duration) -> u64 {
+    retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0)
+}
+
+/// The token buckets, along with when refilled buckets were last discarded.
+#[derive(Debug, Default)]
+struct BucketTable {
+    /// The bucket for each route and client.
+    buckets: HashMap<(String, String), Bucket>,
+
+    /// When refilled buckets were last discarded, if they ever have been.
+    pruned_at: Option<Instant>,
+}
+
+/// Tracks token buckets for every client on every limited route.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    /// The limits for each route, keyed by method and path pattern, e.g. `POST /receipts/process`.
+    limits: HashMap<String, LimitConfig>,
+
+    buckets: Mutex<BucketTable>,
+}
+
+impl RateLimiter {
+    /// Constructs a rate limiter from the configuration.
+    pub fn from_config(config: &RateLimitConfig) -> Self {
+        Self {
+            limits: config.routes.clone(),
+            buckets: Default::default(),
+        }
+    }
+
+    /// Takes a token from the bucket of the client with the given principal and IP address for the route, if the route
+    /// is limited. If the bucket is empty, returns the seconds the client should wait before retrying.
+    pub fn acquire(
+        &self,
+        route: &str,
+        principal: &Principal,
+        ip: Option<IpAddr>,
+    ) -> Result<(), u64> {
+        let Some(limit) = self.limits.get(route) else {
+            return Ok(());
+        };
+        match self.take(route, &client(Some(principal), ip), limit, Instant::now()) {
+            Decision::Allowed { .. } => Ok(()),
+            Decision::Limited { retry_after } => Err(retry_after_seconds(retry_after)),
+        }
+    }
+
+    /// Takes a token from the client's bucket for the route.
+    fn take(&self, route: &str, client: &str, limit: &LimitConfig, now: Instant) -> Decision {
+        let mut table = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if table.pruned_at.map_or(true, |at| {
+            now.saturating_duration_since(at) >= PRUNE_INTERVAL
+        }) {
+            // full buckets are indistinguishable from new ones, so they don't need to be kept
+            table.buckets.retain(|(route, _), bucket| {
+                self.limits
+                    .get(route)
+                    .is_some_and(|limit| bucket.tokens_at(now, limit) < f64::from(limit.capacity))
+            });
+            table.pruned_at = Some(now);
+        }
+
+        let bucket = table
+            .buckets
+            .entry((route.to_owned(), client.to_owned()))
+            .or_insert(Bucket {
+                tokens: f64::from(limit.capacity),
+                updated: now,
+            });
+        let tokens = bucket.tokens_at(now, limit);
+        *bucket = Bucket {
+            tokens,
+            updated: now,
+        };
+        if tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Decision::Allowed {
+                remaining: bucket.tokens as u32,
+            }
+        } else {
+            let seconds = (1.0 - tokens) / limit.refill_per_second;
+            Decision::Limited {
+                retry_after: Duration::from_secs_f64(seconds.min(u32::MAX.into())),
+            }
+        }
+    }
+}
+
+/// Middleware that limits the rate of requests each client may make to each configured route.
+#[derive(Clone)]
+pub struct RateLimit {
+    limiter: Arc<RateLimiter>,
+}
+
+impl RateLimit {
+    /// Constructs the middleware using the given rate limiter.
+    pub fn new(limiter: Arc<RateLimiter>) -> Self {
+        Self { limiter }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service,
+            limiter: self.limiter.clone(),
+        }))
+    }
+}
+
+/// The service produced by the [`RateLimit`] middleware.
+pub struct RateLimitMiddleware<S> {
+    service: S,
+    limiter: Arc<RateLimiter>,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = Either<
+        LocalBoxFuture<'static, Result<Self::Response, Self::Error>>,
+        Ready<Result<Self::Response, Self::Error>>,
+    >;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let route = match req.match_pattern() {
+            Some(pattern) => format!("{} {pattern}", req.method()),
+            None => format!("{} {}", req.method(), req.path()),
+        };
+        let Some(limit) = self.limiter.limits.get(&route) else {
+            let response = self.service.call(req);
+            return Either::Left(Box::pin(async move {
+                response.await.map(ServiceResponse::map_into_left_body)
+            }));
+        };
+
+        let client = client(
+            req.extensions().get::<Principal>(),
+            req.peer_addr().map(|addr| addr.ip()),
+        );
+
+        let capacity = HeaderValue::from(limit.capacity);
+        match self.limiter.take(&route, &client, limit, Instant::now()) {
+            Decision::Allowed { remaining } => {
+                let response = self.service.call(req);
+                Either::Left(Box::pin(async move {
+                    let mut response = response.await?;
+                    let headers = response.headers_mut();
+                    headers.insert(LIMIT_HEADER, capacity);
+                    headers.insert(REMAINING_HEADER, HeaderValue::from(remaining));
+                    Ok(response.map_into_left_body())
+                }))
+            }
+            Decision::Limited { retry_after } => {
+                let retry_after = retry_after_seconds(retry_after);
+                let response = HttpResponse::TooManyRequests()
+                    .insert_header((header::RETRY_AFTER, retry_after))
+                    .insert_header((LIMIT_HEADER, capacity))
+                    .insert_header((REMAINING_HEADER, 0))
+                    .json(ErrorResponse {
+                        error: format!("rate limit exceeded, retry after {retry_after} seconds"),
+                    })
+                    .map_into_right_body();
+                Either::Right(ready(Ok(req.into_response(response))))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_bucket() {
+        let limit = LimitConfig {
+            capacity: 2,
+            refill_per_second: 0.5,
+        };
+        let limiter = RateLimiter::default();
+        let start = Instant::now();
+        let take = |client, secs| {
+            limiter.take(
+                "POST /receipts/process",
+                client,
+                &limit,
+                start + Duration::from_secs(secs),
+            )
+        };
+
+        assert_eq!(take("a", 0), Decision::Allowed { remaining: 1 });
+        assert_eq!(take("a", 0), Decision::Allowed { remaining: 0 });
+        assert_eq!(
+            take("a", 0),
+            Decision::Limited {
+                retry_after: Duration::from_secs(2)
+            }
+        );
+        // other clients have their own buckets
+        assert_eq!(take("b", 0), Decision::Allowed { remaining: 1 });
+        // one token is refilled every two seconds
+        assert_eq!(take("a", 2), Decision::Allowed { remaining: 0 });
+        assert_eq!(take("a", 10), Decision::Allowed { remaining: 1 });
+    }
+
+    #[test]
+    fn pruning() {
+        let limit = LimitConfig {
+            capacity: 2,
+            refill_per_second: 1.0,
+        };
+        let limiter = RateLimiter {
+            limits: HashMap::from([("GET /events".to_owned(), limit)]),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let take = |client, secs| limiter.take("GET /events", client, &limit, start + secs);
+        let bucket_count = || limiter.buckets.lock().unwrap().buckets.len();
+
+        take("a", Duration::ZERO);
+        take("b", PRUNE_INTERVAL - Duration::from_secs(1));
+        take("b", PRUNE_INTERVAL - Duration::from_secs(1));
+        // a's bucket has refilled, but the interval hasn't passed
+        assert_eq!(bucket_count(), 2);
+
+        // once it has, only buckets that have refilled are discarded
+        take("c", PRUNE_INTERVAL);
+        assert!(limiter
+            .buckets
+            .lock()
+            .unwrap()
+            .buckets
+            .keys()
+            .all(|(_, client)| client != "a"));
+        assert_eq!(bucket_count(), 2);
+    }
+}
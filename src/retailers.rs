@@ -0,0 +1,162 @@
+//! Contains the retailer registry, which maps the many spellings of a retailer's name on receipts (e.g. "TARGET",
+//! "Target Store #1234") to a single canonical retailer.
+
+use std::{collections::HashMap, fs, io, path::Path, sync::OnceLock};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// A retailer known to the registry.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Retailer {
+    /// The stable identifier for the retailer.
+    pub id: String,
+
+    /// The canonical name of the retailer.
+    pub name: String,
+
+    /// Other names the retailer may appear under on receipts.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+/// A registry of known retailers.
+#[derive(Debug, Clone, Default)]
+pub struct RetailerRegistry {
+    /// The retailers in the registry.
+    retailers: Vec<Retailer>,
+
+    /// Index into `retailers` by normalized name or alias.
+    by_name: HashMap<String, usize>,
+
+    /// The minimum similarity, from 0 to 1, for a name to fuzzily match a retailer.
+    fuzzy_threshold: f64,
+}
+
+impl RetailerRegistry {
+    /// Constructs a registry from a list of retailers.
+    pub fn new(retailers: Vec<Retailer>, fuzzy_threshold: f64) -> Self {
+        let mut by_name = HashMap::new();
+        for (i, retailer) in retailers.iter().enumerate() {
+            for name in std::iter::once(&retailer.name).chain(retailer.aliases.iter()) {
+                by_name.entry(normalize(name)).or_insert(i);
+            }
+        }
+        Self {
+            retailers,
+            by_name,
+            fuzzy_threshold,
+        }
+    }
+
+    /// Loads a registry from a JSON file containing a list of retailers.
+    pub fn load(path: &Path, fuzzy_threshold: f64) -> io::Result<Self> {
+        let contents = fs::read(path)?;
+        let retailers = serde_json::from_slice(&contents).map_err(io::Error::other)?;
+        Ok(Self::new(retailers, fuzzy_threshold))
+    }
+
+    /// Finds the retailer a name on a receipt refers to. Names are matched exactly after normalization, then fuzzily
+    /// against every known name and alias. Returns None if no retailer matches.
+    pub fn resolve(&self, name: &str) -> Option<&Retailer> {
+        let name = normalize(name);
+        if let Some(&i) = self.by_name.get(&name) {
+            return Some(&self.retailers[i]);
+        }
+        self.by_name
+            .iter()
+            .map(|(known, &i)| (strsim::normalized_levenshtein(&name, known), i))
+            .filter(|&(similarity, _)| similarity >= self.fuzzy_threshold)
+            // break ties by index so the result doesn't depend on hash order
+            .max_by(|(a, i), (b, j)| a.total_cmp(b).then(j.cmp(i)))
+            .map(|(_, i)| &self.retailers[i])
+    }
+}
+
+/// Normalizes a retailer name for matching. Case, punctuation, repeated whitespace and trailing store numbers are
+/// removed. Only numbers following a `#` or the word "Store" (e.g. "Store #1234", "Store 12", "#1234") are store
+/// numbers, so names ending in a number, like "Forever 21", keep it.
+pub fn normalize(name: &str) -> String {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    let store_number = REGEX.get_or_init(|| {
+        Regex::new(r"(?i)\s+(?:store\s*(?:no\.?\s*)?#?|#)\s*\d+\s*$")
+            .expect("store number regex should be valid")
+    });
+
+    let name = store_number.replace(name.trim(), "");
+    name.split(|c: char| !(c.is_alphanumeric() || c == '&'))
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> RetailerRegistry {
+        RetailerRegistry::new(
+            vec![
+                Retailer {
+                    id: "target".to_owned(),
+                    name: "Target".to_owned(),
+                    aliases: vec!["Tgt".to_owned()],
+                },
+                Retailer {
+                    id: "mm-corner-market".to_owned(),
+                    name: "M&M Corner Market".to_owned(),
+                    aliases: vec![],
+                },
+            ],
+            0.8,
+        )
+    }
+
+    #[test]
+    fn spelling_variants() {
+        let registry = registry();
+        for name in [
+            "Target",
+            "TARGET",
+            "Target Store #1234",
+            "  target ",
+            "TGT",
+            "Targett",
+        ] {
+            assert_eq!(
+                registry.resolve(name).map(|r| r.id.as_str()),
+                Some("target"),
+                "{name}"
+            );
+        }
+        assert_eq!(
+            registry
+                .resolve("M & M Corner Market")
+                .map(|r| r.id.as_str()),
+            Some("mm-corner-market")
+        );
+    }
+
+    #[test]
+    fn store_numbers() {
+        for (name, normalized) in [
+            ("Target Store #1234", "target"),
+            ("Target Store 12", "target"),
+            ("Target Store No. 7", "target"),
+            ("Target #1234", "target"),
+            ("Forever 21", "forever 21"),
+            ("Walgreens 24", "walgreens 24"),
+            ("7-Eleven", "7 eleven"),
+        ] {
+            assert_eq!(normalize(name), normalized, "{name}");
+        }
+    }
+
+    #[test]
+    fn unknown_retailer() {
+        assert_eq!(registry().resolve("Walgreens"), None);
+        assert_eq!(RetailerRegistry::default().resolve("Target"), None);
+    }
+}
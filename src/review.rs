@@ -0,0 +1,94 @@
+//! Contains the manual review of receipts. Receipts whose risk score holds them for review start out pending, and
+//! staff either approve them, crediting their points to their owner, or reject them, leaving them worth nothing.
+
+use std::{fmt, io};
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::routes::{ErrorResponse, UNAVAILABLE};
+
+/// Where a receipt is in the review process.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ReceiptStatus {
+    /// The receipt is held for review, and is worth no points until approved.
+    Pending,
+    /// The receipt is worth its points. Receipts not held for review are approved when stored.
+    #[default]
+    Approved,
+    /// The receipt was rejected on review, and is worth no points.
+    Rejected,
+}
+
+impl ReceiptStatus {
+    /// Gets the name of the status, as used in JSON.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Approved => "approved",
+            Self::Rejected => "rejected",
+        }
+    }
+}
+
+/// A decision staff made on a receipt held for review.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Review {
+    /// Whether the receipt was approved or rejected.
+    pub status: ReceiptStatus,
+
+    /// Free-form detail about the decision.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+
+    /// The subject of the principal who reviewed the receipt.
+    pub reviewer: String,
+
+    #[serde(with = "time::serde::rfc3339")]
+    pub reviewed_at: OffsetDateTime,
+}
+
+/// Reasons a review can be refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReviewError {
+    /// The receipt isn't pending review.
+    NotPending { status: ReceiptStatus },
+    /// The review couldn't be persisted, so it wasn't recorded.
+    Unavailable,
+}
+
+impl fmt::Display for ReviewError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotPending { status } => {
+                write!(f, "receipt is {}, not pending review", status.name())
+            }
+            Self::Unavailable => write!(f, "{UNAVAILABLE}"),
+        }
+    }
+}
+
+impl From<io::Error> for ReviewError {
+    /// Reports a review that couldn't be persisted. The cause is logged when the write fails.
+    fn from(_: io::Error) -> Self {
+        Self::Unavailable
+    }
+}
+
+impl ResponseError for ReviewError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::NotPending { .. } => StatusCode::CONFLICT,
+            Self::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ErrorResponse {
+            error: self.to_string(),
+        })
+    }
+}
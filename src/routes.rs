@@ -1,31 +1,222 @@
+mod audit;
+mod campaigns;
+mod encoding;
+mod error;
+mod events;
+mod export;
+mod import;
+mod jobs;
+mod ledger;
+mod metrics;
+mod payload;
 mod points;
 mod process;
+mod receipt;
+mod review;
+mod stats;
+mod users;
+mod webhooks;
+
+use actix_web::web;
+
+pub use encoding::Encoding;
+pub use error::{ErrorResponse, StorageError, CONFLICTING_TRANSACTION, UNAVAILABLE};
+pub use payload::{check_limits, parse_receipt, parse_receipt_value};
 
 // Re-export the routes
-pub use points::get_points;
+pub use audit::get_audit_log;
+pub use campaigns::{
+    create_campaign, delete_campaign, get_campaign, list_campaigns, replace_campaign,
+};
+pub use events::get_events;
+pub use export::export_receipts;
+pub use import::import_receipts;
+pub use jobs::get_job;
+pub use ledger::{adjust_points, check_ledger, get_ledger, redeem_points};
+pub use metrics::get_metrics;
+pub use points::{adjust_receipt, get_points, preview_points};
 pub use process::process_receipt;
+pub use receipt::get_receipt;
+pub use review::{approve_receipt, list_pending_receipts, reject_receipt};
+pub use stats::{get_date_stats, get_hour_stats, get_retailer_stats, get_stats, get_weekday_stats};
+pub use users::{create_user, get_user_points};
+pub use webhooks::{create_webhook, delete_webhook, list_webhook_deliveries, list_webhooks};
+
+/// Registers every route with the application.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_points)
+        .service(preview_points)
+        // registered before get_receipt, which would otherwise take "export" for a receipt ID
+        .service(export_receipts)
+        .service(get_receipt)
+        .service(process_receipt)
+        .service(import_receipts)
+        .service(create_user)
+        .service(get_user_points)
+        .service(get_metrics)
+        .service(create_campaign)
+        .service(list_campaigns)
+        .service(get_campaign)
+        .service(replace_campaign)
+        .service(delete_campaign)
+        .service(get_ledger)
+        .service(redeem_points)
+        .service(adjust_points)
+        .service(check_ledger)
+        .service(adjust_receipt)
+        .service(get_audit_log)
+        .service(list_pending_receipts)
+        .service(approve_receipt)
+        .service(reject_receipt)
+        .service(create_webhook)
+        .service(list_webhooks)
+        .service(delete_webhook)
+        .service(list_webhook_deliveries)
+        .service(get_events)
+        .service(get_job)
+        .service(get_stats)
+        .service(get_retailer_stats)
+        .service(get_date_stats)
+        .service(get_hour_stats)
+        .service(get_weekday_stats);
+}
 
 #[cfg(test)]
 mod tests {
-    use actix_web::{http::header::ContentType, test, web::Data, App};
+    use std::{
+        fmt,
+        future::poll_fn,
+        pin::Pin,
+        sync::{Arc, Mutex},
+    };
+
+    use actix_web::{
+        body::MessageBody,
+        dev::{ServiceFactory, ServiceRequest, ServiceResponse},
+        http::{
+            header::{self, ContentType},
+            StatusCode,
+        },
+        test,
+        web::Data,
+        App, HttpRequest, HttpResponse, HttpServer,
+    };
 
+    use sha2::{Digest, Sha256};
     use uuid::Uuid;
 
     use crate::{
-        db::Connection,
-        routes::{points::PointsResponse, process::ProcessReceiptResponse},
+        audit::{AuditAction, AuditEntry},
+        auth::{Authentication, Authenticator, Scope},
+        config::{
+            ApiKeyConfig, AuthConfig, Config, FraudConfig, JobConfig, LimitConfig, RateLimitConfig,
+            StoreConfig, WebhookConfig,
+        },
+        data::{PaymentMethod, Price, Receipt, User},
+        db::{Connection, LedgerCheck, ReceiptRecord},
+        fraud::RiskReason,
+        import::ImportReport,
+        jobs::{Job, JobQueue, JobStatus},
+        ledger::{ReasonCode, ReceiptAdjustment, TransactionKind},
+        points::{Rules, Ruleset, RulesetRegistry},
+        rate_limit::{RateLimit, RateLimiter},
+        retailers::{Retailer, RetailerRegistry},
+        review::ReceiptStatus,
+        routes::{
+            campaigns::{CampaignResponse, CreateCampaignResponse},
+            ledger::{AdjustRequest, LedgerResponse, RedeemRequest},
+            points::{AdjustReceiptRequest, PointsResponse, PreviewPointsResponse},
+            process::{ProcessJobResponse, ProcessReceiptResponse},
+            review::{ReviewReceiptResponse, ReviewRequest},
+            users::UserPointsResponse,
+            webhooks::CreateWebhookResponse,
+        },
+        stats::{Aggregate, Group, GroupKey, Weekday},
+        webhooks::{sign, DeliveryAttempt, Event, EventType, Subscription, SIGNATURE_HEADER},
         AppState,
     };
 
     use super::*;
 
+    /// Gets an authentication configuration granting clients presenting no credentials access to receipts and
+    /// members, so most tests needn't authenticate.
+    fn anonymous_auth_config() -> AuthConfig {
+        AuthConfig {
+            anonymous_scopes: vec![
+                Scope::ReceiptsRead,
+                Scope::ReceiptsWrite,
+                Scope::UsersRead,
+                Scope::UsersWrite,
+            ],
+            ..Default::default()
+        }
+    }
+
+    /// Constructs the authentication middleware allowing anonymous access to receipts and members.
+    fn authentication() -> Authentication {
+        Authentication::new(Authenticator::from_config(&anonymous_auth_config()).unwrap())
+    }
+
+    /// Constructs the authentication middleware allowing anonymous access to receipts and members, plus an "admin"
+    /// API key.
+    fn admin_authentication() -> Authentication {
+        let auth_config = AuthConfig {
+            api_keys: vec![ApiKeyConfig {
+                name: "admin".to_owned(),
+                key_hash: hex::encode(Sha256::digest("admin")),
+                scopes: vec![Scope::Admin],
+                user_id: None,
+            }],
+            ..anonymous_auth_config()
+        };
+        Authentication::new(Authenticator::from_config(&auth_config).unwrap())
+    }
+
+    /// A receipt from Target for a single Pepsi, worth 31 points.
+    const TARGET_RECEIPT: &str = r#"
+        {
+            "retailer": "Target",
+            "purchaseDate": "2022-01-02",
+            "purchaseTime": "13:13",
+            "total": "1.25",
+            "items": [
+                { "shortDescription": "Pepsi - 12-oz", "price": "1.25" }
+            ]
+        }
+    "#;
+
+    /// Constructs an app serving every route over the given database with the default configuration, authenticating
+    /// clients with the given middleware.
+    fn test_app(
+        connection: Connection,
+        authentication: Authentication,
+    ) -> App<
+        impl ServiceFactory<
+            ServiceRequest,
+            Config = (),
+            Response = ServiceResponse<impl MessageBody<Error: fmt::Debug>>,
+            Error = actix_web::Error,
+            InitError = (),
+        >,
+    > {
+        App::new()
+            .app_data(Data::new(AppState {
+                connection,
+                config: Config::default(),
+            }))
+            .wrap(authentication)
+            .configure(configure)
+    }
+
     /// Takes data for a receipt, sends it to the server, and gets the points total for that receipt.
     async fn run_full_trip(receipt_json: &'static [u8], expected_pts: u64) {
         let app = test::init_service(
             App::new()
                 .app_data(Data::new(AppState {
                     connection: Connection::new(),
+                    config: Config::default(),
                 }))
+                .wrap(authentication())
                 .service(process_receipt)
                 .service(get_points),
         )
@@ -37,7 +228,8 @@ mod tests {
             .insert_header(ContentType::json())
             .set_payload(receipt_json)
             .to_request();
-        let ProcessReceiptResponse { id } = test::call_and_read_body_json(&app, process_req).await;
+        let ProcessReceiptResponse { id, .. } =
+            test::call_and_read_body_json(&app, process_req).await;
 
         // get its points
         let points_req = test::TestRequest::get()
@@ -130,52 +322,1706 @@ mod tests {
     }
 
     #[actix_web::test]
-    async fn not_found() {
+    async fn example_2_with_quantity() {
+        run_full_trip(
+            br#"
+            {
+                "retailer": "M&M Corner Market",
+                "purchaseDate": "2022-03-20",
+                "purchaseTime": "14:33",
+                "items": [
+                {
+                    "shortDescription": "Gatorade",
+                    "price": "9.00",
+                    "quantity": 4,
+                    "unitPrice": "2.25",
+                    "upc": "052000338775",
+                    "category": "beverages"
+                }
+                ],
+                "total": "9.00"
+            }"#,
+            109,
+        )
+        .await;
+    }
+
+    #[actix_web::test]
+    async fn canonical_retailer() {
+        let retailers = RetailerRegistry::new(
+            vec![Retailer {
+                id: "target".to_owned(),
+                name: "Target".to_owned(),
+                aliases: vec![],
+            }],
+            0.85,
+        );
         let app = test::init_service(
             App::new()
                 .app_data(Data::new(AppState {
-                    connection: Connection::new(),
+                    connection: Connection::with_retailers(retailers),
+                    config: Config::default(),
                 }))
-                .service(get_points),
+                .wrap(authentication())
+                .service(process_receipt)
+                .service(get_points)
+                .service(get_receipt),
         )
         .await;
 
-        let id = Uuid::new_v4();
-        let req = test::TestRequest::get()
+        let process_req = test::TestRequest::post()
+            .uri("/receipts/process")
+            .insert_header(ContentType::json())
+            .set_payload(
+                r#"
+                {
+                    "retailer": "TARGET Store #1234",
+                    "purchaseDate": "2022-01-02",
+                    "purchaseTime": "13:13",
+                    "total": "1.25",
+                    "items": [
+                        { "shortDescription": "Pepsi - 12-oz", "price": "1.25" }
+                    ]
+                }
+            "#,
+            )
+            .to_request();
+        let ProcessReceiptResponse { id, .. } =
+            test::call_and_read_body_json(&app, process_req).await;
+
+        // same points as simple_receipt, which uses the canonical name
+        let points_req = test::TestRequest::get()
             .uri(&format!("/receipts/{id}/points"))
             .to_request();
-        let resp = test::call_service(&app, req).await;
-        assert!(resp.status().is_client_error());
+        let PointsResponse { points } = test::call_and_read_body_json(&app, points_req).await;
+        assert_eq!(points, 31);
+
+        let receipt_req = test::TestRequest::get()
+            .uri(&format!("/receipts/{id}"))
+            .to_request();
+        let record: ReceiptRecord = test::call_and_read_body_json(&app, receipt_req).await;
+        assert_eq!(record.retailer_id.as_deref(), Some("target"));
+        assert_eq!(record.receipt.retailer, "TARGET Store #1234");
     }
 
     #[actix_web::test]
-    async fn bad_receipt() {
-        let request_json: &[u8] = br#"
+    async fn preview_ruleset() {
+        let afternoons = Ruleset {
+            version: 2,
+            name: "afternoons".to_owned(),
+            effective_from: Some(time::macros::date!(2030 - 01 - 01)),
+            effective_until: None,
+            rules: Rules {
+                afternoon_start: time::macros::time!(13:00),
+                ..Rules::default()
+            },
+        };
+        let rulesets = RulesetRegistry::new(vec![Ruleset::standard(), afternoons]).unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(AppState {
+                    connection: Connection::new().with_rulesets(rulesets),
+                    config: Config::default(),
+                }))
+                .wrap(authentication())
+                .service(process_receipt)
+                .service(get_points)
+                .service(preview_points),
+        )
+        .await;
+
+        let process_req = test::TestRequest::post()
+            .uri("/receipts/process")
+            .insert_header(ContentType::json())
+            .set_payload(
+                r#"
+                {
+                    "retailer": "Target",
+                    "purchaseDate": "2022-01-02",
+                    "purchaseTime": "13:13",
+                    "total": "1.25",
+                    "items": [
+                        { "shortDescription": "Pepsi - 12-oz", "price": "1.25" }
+                    ]
+                }
+            "#,
+            )
+            .to_request();
+        let ProcessReceiptResponse { id, .. } =
+            test::call_and_read_body_json(&app, process_req).await;
+
+        // the receipt was purchased before the new ruleset took effect, so keeps the standard points
+        let preview_req = test::TestRequest::get()
+            .uri(&format!("/receipts/{id}/points/preview?ruleset=2"))
+            .to_request();
+        let preview: PreviewPointsResponse = test::call_and_read_body_json(&app, preview_req).await;
+        assert_eq!(
+            preview,
+            PreviewPointsResponse {
+                points: 41,
+                ruleset_version: 2,
+                ruleset_name: "afternoons".to_owned(),
+            }
+        );
+        let points_req = test::TestRequest::get()
+            .uri(&format!("/receipts/{id}/points"))
+            .to_request();
+        let PointsResponse { points } = test::call_and_read_body_json(&app, points_req).await;
+        assert_eq!(points, 31);
+
+        let preview_req = test::TestRequest::get()
+            .uri(&format!("/receipts/{id}/points/preview?ruleset=3"))
+            .to_request();
+        let resp = test::call_service(&app, preview_req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn campaign_bonus() {
+        let app = test::init_service(test_app(Connection::new(), admin_authentication())).await;
+        let campaign = r#"
             {
-                "retailer": "Target!",
-                "purchaseDate": "2022-01-02",
-                "purchaseTime": "13:13",
-                "total": "1.25",
-                "items": [
-                    { "shortDescription": "Pepsi - 12-oz", "price": "1.25" }
-                ]
+                "name": "Gatorade March",
+                "startsOn": "2024-03-01",
+                "endsOn": "2024-03-31",
+                "itemDescription": "gatorade",
+                "reward": { "type": "bonus", "points": 100 }
             }
         "#;
 
+        // only admins may manage campaigns
+        let create_req = test::TestRequest::post()
+            .uri("/campaigns")
+            .insert_header(ContentType::json())
+            .set_payload(campaign)
+            .to_request();
+        let resp = test::call_service(&app, create_req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let create_req = test::TestRequest::post()
+            .uri("/campaigns")
+            .insert_header(ContentType::json())
+            .insert_header(("X-Api-Key", "admin"))
+            .set_payload(campaign)
+            .to_request();
+        let CreateCampaignResponse { id: campaign_id } =
+            test::call_and_read_body_json(&app, create_req).await;
+
+        let process_req = test::TestRequest::post()
+            .uri("/receipts/process")
+            .insert_header(ContentType::json())
+            .set_payload(
+                r#"
+                {
+                    "retailer": "Walgreens",
+                    "purchaseDate": "2024-03-09",
+                    "purchaseTime": "13:13",
+                    "total": "2.25",
+                    "items": [
+                        { "shortDescription": "Gatorade", "price": "2.25" }
+                    ]
+                }
+            "#,
+            )
+            .to_request();
+        let ProcessReceiptResponse { id, .. } =
+            test::call_and_read_body_json(&app, process_req).await;
+        let points_req = test::TestRequest::get()
+            .uri(&format!("/receipts/{id}/points"))
+            .to_request();
+        let PointsResponse { points } = test::call_and_read_body_json(&app, points_req).await;
+        assert_eq!(points, 140);
+
+        let list_req = test::TestRequest::get()
+            .uri("/campaigns")
+            .insert_header(("X-Api-Key", "admin"))
+            .to_request();
+        let campaigns: Vec<CampaignResponse> = test::call_and_read_body_json(&app, list_req).await;
+        assert_eq!(campaigns.len(), 1);
+        assert_eq!(campaigns[0].id, campaign_id);
+        assert_eq!(campaigns[0].campaign.name, "Gatorade March");
+
+        let replace_req = test::TestRequest::put()
+            .uri(&format!("/campaigns/{campaign_id}"))
+            .insert_header(ContentType::json())
+            .insert_header(("X-Api-Key", "admin"))
+            .set_payload(campaign.replace("2024-03-01", "2024-04-01"))
+            .to_request();
+        let resp = test::call_service(&app, replace_req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let delete_req = test::TestRequest::delete()
+            .uri(&format!("/campaigns/{campaign_id}"))
+            .insert_header(("X-Api-Key", "admin"))
+            .to_request();
+        let resp = test::call_service(&app, delete_req).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let get_req = test::TestRequest::get()
+            .uri(&format!("/campaigns/{campaign_id}"))
+            .insert_header(("X-Api-Key", "admin"))
+            .to_request();
+        let resp = test::call_service(&app, get_req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // receipts keep the bonuses they were awarded
+        let points_req = test::TestRequest::get()
+            .uri(&format!("/receipts/{id}/points"))
+            .to_request();
+        let PointsResponse { points } = test::call_and_read_body_json(&app, points_req).await;
+        assert_eq!(points, 140);
+    }
+
+    #[actix_web::test]
+    async fn points_ledger() {
+        let connection = Connection::new();
+        let user_id = connection
+            .store_user(User {
+                name: "Pat Doe".to_owned(),
+                email: None,
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        let auth_config = AuthConfig {
+            anonymous_scopes: vec![],
+            api_keys: vec![
+                ApiKeyConfig {
+                    name: "pat".to_owned(),
+                    key_hash: hex::encode(Sha256::digest("pat")),
+                    scopes: vec![Scope::ReceiptsWrite],
+                    user_id: Some(user_id),
+                },
+                ApiKeyConfig {
+                    name: "admin".to_owned(),
+                    key_hash: hex::encode(Sha256::digest("admin")),
+                    scopes: vec![Scope::Admin],
+                    user_id: None,
+                },
+            ],
+            ..Default::default()
+        };
+        let app = test::init_service(test_app(
+            connection,
+            Authentication::new(Authenticator::from_config(&auth_config).unwrap()),
+        ))
+        .await;
+
+        // the member earns 31 points
+        let process_req = test::TestRequest::post()
+            .uri("/receipts/process")
+            .insert_header(ContentType::json())
+            .insert_header(("X-Api-Key", "pat"))
+            .set_payload(TARGET_RECEIPT)
+            .to_request();
+        let ProcessReceiptResponse { id, .. } =
+            test::call_and_read_body_json(&app, process_req).await;
+
+        let redeem = |points| {
+            test::TestRequest::post()
+                .uri(&format!("/users/{user_id}/redemptions"))
+                .insert_header(("X-Api-Key", "pat"))
+                .set_json(RedeemRequest {
+                    points,
+                    description: "Coffee".to_owned(),
+                })
+                .to_request()
+        };
+        let resp = test::call_service(&app, redeem(20)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(&app, redeem(20)).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        // only admins may adjust points
+        let adjust = |key| {
+            test::TestRequest::post()
+                .uri(&format!("/users/{user_id}/adjustments"))
+                .insert_header(("X-Api-Key", key))
+                .set_json(AdjustRequest {
+                    points: 5,
+                    reason: "goodwill".to_owned(),
+                })
+                .to_request()
+        };
+        let resp = test::call_service(&app, adjust("pat")).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = test::call_service(&app, adjust("admin")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ledger_req = test::TestRequest::get()
+            .uri(&format!("/users/{user_id}/ledger"))
+            .insert_header(("X-Api-Key", "pat"))
+            .to_request();
+        let ledger: LedgerResponse = test::call_and_read_body_json(&app, ledger_req).await;
+        assert_eq!(ledger.balance, 16);
+        assert_eq!(
+            ledger
+                .transactions
+                .iter()
+                .map(|transaction| transaction.points)
+                .collect::<Vec<_>>(),
+            vec![31, -20, 5]
+        );
+        assert!(matches!(
+            ledger.transactions[0].kind,
+            TransactionKind::Earn { receipt_id, .. } if receipt_id == id
+        ));
+
+        let check_req = test::TestRequest::get()
+            .uri("/ledger/check")
+            .insert_header(("X-Api-Key", "admin"))
+            .to_request();
+        let check: LedgerCheck = test::call_and_read_body_json(&app, check_req).await;
+        assert!(check.is_consistent());
+        assert_eq!(check.earns_checked, 1);
+    }
+
+    #[actix_web::test]
+    async fn receipt_adjustments() {
+        let app = test::init_service(test_app(Connection::new(), admin_authentication())).await;
+        let process_req = test::TestRequest::post()
+            .uri("/receipts/process")
+            .insert_header(ContentType::json())
+            .set_payload(TARGET_RECEIPT)
+            .to_request();
+        let ProcessReceiptResponse { id, .. } =
+            test::call_and_read_body_json(&app, process_req).await;
+
+        // only admins may adjust points
+        let adjust = |key, points, reason| {
+            test::TestRequest::post()
+                .uri(&format!("/receipts/{id}/adjustments"))
+                .insert_header(("X-Api-Key", key))
+                .set_json(AdjustReceiptRequest {
+                    points,
+                    reason,
+                    note: None,
+                })
+                .to_request()
+        };
+        let resp = test::call_service(&app, adjust("guest", 10, ReasonCode::Goodwill)).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = test::call_service(&app, adjust("admin", 0, ReasonCode::Goodwill)).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let adjustment: ReceiptAdjustment =
+            test::call_and_read_body_json(&app, adjust("admin", 10, ReasonCode::Goodwill)).await;
+        assert_eq!(adjustment.actor, "admin");
+
+        let points = || {
+            test::TestRequest::get()
+                .uri(&format!("/receipts/{id}/points"))
+                .to_request()
+        };
+        let PointsResponse { points: adjusted } =
+            test::call_and_read_body_json(&app, points()).await;
+        assert_eq!(adjusted, 41);
+
+        // revoking points never takes a receipt below zero
+        let resp = test::call_service(&app, adjust("admin", -100, ReasonCode::Fraud)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let PointsResponse { points: revoked } =
+            test::call_and_read_body_json(&app, points()).await;
+        assert_eq!(revoked, 0);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri(&format!("/receipts/{}/adjustments", Uuid::new_v4()))
+                .insert_header(("X-Api-Key", "admin"))
+                .set_json(AdjustReceiptRequest {
+                    points: 10,
+                    reason: ReasonCode::Goodwill,
+                    note: None,
+                })
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // every adjustment is audited
+        let audit_req = test::TestRequest::get()
+            .uri("/audit")
+            .insert_header(("X-Api-Key", "admin"))
+            .to_request();
+        let audit_log: Vec<AuditEntry> = test::call_and_read_body_json(&app, audit_req).await;
+        assert_eq!(audit_log.len(), 2);
+        assert!(audit_log.iter().all(|entry| entry.actor == "admin"));
+        assert_eq!(
+            audit_log[0].action,
+            AuditAction::AdjustReceipt {
+                receipt_id: id,
+                adjustment_id: adjustment.id,
+                points: 10,
+                reason: ReasonCode::Goodwill,
+            }
+        );
+        assert!(matches!(
+            audit_log[1].action,
+            AuditAction::AdjustReceipt {
+                points: -100,
+                reason: ReasonCode::Fraud,
+                ..
+            }
+        ));
+    }
+
+    #[actix_web::test]
+    async fn review_queue() {
+        let fraud = FraudConfig {
+            hold_threshold: Some(20),
+            ..Default::default()
+        };
+        let app = test::init_service(test_app(
+            Connection::new().with_fraud(&fraud),
+            admin_authentication(),
+        ))
+        .await;
+
+        // the total is a multiple of $0.25, so the receipt is held for review
+        let process_req = test::TestRequest::post()
+            .uri("/receipts/process")
+            .insert_header(ContentType::json())
+            .set_payload(TARGET_RECEIPT)
+            .to_request();
+        let ProcessReceiptResponse { id, .. } =
+            test::call_and_read_body_json(&app, process_req).await;
+
+        let points_req = || {
+            test::TestRequest::get()
+                .uri(&format!("/receipts/{id}/points"))
+                .to_request()
+        };
+        let resp = test::call_service(&app, points_req()).await;
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        let PointsResponse { points } = test::read_body_json(resp).await;
+        assert_eq!(points, 0);
+
+        let pending_req = test::TestRequest::get()
+            .uri("/review/pending")
+            .insert_header(("X-Api-Key", "admin"))
+            .to_request();
+        let pending: Vec<ReviewReceiptResponse> =
+            test::call_and_read_body_json(&app, pending_req).await;
+        assert_eq!(
+            pending.iter().map(|receipt| receipt.id).collect::<Vec<_>>(),
+            vec![id]
+        );
+        assert_eq!(
+            pending[0].record.risk.reasons,
+            vec![RiskReason::QuarterTotal]
+        );
+
+        // only admins may review receipts, and only once
+        let review = |decision, key| {
+            test::TestRequest::post()
+                .uri(&format!("/receipts/{id}/{decision}"))
+                .insert_header(("X-Api-Key", key))
+                .set_json(ReviewRequest {
+                    note: Some("duplicate of a paper receipt".to_owned()),
+                })
+                .to_request()
+        };
+        let resp = test::call_service(&app, review("reject", "guest")).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let rejected: ReviewReceiptResponse =
+            test::call_and_read_body_json(&app, review("reject", "admin")).await;
+        assert_eq!(rejected.record.status, ReceiptStatus::Rejected);
+        let review_record = rejected.record.review.unwrap();
+        assert_eq!(review_record.reviewer, "admin");
+        assert_eq!(
+            review_record.note.as_deref(),
+            Some("duplicate of a paper receipt")
+        );
+        let resp = test::call_service(&app, review("approve", "admin")).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        let resp = test::call_service(&app, points_req()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let PointsResponse { points } = test::read_body_json(resp).await;
+        assert_eq!(points, 0);
+
+        // the note is optional, so a decision may be sent without a body
+        let process_req = test::TestRequest::post()
+            .uri("/receipts/process")
+            .insert_header(ContentType::json())
+            .set_payload(
+                r#"
+                {
+                    "retailer": "Target",
+                    "purchaseDate": "2022-01-02",
+                    "purchaseTime": "13:13",
+                    "total": "2.50",
+                    "items": [
+                        { "shortDescription": "Pepsi - 12-oz", "price": "2.50" }
+                    ]
+                }
+            "#,
+            )
+            .to_request();
+        let ProcessReceiptResponse { id, .. } =
+            test::call_and_read_body_json(&app, process_req).await;
+
+        // but a body that is present must be a valid request
+        for body in [r#"{ "note": 5 }"#, r#"{ "note": "dup"#] {
+            let approve_req = test::TestRequest::post()
+                .uri(&format!("/receipts/{id}/approve"))
+                .insert_header(ContentType::json())
+                .insert_header(("X-Api-Key", "admin"))
+                .set_payload(body)
+                .to_request();
+            let resp = test::call_service(&app, approve_req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{body}");
+        }
+
+        let approve_req = test::TestRequest::post()
+            .uri(&format!("/receipts/{id}/approve"))
+            .insert_header(("X-Api-Key", "admin"))
+            .to_request();
+        let approved: ReviewReceiptResponse =
+            test::call_and_read_body_json(&app, approve_req).await;
+        assert_eq!(approved.record.status, ReceiptStatus::Approved);
+        assert_eq!(approved.record.review.unwrap().note, None);
+    }
+
+    #[actix_web::test]
+    async fn webhook_notifications() {
+        // a stand-in for a subscriber, failing the first attempt to deliver each event
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let subscriber = {
+            let requests = Arc::clone(&requests);
+            HttpServer::new(move || {
+                let requests = Arc::clone(&requests);
+                App::new().route(
+                    "/hook",
+                    web::post().to(move |req: HttpRequest, body: web::Bytes| {
+                        let requests = Arc::clone(&requests);
+                        async move {
+                            let signature = req
+                                .headers()
+                                .get(SIGNATURE_HEADER)
+                                .unwrap()
+                                .to_str()
+                                .unwrap();
+                            let event: Event = serde_json::from_slice(&body).unwrap();
+                            let mut requests = requests.lock().unwrap();
+                            let retry = requests.iter().any(|(id, _)| *id == event.id);
+                            requests.push((event.id, signature == sign("s3cret", &body)));
+                            if retry {
+                                HttpResponse::Ok().finish()
+                            } else {
+                                HttpResponse::InternalServerError().finish()
+                            }
+                        }
+                    }),
+                )
+            })
+            .workers(1)
+            .bind(("127.0.0.1", 0))
+            .unwrap()
+        };
+        let address = subscriber.addrs()[0];
+        let subscriber = subscriber.run();
+        let subscriber_handle = subscriber.handle();
+        actix_web::rt::spawn(subscriber);
+
+        let webhooks = WebhookConfig {
+            initial_backoff_millis: 10,
+            ..Default::default()
+        };
+        let connection = Connection::new().with_webhooks(&webhooks);
+        let app = test::init_service(test_app(connection.clone(), admin_authentication())).await;
+        let create_req = test::TestRequest::post()
+            .uri("/webhooks")
+            .insert_header(("X-Api-Key", "admin"))
+            .set_json(Subscription {
+                url: format!("http://{address}/hook"),
+                secret: "s3cret".to_owned(),
+                events: vec![EventType::ReceiptProcessed, EventType::PointsAwarded],
+            })
+            .to_request();
+        let CreateWebhookResponse { id: webhook_id } =
+            test::call_and_read_body_json(&app, create_req).await;
+
+        let process_req = test::TestRequest::post()
+            .uri("/receipts/process")
+            .insert_header(ContentType::json())
+            .set_payload(TARGET_RECEIPT)
+            .to_request();
+        let resp = test::call_service(&app, process_req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // draining waits for both events to be delivered, on their second attempt
+        actix_web::rt::task::spawn_blocking(move || connection.drain_webhooks())
+            .await
+            .unwrap();
+        let deliveries_req = test::TestRequest::get()
+            .uri("/webhooks/deliveries")
+            .insert_header(("X-Api-Key", "admin"))
+            .to_request();
+        let deliveries: Vec<DeliveryAttempt> =
+            test::call_and_read_body_json(&app, deliveries_req).await;
+        let mut attempts: Vec<_> = deliveries
+            .iter()
+            .map(|attempt| {
+                assert_eq!(attempt.subscription_id, webhook_id);
+                (
+                    attempt.event_type.name(),
+                    attempt.attempt,
+                    attempt.delivered,
+                    attempt.response_status,
+                )
+            })
+            .collect();
+        attempts.sort();
+        assert_eq!(
+            attempts,
+            vec![
+                ("points.awarded", 1, false, Some(500)),
+                ("points.awarded", 2, true, Some(200)),
+                ("receipt.processed", 1, false, Some(500)),
+                ("receipt.processed", 2, true, Some(200)),
+            ]
+        );
+        assert!(requests.lock().unwrap().iter().all(|&(_, signed)| signed));
+
+        let delete_req = test::TestRequest::delete()
+            .uri(&format!("/webhooks/{webhook_id}"))
+            .insert_header(("X-Api-Key", "admin"))
+            .to_request();
+        let resp = test::call_service(&app, delete_req).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        subscriber_handle.stop(true).await;
+    }
+
+    #[actix_web::test]
+    async fn event_stream() {
+        let app = test::init_service(test_app(Connection::new(), authentication())).await;
+        let process = || async {
+            let process_req = test::TestRequest::post()
+                .uri("/receipts/process")
+                .insert_header(ContentType::json())
+                .set_payload(TARGET_RECEIPT)
+                .to_request();
+            let ProcessReceiptResponse { id, .. } =
+                test::call_and_read_body_json(&app, process_req).await;
+            id
+        };
+        process().await;
+        let missed_id = process().await;
+
+        // a client resuming after the first event gets the second
+        let events_req = test::TestRequest::get()
+            .uri("/events")
+            .insert_header(("Last-Event-ID", "1"))
+            .to_request();
+        let resp = test::call_service(&app, events_req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/event-stream"
+        );
+        let mut body = resp.into_body();
+        let chunk = poll_fn(|cx| Pin::new(&mut body).poll_next(cx))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            chunk,
+            format!(
+                "id: 2\nevent: receipt\ndata: \
+                 {{\"receiptId\":\"{missed_id}\",\"retailer\":\"Target\",\"points\":31}}\n\n"
+            )
+        );
+
+        // and then new events as they're published
+        let new_id = process().await;
+        let chunk = poll_fn(|cx| Pin::new(&mut body).poll_next(cx))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(std::str::from_utf8(&chunk).unwrap().starts_with(&format!(
+            "id: 3\nevent: receipt\ndata: {{\"receiptId\":\"{new_id}\""
+        )));
+
+        // a client resuming from an event that was never published, as after a restart, is told to reset
+        let events_req = test::TestRequest::get()
+            .uri("/events")
+            .insert_header(("Last-Event-ID", "7"))
+            .to_request();
+        let mut body = test::call_service(&app, events_req).await.into_body();
+        let chunk = poll_fn(|cx| Pin::new(&mut body).poll_next(cx))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(chunk, "id: 3\nevent: reset\ndata: {}\n\n");
+    }
+
+    #[actix_web::test]
+    async fn user_points() {
+        let connection = Connection::new();
+        let user = User {
+            name: "Pat Doe".to_owned(),
+            email: Some("pat@example.com".to_owned()),
+        };
+        let user_id = connection.store_user(user.clone()).await.unwrap().unwrap();
+        let other_user_id = connection.store_user(user).await.unwrap().unwrap();
+
+        // one key for the member, and one for a member that doesn't exist
+        let member_key = |name: &str, user_id| ApiKeyConfig {
+            name: name.to_owned(),
+            key_hash: hex::encode(Sha256::digest(name)),
+            scopes: vec![Scope::ReceiptsWrite],
+            user_id: Some(user_id),
+        };
+        let auth_config = AuthConfig {
+            anonymous_scopes: vec![],
+            api_keys: vec![
+                member_key("pat", user_id),
+                member_key("ghost", Uuid::new_v4()),
+            ],
+            ..Default::default()
+        };
         let app = test::init_service(
             App::new()
                 .app_data(Data::new(AppState {
-                    connection: Connection::new(),
+                    connection: connection.clone(),
+                    config: Config::default(),
                 }))
-                .service(process_receipt),
+                .wrap(Authentication::new(
+                    Authenticator::from_config(&auth_config).unwrap(),
+                ))
+                .service(process_receipt)
+                .service(get_user_points),
         )
         .await;
-        let req = test::TestRequest::post()
-            .uri("/receipts/process")
-            .append_header(ContentType::json())
-            .set_payload(request_json)
+
+        // one receipt in January worth 31 points and one in March worth 109 points
+        for receipt_json in [
+            r#"
+            {
+                "retailer": "Target",
+                "purchaseDate": "2022-01-02",
+                "purchaseTime": "13:13",
+                "total": "1.25",
+                "items": [
+                    { "shortDescription": "Pepsi - 12-oz", "price": "1.25" }
+                ]
+            }"#,
+            r#"
+            {
+                "retailer": "M&M Corner Market",
+                "purchaseDate": "2022-03-20",
+                "purchaseTime": "14:33",
+                "items": [
+                    { "shortDescription": "Gatorade", "price": "9.00", "quantity": 4, "unitPrice": "2.25" }
+                ],
+                "total": "9.00"
+            }"#,
+        ] {
+            let process_req = test::TestRequest::post()
+                .uri("/receipts/process")
+                .insert_header(ContentType::json())
+                .insert_header(("X-Api-Key", "pat"))
+                .set_payload(receipt_json)
+                .to_request();
+            let resp = test::call_service(&app, process_req).await;
+            assert!(resp.status().is_success());
+        }
+
+        let points_req = test::TestRequest::get()
+            .uri(&format!(
+                "/users/{user_id}/points?from=2022-03-01&to=2022-03-31"
+            ))
+            .insert_header(("X-Api-Key", "pat"))
             .to_request();
-        let resp = test::call_service(&app, req).await;
-        assert!(resp.status().is_client_error());
+        let points: UserPointsResponse = test::call_and_read_body_json(&app, points_req).await;
+        assert_eq!(
+            points,
+            UserPointsResponse {
+                lifetime: 140,
+                period: 109,
+            }
+        );
+
+        // members can't see each other's points
+        let points_req = test::TestRequest::get()
+            .uri(&format!("/users/{other_user_id}/points"))
+            .insert_header(("X-Api-Key", "pat"))
+            .to_request();
+        let resp = test::call_service(&app, points_req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        // receipts can't be submitted on behalf of members that don't exist, even if they're valid
+        let receipt_json = r#"
+            {
+                "retailer": "Walgreens",
+                "purchaseDate": "2022-01-02",
+                "purchaseTime": "08:13",
+                "total": "2.65",
+                "items": [
+                    { "shortDescription": "Pepsi - 12-oz", "price": "1.25" },
+                    { "shortDescription": "Dasani", "price": "1.40" }
+                ]
+            }"#;
+        let process_req = test::TestRequest::post()
+            .uri("/receipts/process")
+            .insert_header(ContentType::json())
+            .insert_header(("X-Api-Key", "ghost"))
+            .set_payload(receipt_json)
+            .to_request();
+        let resp = test::call_service(&app, process_req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let body: ErrorResponse = test::read_body_json(resp).await;
+        assert_eq!(body.error, "unknown user");
+
+        // anonymous access is disabled
+        let process_req = test::TestRequest::post()
+            .uri("/receipts/process")
+            .insert_header(ContentType::json())
+            .set_payload(receipt_json)
+            .to_request();
+        let resp = test::call_service(&app, process_req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(connection.receipt_count().await, 2);
+    }
+
+    #[actix_web::test]
+    async fn receipt_owners() {
+        let connection = Connection::new();
+        let mut api_keys = Vec::new();
+        for name in ["pat", "sam"] {
+            let user_id = connection
+                .store_user(User {
+                    name: name.to_owned(),
+                    email: None,
+                })
+                .await
+                .unwrap()
+                .unwrap();
+            api_keys.push(ApiKeyConfig {
+                name: name.to_owned(),
+                key_hash: hex::encode(Sha256::digest(name)),
+                scopes: vec![Scope::ReceiptsRead, Scope::ReceiptsWrite],
+                user_id: Some(user_id),
+            });
+        }
+        let auth_config = AuthConfig {
+            api_keys,
+            ..anonymous_auth_config()
+        };
+        let app = test::init_service(test_app(
+            connection,
+            Authentication::new(Authenticator::from_config(&auth_config).unwrap()),
+        ))
+        .await;
+
+        // a member submits a receipt
+        let req = test::TestRequest::post()
+            .uri("/receipts/process")
+            .insert_header(ContentType::json())
+            .insert_header(("X-Api-Key", "pat"))
+            .set_payload(TARGET_RECEIPT)
+            .to_request();
+        let ProcessReceiptResponse { id, .. } = test::call_and_read_body_json(&app, req).await;
+
+        // the member and clients that may read every member can read the receipt and its points, but other members
+        // can't
+        for uri in [
+            format!("/receipts/{id}"),
+            format!("/receipts/{id}/points"),
+            format!("/receipts/{id}/points/preview?ruleset=1"),
+        ] {
+            for (key, status) in [
+                (Some("pat"), StatusCode::OK),
+                (None, StatusCode::OK),
+                (Some("sam"), StatusCode::FORBIDDEN),
+            ] {
+                let mut req = test::TestRequest::get().uri(&uri);
+                if let Some(key) = key {
+                    req = req.insert_header(("X-Api-Key", key));
+                }
+                let resp = test::call_service(&app, req.to_request()).await;
+                assert_eq!(resp.status(), status, "{uri} {key:?}");
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn receipt_metadata() {
+        let receipt_json: &[u8] = br#"
+            {
+                "retailer": "Target",
+                "purchaseDate": "2022-01-02",
+                "purchaseTime": "13:13",
+                "total": "1.35",
+                "subtotal": "1.25",
+                "taxes": [
+                    { "description": "State sales tax", "amount": "0.10" }
+                ],
+                "store": { "storeId": "1234", "address": "100 Main St, Springfield" },
+                "registerNumber": "7",
+                "transactionNumber": "0042",
+                "paymentMethod": "credit",
+                "items": [
+                    { "shortDescription": "Pepsi - 12-oz", "price": "1.25" }
+                ]
+            }
+        "#;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(AppState {
+                    connection: Connection::new(),
+                    config: Config::default(),
+                }))
+                .wrap(authentication())
+                .service(process_receipt)
+                .service(get_receipt),
+        )
+        .await;
+
+        let process_req = || {
+            test::TestRequest::post()
+                .uri("/receipts/process")
+                .insert_header(ContentType::json())
+                .set_payload(receipt_json)
+                .to_request()
+        };
+        let ProcessReceiptResponse { id, duplicate } =
+            test::call_and_read_body_json(&app, process_req()).await;
+        assert!(!duplicate);
+
+        // the same transaction submitted again maps to the same receipt, and is marked as a duplicate
+        let ProcessReceiptResponse {
+            id: dup_id,
+            duplicate,
+        } = test::call_and_read_body_json(&app, process_req()).await;
+        assert_eq!(id, dup_id);
+        assert!(duplicate);
+
+        let receipt_req = test::TestRequest::get()
+            .uri(&format!("/receipts/{id}"))
+            .to_request();
+        let receipt: Receipt = test::call_and_read_body_json(&app, receipt_req).await;
+        assert_eq!(receipt.payment_method, Some(PaymentMethod::Credit));
+        assert_eq!(receipt.transaction_number.as_deref(), Some("0042"));
+        assert_eq!(
+            receipt.store.and_then(|s| s.store_id).as_deref(),
+            Some("1234")
+        );
+        assert_eq!(receipt.taxes.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn conflicting_transactions() {
+        let connection = Connection::new();
+        let mut api_keys = Vec::new();
+        for name in ["pat", "sam"] {
+            let user_id = connection
+                .store_user(User {
+                    name: name.to_owned(),
+                    email: None,
+                })
+                .await
+                .unwrap()
+                .unwrap();
+            api_keys.push(ApiKeyConfig {
+                name: name.to_owned(),
+                key_hash: hex::encode(Sha256::digest(name)),
+                scopes: vec![Scope::ReceiptsWrite],
+                user_id: Some(user_id),
+            });
+        }
+        let auth_config = AuthConfig {
+            anonymous_scopes: vec![],
+            api_keys,
+            ..Default::default()
+        };
+        let app = test::init_service(test_app(
+            connection.clone(),
+            Authentication::new(Authenticator::from_config(&auth_config).unwrap()),
+        ))
+        .await;
+
+        let receipt_json = r#"
+            {
+                "retailer": "Target",
+                "purchaseDate": "2022-01-02",
+                "purchaseTime": "13:13",
+                "total": "1.25",
+                "store": { "storeId": "1234" },
+                "transactionNumber": "0042",
+                "items": [
+                    { "shortDescription": "Pepsi - 12-oz", "price": "1.25" }
+                ]
+            }
+        "#;
+        let process_req = |key: &'static str| {
+            test::TestRequest::post()
+                .uri("/receipts/process")
+                .insert_header(ContentType::json())
+                .insert_header(("X-Api-Key", key))
+                .set_payload(receipt_json)
+                .to_request()
+        };
+        let ProcessReceiptResponse { id, .. } =
+            test::call_and_read_body_json(&app, process_req("pat")).await;
+
+        // another member can't claim the same transaction, and isn't told the existing receipt's ID
+        let resp = test::call_service(&app, process_req("sam")).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        let body: ErrorResponse = test::read_body_json(resp).await;
+        assert_eq!(body.error, CONFLICTING_TRANSACTION);
+
+        // the member submitting it again gets their own receipt back
+        let ProcessReceiptResponse {
+            id: dup_id,
+            duplicate,
+        } = test::call_and_read_body_json(&app, process_req("pat")).await;
+        assert_eq!(dup_id, id);
+        assert!(duplicate);
+        assert_eq!(connection.receipt_count().await, 1);
+    }
+
+    #[actix_web::test]
+    async fn unbalanced_taxes() {
+        let request_json: &[u8] = br#"
+            {
+                "retailer": "Target",
+                "purchaseDate": "2022-01-02",
+                "purchaseTime": "13:13",
+                "total": "1.25",
+                "subtotal": "1.25",
+                "taxes": [
+                    { "description": "State sales tax", "amount": "0.10" }
+                ],
+                "items": [
+                    { "shortDescription": "Pepsi - 12-oz", "price": "1.25" }
+                ]
+            }
+        "#;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(AppState {
+                    connection: Connection::new(),
+                    config: Config::default(),
+                }))
+                .wrap(authentication())
+                .service(process_receipt),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/receipts/process")
+            .append_header(ContentType::json())
+            .set_payload(request_json)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_client_error());
+    }
+
+    #[actix_web::test]
+    async fn oversized_body() {
+        let mut config = Config::default();
+        config.receipts.max_body_bytes = 16;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(AppState {
+                    connection: Connection::new(),
+                    config,
+                }))
+                .wrap(authentication())
+                .service(process_receipt),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/receipts/process")
+            .append_header(ContentType::json())
+            .set_payload(r#"{ "retailer": "Target", "items": [] }"#)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[actix_web::test]
+    async fn unsupported_media_type() {
+        let app = test::init_service(test_app(Connection::new(), authentication())).await;
+
+        // bodies must be declared as JSON or a binary encoding, as they were when receipts were read with `web::Json`
+        for content_type in [
+            Some("text/plain"),
+            Some("application/x-www-form-urlencoded"),
+            None,
+        ] {
+            let mut req = test::TestRequest::post()
+                .uri("/receipts/process")
+                .set_payload(TARGET_RECEIPT);
+            if let Some(content_type) = content_type {
+                req = req.insert_header((header::CONTENT_TYPE, content_type));
+            }
+            let resp = test::call_service(&app, req.to_request()).await;
+            assert_eq!(
+                resp.status(),
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "{content_type:?}"
+            );
+            let body: ErrorResponse = test::read_body_json(resp).await;
+            assert_eq!(body.error, "request body must be JSON, MessagePack or CBOR");
+        }
+
+        let req = test::TestRequest::post()
+            .uri("/receipts/process")
+            .insert_header((header::CONTENT_TYPE, "application/json; charset=utf-8"))
+            .set_payload(TARGET_RECEIPT)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn rate_limited() {
+        let rate_limits = RateLimitConfig {
+            routes: [(
+                "GET /receipts/{id}/points".to_owned(),
+                LimitConfig {
+                    capacity: 1,
+                    refill_per_second: 0.1,
+                },
+            )]
+            .into(),
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(AppState {
+                    connection: Connection::new(),
+                    config: Config::default(),
+                }))
+                .wrap(RateLimit::new(Arc::new(RateLimiter::from_config(
+                    &rate_limits,
+                ))))
+                .wrap(authentication())
+                .service(get_points),
+        )
+        .await;
+
+        let id = Uuid::new_v4();
+        let points_req = || {
+            test::TestRequest::get()
+                .uri(&format!("/receipts/{id}/points"))
+                .peer_addr("10.0.0.1:4321".parse().unwrap())
+                .to_request()
+        };
+        let resp = test::call_service(&app, points_req()).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(resp.headers().get("x-ratelimit-remaining").unwrap(), "0");
+
+        let resp = test::call_service(&app, points_req()).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers().get(header::RETRY_AFTER).unwrap(), "10");
+    }
+
+    #[actix_web::test]
+    async fn evicted_receipt() {
+        let connection = Connection::new().with_eviction(StoreConfig {
+            capacity: Some(1),
+            ..Default::default()
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(AppState {
+                    connection,
+                    config: Config::default(),
+                }))
+                .wrap(authentication())
+                .service(process_receipt)
+                .service(get_points),
+        )
+        .await;
+
+        let mut ids = Vec::new();
+        for _ in 0..2 {
+            let process_req = test::TestRequest::post()
+                .uri("/receipts/process")
+                .insert_header(ContentType::json())
+                .set_payload(
+                    r#"
+                    {
+                        "retailer": "Target",
+                        "purchaseDate": "2022-01-02",
+                        "purchaseTime": "13:13",
+                        "total": "1.25",
+                        "items": [
+                            { "shortDescription": "Pepsi - 12-oz", "price": "1.25" }
+                        ]
+                    }
+                "#,
+                )
+                .to_request();
+            let ProcessReceiptResponse { id, .. } =
+                test::call_and_read_body_json(&app, process_req).await;
+            ids.push(id);
+        }
+
+        // the first receipt was evicted to make room for the second
+        let points_req = test::TestRequest::get()
+            .uri(&format!("/receipts/{}/points", ids[0]))
+            .to_request();
+        let resp = test::call_service(&app, points_req).await;
+        assert_eq!(resp.status(), StatusCode::GONE);
+
+        let points_req = test::TestRequest::get()
+            .uri(&format!("/receipts/{}/points", ids[1]))
+            .to_request();
+        let resp = test::call_service(&app, points_req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn not_found() {
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(AppState {
+                    connection: Connection::new(),
+                    config: Config::default(),
+                }))
+                .wrap(authentication())
+                .service(get_points),
+        )
+        .await;
+
+        let id = Uuid::new_v4();
+        let req = test::TestRequest::get()
+            .uri(&format!("/receipts/{id}/points"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_client_error());
+    }
+
+    #[actix_web::test]
+    async fn bad_receipt() {
+        let request_json: &[u8] = br#"
+            {
+                "retailer": "Target!",
+                "purchaseDate": "2022-01-02",
+                "purchaseTime": "13:13",
+                "total": "1.25",
+                "items": [
+                    { "shortDescription": "Pepsi - 12-oz", "price": "1.25" }
+                ]
+            }
+        "#;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(AppState {
+                    connection: Connection::new(),
+                    config: Config::default(),
+                }))
+                .wrap(authentication())
+                .service(process_receipt),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/receipts/process")
+            .append_header(ContentType::json())
+            .set_payload(request_json)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_client_error());
+    }
+
+    #[actix_web::test]
+    async fn binary_encodings() {
+        let app = test::init_service(test_app(Connection::new(), authentication())).await;
+        let receipt: Receipt = serde_json::from_str(TARGET_RECEIPT).unwrap();
+        // send MessagePack, receive CBOR
+        let req = test::TestRequest::post()
+            .uri("/receipts/process")
+            .insert_header((header::CONTENT_TYPE, "application/msgpack"))
+            .insert_header((header::ACCEPT, "application/cbor"))
+            .set_payload(rmp_serde::to_vec_named(&receipt).unwrap())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/cbor"
+        );
+        let body = test::read_body(resp).await;
+        let ProcessReceiptResponse { id, .. } = ciborium::from_reader(body.as_ref()).unwrap();
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/receipts/{id}/points"))
+            .insert_header((header::ACCEPT, "application/msgpack"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/msgpack"
+        );
+        let body = test::read_body(resp).await;
+        let response: PointsResponse = rmp_serde::from_slice(&body).unwrap();
+        assert_eq!(response.points, 31);
+
+        // unsupported media types fall back to JSON
+        let req = test::TestRequest::get()
+            .uri(&format!("/receipts/{id}/points"))
+            .insert_header((header::ACCEPT, "text/html"))
+            .to_request();
+        let PointsResponse { points } = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(points, 31);
+
+        let mut cbor = Vec::new();
+        ciborium::into_writer(&receipt, &mut cbor).unwrap();
+        let req = test::TestRequest::post()
+            .uri("/receipts/process")
+            .insert_header((header::CONTENT_TYPE, "application/cbor"))
+            .set_payload(cbor)
+            .to_request();
+        let ProcessReceiptResponse { id: second_id, .. } =
+            test::call_and_read_body_json(&app, req).await;
+        assert_ne!(second_id, id);
+
+        // bodies that aren't in the encoding they claim are malformed, and errors are always JSON
+        let req = test::TestRequest::post()
+            .uri("/receipts/process")
+            .insert_header((header::CONTENT_TYPE, "application/msgpack"))
+            .insert_header((header::ACCEPT, "application/msgpack"))
+            .set_payload(serde_json::to_vec(&receipt).unwrap())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+    }
+
+    #[actix_web::test]
+    async fn async_processing() {
+        let connection = Connection::new();
+        let jobs = JobQueue::start(
+            connection.clone(),
+            Config::default().receipts,
+            &JobConfig::default(),
+        );
+        let app =
+            test::init_service(test_app(connection, authentication()).app_data(Data::new(jobs)))
+                .await;
+
+        // submit a receipt and a malformed one
+        let submit = |payload: &'static str| {
+            test::TestRequest::post()
+                .uri("/receipts/process?async=true")
+                .insert_header(ContentType::json())
+                .set_payload(payload)
+                .to_request()
+        };
+        let resp = test::call_service(&app, submit(TARGET_RECEIPT)).await;
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        let location = resp.headers().get(header::LOCATION).unwrap().clone();
+        let ProcessJobResponse { job_id } = test::read_body_json(resp).await;
+        assert_eq!(location, format!("/jobs/{job_id}").as_str());
+        let resp = test::call_service(&app, submit(r#"{ "retailer": "Target" }"#)).await;
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        let ProcessJobResponse { job_id: failed_id } = test::read_body_json(resp).await;
+
+        // poll both jobs until the workers finish them
+        let mut finished = Vec::new();
+        for id in [job_id, failed_id] {
+            let job = loop {
+                let req = test::TestRequest::get()
+                    .uri(&format!("/jobs/{id}"))
+                    .to_request();
+                let job: Job = test::call_and_read_body_json(&app, req).await;
+                if job.status.is_finished() {
+                    break job;
+                }
+                actix_web::rt::time::sleep(std::time::Duration::from_millis(10)).await;
+            };
+            finished.push(job.status);
+        }
+        let JobStatus::Succeeded { receipt_id } = finished[0] else {
+            panic!("job should succeed, but was {:?}", finished[0]);
+        };
+        assert!(matches!(finished[1], JobStatus::Failed { .. }));
+
+        // the receipt was stored
+        let req = test::TestRequest::get()
+            .uri(&format!("/receipts/{receipt_id}/points"))
+            .to_request();
+        let PointsResponse { points } = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(points, 31);
+
+        // unknown jobs aren't found
+        let req = test::TestRequest::get()
+            .uri(&format!("/jobs/{}", Uuid::new_v4()))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn job_owners() {
+        let connection = Connection::new();
+        let mut api_keys = Vec::new();
+        for name in ["pat", "sam"] {
+            let user_id = connection
+                .store_user(User {
+                    name: name.to_owned(),
+                    email: None,
+                })
+                .await
+                .unwrap()
+                .unwrap();
+            api_keys.push(ApiKeyConfig {
+                name: name.to_owned(),
+                key_hash: hex::encode(Sha256::digest(name)),
+                scopes: vec![Scope::ReceiptsRead, Scope::ReceiptsWrite],
+                user_id: Some(user_id),
+            });
+        }
+        let auth_config = AuthConfig {
+            api_keys,
+            ..anonymous_auth_config()
+        };
+        let jobs = JobQueue::start(
+            connection.clone(),
+            Config::default().receipts,
+            &JobConfig::default(),
+        );
+        let app = test::init_service(
+            test_app(
+                connection,
+                Authentication::new(Authenticator::from_config(&auth_config).unwrap()),
+            )
+            .app_data(Data::new(jobs)),
+        )
+        .await;
+
+        // a member submits a receipt
+        let req = test::TestRequest::post()
+            .uri("/receipts/process?async=true")
+            .insert_header(ContentType::json())
+            .insert_header(("X-Api-Key", "pat"))
+            .set_payload(r#"{ "retailer": "Target" }"#)
+            .to_request();
+        let ProcessJobResponse { job_id } = test::call_and_read_body_json(&app, req).await;
+
+        // the member and clients that may read every member can read the job, but other members can't
+        for (key, status) in [
+            (Some("pat"), StatusCode::OK),
+            (None, StatusCode::OK),
+            (Some("sam"), StatusCode::FORBIDDEN),
+        ] {
+            let mut req = test::TestRequest::get().uri(&format!("/jobs/{job_id}"));
+            if let Some(key) = key {
+                req = req.insert_header(("X-Api-Key", key));
+            }
+            let resp = test::call_service(&app, req.to_request()).await;
+            assert_eq!(resp.status(), status, "{key:?}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn stats() {
+        let connection = Connection::new();
+        for receipt in [
+            TARGET_RECEIPT,
+            r#"
+            {
+                "retailer": "M&M Corner Market",
+                "purchaseDate": "2022-03-20",
+                "purchaseTime": "14:33",
+                "total": "9.00",
+                "items": [
+                    { "shortDescription": "Gatorade", "price": "2.25" },
+                    { "shortDescription": "Gatorade", "price": "2.25" },
+                    { "shortDescription": "Gatorade", "price": "2.25" },
+                    { "shortDescription": "Gatorade", "price": "2.25" }
+                ]
+            }"#,
+        ] {
+            let receipt: Receipt = serde_json::from_str(receipt).unwrap();
+            connection
+                .store_receipt(receipt, None)
+                .await
+                .unwrap()
+                .unwrap();
+        }
+        let app = test::init_service(test_app(connection, admin_authentication())).await;
+        let get = |uri: &str| {
+            test::TestRequest::get()
+                .uri(uri)
+                .insert_header(("X-Api-Key", "admin"))
+                .to_request()
+        };
+
+        let overall: Aggregate = test::call_and_read_body_json(&app, get("/stats")).await;
+        assert_eq!(overall.count, 2);
+        assert_eq!(overall.points.unwrap().sum, 140);
+        assert_eq!(overall.total.unwrap().max, Price::from_cents(900));
+
+        let by_retailer: Vec<Group> =
+            test::call_and_read_body_json(&app, get("/stats/retailers")).await;
+        assert_eq!(
+            by_retailer
+                .iter()
+                .map(|group| (group.key.clone(), group.aggregate.points.unwrap().sum))
+                .collect::<Vec<_>>(),
+            vec![
+                (GroupKey::Retailer("M&M Corner Market".to_owned()), 109),
+                (GroupKey::Retailer("Target".to_owned()), 31),
+            ]
+        );
+
+        // only receipts purchased within the range are aggregated
+        let by_month: Vec<Group> = test::call_and_read_body_json(
+            &app,
+            get("/stats/dates?bucket=month&from=2022-03-01&to=2022-03-31"),
+        )
+        .await;
+        assert_eq!(by_month.len(), 1);
+        assert_eq!(
+            by_month[0].key,
+            GroupKey::Date(time::macros::date!(2022 - 03 - 01))
+        );
+        let json = serde_json::to_value(&by_month[0]).unwrap();
+        assert_eq!(json["key"], "2022-03-01");
+        assert_eq!(json["count"], 1);
+        assert_eq!(json["total"]["p50"], "9.00");
+
+        let by_hour: Vec<Group> = test::call_and_read_body_json(&app, get("/stats/hours")).await;
+        let json = serde_json::to_value(&by_hour).unwrap();
+        assert_eq!(json[0]["key"], 13);
+        assert_eq!(json[1]["key"], 14);
+
+        // both receipts were purchased on a Sunday
+        let by_weekday: Vec<Group> =
+            test::call_and_read_body_json(&app, get("/stats/weekdays")).await;
+        assert_eq!(by_weekday.len(), 1);
+        assert_eq!(by_weekday[0].key, GroupKey::Weekday(Weekday::Sunday));
+        assert_eq!(by_weekday[0].aggregate.count, 2);
+        let json = serde_json::to_value(&by_weekday).unwrap();
+        assert_eq!(json[0]["key"], "sunday");
+
+        let empty: Aggregate =
+            test::call_and_read_body_json(&app, get("/stats?from=2023-01-01")).await;
+        assert_eq!(empty.count, 0);
+        assert!(empty.total.is_none());
+    }
+
+    #[actix_web::test]
+    async fn export() {
+        let connection = Connection::new();
+        for receipt in [
+            TARGET_RECEIPT,
+            r#"
+            {
+                "retailer": "M&M Corner Market",
+                "purchaseDate": "2022-03-20",
+                "purchaseTime": "14:33",
+                "total": "9.00",
+                "items": [
+                    { "shortDescription": "Gatorade", "price": "4.50", "quantity": 2 },
+                    { "shortDescription": "Gatorade", "price": "4.50", "quantity": 2 }
+                ]
+            }"#,
+        ] {
+            let receipt: Receipt = serde_json::from_str(receipt).unwrap();
+            connection
+                .store_receipt(receipt, None)
+                .await
+                .unwrap()
+                .unwrap();
+        }
+        let app = test::init_service(test_app(connection, admin_authentication())).await;
+        let get = |uri: &str| {
+            test::TestRequest::get()
+                .uri(uri)
+                .insert_header(("X-Api-Key", "admin"))
+                .to_request()
+        };
+
+        // only admins may export receipts
+        let req = test::TestRequest::get()
+            .uri("/receipts/export?format=csv")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = test::call_service(&app, get("/receipts/export?format=csv")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/csv; charset=utf-8"
+        );
+        let body = test::read_body(resp).await;
+        let mut reader = csv::Reader::from_reader(&body[..]);
+        let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(rows.len(), 2);
+        let headers = reader.headers().unwrap().clone();
+        let column = |name: &str| headers.iter().position(|header| header == name).unwrap();
+        let points: Vec<_> = rows.iter().map(|row| &row[column("points")]).collect();
+        assert_eq!(points, vec!["31", "109"]);
+        assert_eq!(&rows[1][column("total")], "9.00");
+        assert_eq!(&rows[1][column("purchaseDate")], "2022-03-20");
+
+        let body =
+            test::call_and_read_body(&app, get("/receipts/export?format=ndjson&rows=item")).await;
+        let rows: Vec<serde_json::Value> = std::str::from_utf8(&body)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[2]["retailer"], "M&M Corner Market");
+        assert_eq!(rows[2]["quantity"], 2);
+        assert_eq!(rows[2]["points"], 109);
+
+        let resp = test::call_service(&app, get("/receipts/export?format=xml")).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn import() {
+        let app = test::init_service(test_app(Connection::new(), admin_authentication())).await;
+        let import_req = |csv: Vec<u8>| {
+            test::TestRequest::post()
+                .uri("/receipts/import")
+                .insert_header(("X-Api-Key", "admin"))
+                .insert_header((header::CONTENT_TYPE, "text/csv"))
+                .set_payload(csv)
+                .to_request()
+        };
+
+        let csv = "\
+receiptId,retailer,purchaseDate,purchaseTime,total,storeId,transactionNumber,shortDescription,price,quantity
+a,M&M Corner Market,2022-03-20,14:33,9.00,12,2001,Gatorade,4.50,2
+a,M&M Corner Market,2022-03-20,14:33,9.00,12,2001,Gatorade,4.50,2
+b,Target,2022-01-02,13:13,1.25,,,Pepsi - 12-oz,1.25x,
+";
+        let report: ImportReport =
+            test::call_and_read_body_json(&app, import_req(csv.into())).await;
+        assert_eq!(report.accepted.len(), 1);
+        assert_eq!(report.accepted[0].rows, vec![2, 3]);
+        assert_eq!(report.rejected.len(), 1);
+        assert_eq!(report.rejected[0].row, 4);
+        assert!(report.rejected[0].reason.starts_with("malformed item"));
+
+        // an export with a row per item imports the same receipts, which are already stored
+        let export_req = test::TestRequest::get()
+            .uri("/receipts/export?format=csv&rows=item")
+            .insert_header(("X-Api-Key", "admin"))
+            .to_request();
+        let export = test::call_and_read_body(&app, export_req).await;
+        let again: ImportReport =
+            test::call_and_read_body_json(&app, import_req(export.to_vec())).await;
+        assert!(again.accepted.is_empty());
+        assert_eq!(again.duplicates, report.accepted);
+        assert!(again.rejected.is_empty());
+
+        let resp = test::call_service(&app, import_req(b"retailer,total\n".to_vec())).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
     }
 }
@@ -0,0 +1,16 @@
+use actix_web::{get, web, HttpResponse};
+
+use crate::{
+    auth::{Principal, Scope},
+    AppState,
+};
+
+/// Get the audit log of changes staff have made to members' points, oldest first.
+#[get("/audit")]
+pub async fn get_audit_log(
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::Admin)?;
+    Ok(HttpResponse::Ok().json(data.connection.load_audit_log().await))
+}
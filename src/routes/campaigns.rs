@@ -0,0 +1,134 @@
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::error::{ErrorResponse, StorageError};
+use crate::{
+    auth::{Principal, Scope},
+    campaigns::Campaign,
+    AppState,
+};
+
+/// Response sent by the create campaign service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct CreateCampaignResponse {
+    pub id: Uuid,
+}
+
+/// A campaign and its ID, as sent by the campaign services.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CampaignResponse {
+    pub id: Uuid,
+
+    #[serde(flatten)]
+    pub campaign: Campaign,
+}
+
+/// Builds the response for a campaign that failed validation.
+fn unacceptable_campaign() -> HttpResponse {
+    HttpResponse::BadRequest().json(ErrorResponse {
+        error: "campaign is not acceptable".to_owned(),
+    })
+}
+
+/// Create a new campaign. The campaign awards bonuses to receipts stored from now on.
+#[post("/campaigns")]
+pub async fn create_campaign(
+    web::Json(campaign): web::Json<Campaign>,
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::Admin)?;
+    Ok(
+        match data
+            .connection
+            .store_campaign(campaign)
+            .await
+            .map_err(StorageError)?
+        {
+            Some(id) => HttpResponse::Ok().json(CreateCampaignResponse { id }),
+            None => unacceptable_campaign(),
+        },
+    )
+}
+
+/// Get every campaign.
+#[get("/campaigns")]
+pub async fn list_campaigns(
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::Admin)?;
+    let campaigns: Vec<_> = data
+        .connection
+        .load_campaigns()
+        .await
+        .into_iter()
+        .map(|(id, campaign)| CampaignResponse { id, campaign })
+        .collect();
+    Ok(HttpResponse::Ok().json(campaigns))
+}
+
+/// Get the given campaign.
+#[get("/campaigns/{id}")]
+pub async fn get_campaign(
+    path: web::Path<Uuid>,
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::Admin)?;
+    let id = path.into_inner();
+    Ok(match data.connection.load_campaign(id).await {
+        Some(campaign) => HttpResponse::Ok().json(CampaignResponse { id, campaign }),
+        None => HttpResponse::NotFound().into(),
+    })
+}
+
+/// Replace the given campaign. Receipts already stored keep the bonuses they were awarded.
+#[put("/campaigns/{id}")]
+pub async fn replace_campaign(
+    path: web::Path<Uuid>,
+    web::Json(campaign): web::Json<Campaign>,
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::Admin)?;
+    if !campaign.is_acceptable() {
+        return Ok(unacceptable_campaign());
+    }
+    let id = path.into_inner();
+    Ok(
+        if data
+            .connection
+            .replace_campaign(id, campaign.clone())
+            .await
+            .map_err(StorageError)?
+        {
+            HttpResponse::Ok().json(CampaignResponse { id, campaign })
+        } else {
+            HttpResponse::NotFound().into()
+        },
+    )
+}
+
+/// End the given campaign early by deleting it. Receipts already stored keep the bonuses they were awarded.
+#[delete("/campaigns/{id}")]
+pub async fn delete_campaign(
+    path: web::Path<Uuid>,
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::Admin)?;
+    Ok(
+        if data
+            .connection
+            .delete_campaign(path.into_inner())
+            .await
+            .map_err(StorageError)?
+        {
+            HttpResponse::NoContent().into()
+        } else {
+            HttpResponse::NotFound().into()
+        },
+    )
+}
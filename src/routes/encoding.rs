@@ -0,0 +1,163 @@
+//! Content negotiation for receipt request and response bodies. Besides JSON, bodies may be encoded as MessagePack or
+//! CBOR, chosen by the `Content-Type` and `Accept` headers. Request bodies in any other media type are unsupported,
+//! while responses fall back to JSON, so clients that don't ask for a binary encoding see no difference.
+
+use actix_web::{
+    http::header::{self, Header},
+    HttpMessage, HttpRequest, HttpResponse, HttpResponseBuilder,
+};
+use serde::Serialize;
+use serde_json::Value;
+
+/// The encodings request and response bodies may use.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl Encoding {
+    /// Gets the media type of bodies in this encoding.
+    pub fn media_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::MessagePack => "application/msgpack",
+            Self::Cbor => "application/cbor",
+        }
+    }
+
+    /// Gets the encoding of a media type, without its parameters. MessagePack has no registered media type, so the
+    /// spellings in common use are all recognized.
+    fn from_media_type(essence: &str) -> Option<Self> {
+        match essence {
+            "application/json" => Some(Self::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Self::MessagePack)
+            }
+            "application/cbor" => Some(Self::Cbor),
+            _ => None,
+        }
+    }
+
+    /// Gets the encoding of a request's body from its `Content-Type` header. JSON media types with a `+json` suffix are
+    /// JSON too, as they are for `web::Json`. Returns None if the header is missing or names any other media type.
+    pub fn of_request(req: &HttpRequest) -> Option<Self> {
+        let mime = req.mime_type().ok().flatten()?;
+        if mime
+            .suffix()
+            .is_some_and(|suffix| suffix.as_str() == "json")
+        {
+            return Some(Self::Json);
+        }
+        Self::from_media_type(mime.essence_str())
+    }
+
+    /// Chooses the encoding of a response body from a request's `Accept` header: the most preferred encoding the
+    /// client accepts, falling back to JSON. Wildcards accept JSON at their rank.
+    pub fn accepted_by(req: &HttpRequest) -> Self {
+        let Ok(accept) = header::Accept::parse(req) else {
+            return Self::default();
+        };
+        accept
+            .ranked()
+            .iter()
+            .find_map(
+                |accepted| match (accepted.type_().as_str(), accepted.subtype().as_str()) {
+                    ("*", _) | ("application", "*") => Some(Self::Json),
+                    _ => Self::from_media_type(accepted.essence_str()),
+                },
+            )
+            .unwrap_or_default()
+    }
+
+    /// Decodes a body into a JSON value, so it can be checked the same way whatever its encoding.
+    pub fn decode(self, body: &[u8]) -> Result<Value, String> {
+        match self {
+            Self::Json => serde_json::from_slice(body).map_err(|e| e.to_string()),
+            Self::MessagePack => rmp_serde::from_slice(body).map_err(|e| e.to_string()),
+            Self::Cbor => ciborium::from_reader(body).map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Encodes a value. Structs are encoded as maps keyed by field name, as they are in JSON.
+    pub fn encode(self, value: &impl Serialize) -> Vec<u8> {
+        match self {
+            Self::Json => serde_json::to_vec(value).expect("responses should serialize"),
+            Self::MessagePack => {
+                rmp_serde::to_vec_named(value).expect("responses should serialize")
+            }
+            Self::Cbor => {
+                let mut body = Vec::new();
+                ciborium::into_writer(value, &mut body).expect("responses should serialize");
+                body
+            }
+        }
+    }
+
+    /// Finishes a response with the value as its body, in this encoding.
+    pub fn respond(
+        self,
+        mut response: HttpResponseBuilder,
+        value: &impl Serialize,
+    ) -> HttpResponse {
+        response
+            .content_type(self.media_type())
+            .body(self.encode(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    #[test]
+    fn negotiation() {
+        let req = TestRequest::default()
+            .insert_header((header::CONTENT_TYPE, "application/x-msgpack"))
+            .insert_header((
+                header::ACCEPT,
+                "application/json;q=0.5, text/html, application/cbor;q=0.9",
+            ))
+            .to_http_request();
+        assert_eq!(Encoding::of_request(&req), Some(Encoding::MessagePack));
+        assert_eq!(Encoding::accepted_by(&req), Encoding::Cbor);
+
+        let req = TestRequest::default()
+            .insert_header((header::CONTENT_TYPE, "text/plain"))
+            .insert_header((header::ACCEPT, "*/*"))
+            .to_http_request();
+        assert_eq!(Encoding::of_request(&req), None);
+        assert_eq!(Encoding::accepted_by(&req), Encoding::Json);
+
+        // wildcards are as good as JSON
+        for accept in [
+            "*/*, application/cbor;q=0.1",
+            "application/*;q=0.8, application/x-msgpack;q=0.5",
+        ] {
+            let req = TestRequest::default()
+                .insert_header((header::ACCEPT, accept))
+                .to_http_request();
+            assert_eq!(Encoding::accepted_by(&req), Encoding::Json, "{accept}");
+        }
+        let req = TestRequest::default()
+            .insert_header((header::ACCEPT, "*/*;q=0.1, application/cbor"))
+            .to_http_request();
+        assert_eq!(Encoding::accepted_by(&req), Encoding::Cbor);
+
+        let req = TestRequest::default().to_http_request();
+        assert_eq!(Encoding::of_request(&req), None);
+        assert_eq!(Encoding::accepted_by(&req), Encoding::Json);
+
+        let req = TestRequest::default()
+            .insert_header((
+                header::CONTENT_TYPE,
+                "application/vnd.receipt+json; charset=utf-8",
+            ))
+            .to_http_request();
+        assert_eq!(Encoding::of_request(&req), Some(Encoding::Json));
+    }
+}
@@ -0,0 +1,40 @@
+use std::{fmt, io};
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde::{Deserialize, Serialize};
+
+/// The error reported for writes that couldn't be persisted, and so weren't applied.
+pub const UNAVAILABLE: &str = "the write could not be saved, try again later";
+
+/// The error reported for receipts whose transaction was already stored under a different owner.
+pub const CONFLICTING_TRANSACTION: &str =
+    "a receipt for this transaction was already submitted by someone else";
+
+/// Response body sent when a request cannot be fulfilled.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+/// A write that couldn't be persisted, and so wasn't applied. The cause is logged when the write fails, so clients are
+/// only told to retry.
+#[derive(Debug)]
+pub struct StorageError(pub io::Error);
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{UNAVAILABLE}")
+    }
+}
+
+impl ResponseError for StorageError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ErrorResponse {
+            error: self.to_string(),
+        })
+    }
+}
@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use actix_web::{
+    get,
+    http::header::{CacheControl, CacheDirective},
+    web, HttpRequest, HttpResponse,
+};
+use futures_util::{stream, StreamExt};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    auth::{Principal, Scope},
+    AppState,
+};
+
+/// The header carrying the number of the last event a client received, when it reconnects.
+const LAST_EVENT_ID_HEADER: &str = "Last-Event-ID";
+
+/// Stream an event whenever a new receipt is stored, as server-sent events. Clients reconnecting with a
+/// `Last-Event-ID` header first receive the buffered events they missed, or a `reset` event if some are no longer
+/// buffered.
+#[get("/events")]
+pub async fn get_events(
+    req: HttpRequest,
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::ReceiptsRead)?;
+    let last_event_id = req
+        .headers()
+        .get(LAST_EVENT_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok());
+    let (backlog, receiver) = data.connection.events().subscribe(last_event_id);
+    let keep_alive = Duration::from_secs(data.config.events.keep_alive_seconds);
+
+    let backlog = Some(backlog.to_sse()).filter(|backlog| !backlog.is_empty());
+    let backlog = stream::iter(
+        backlog
+            .into_iter()
+            .map(|backlog| Ok::<_, actix_web::Error>(web::Bytes::from(backlog))),
+    );
+    let live = stream::unfold(receiver, move |mut receiver| async move {
+        let chunk = match actix_web::rt::time::timeout(keep_alive, receiver.recv()).await {
+            Ok(Ok(event)) => event.to_sse(),
+            // a client too slow to keep up is disconnected, and can resume from the buffer when it reconnects
+            Ok(Err(RecvError::Lagged(_) | RecvError::Closed)) => return None,
+            Err(_) => ": keep-alive\n\n".to_owned(),
+        };
+        Some((Ok(web::Bytes::from(chunk)), receiver))
+    });
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .streaming(backlog.chain(live)))
+}
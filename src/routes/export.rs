@@ -0,0 +1,65 @@
+use std::convert::Infallible;
+
+use actix_web::{
+    get,
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    web, HttpResponse,
+};
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    auth::{Principal, Scope},
+    export::{Exporter, Format, Rows},
+    AppState,
+};
+
+/// The number of receipts loaded and encoded at a time.
+const CHUNK_SIZE: usize = 500;
+
+/// Query parameters accepted by the export service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportQuery {
+    pub format: Format,
+
+    /// Whether to export a row per receipt or a row per item.
+    #[serde(default)]
+    pub rows: Rows,
+}
+
+/// Stream every stored receipt, oldest first, as CSV or newline-delimited JSON, with the points for each.
+#[get("/receipts/export")]
+pub async fn export_receipts(
+    query: web::Query<ExportQuery>,
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::Admin)?;
+    let ExportQuery { format, rows } = query.into_inner();
+    let chunks: Vec<Vec<Uuid>> = data
+        .connection
+        .load_receipt_ids()
+        .await
+        .chunks(CHUNK_SIZE)
+        .map(<[Uuid]>::to_vec)
+        .collect();
+    let connection = data.connection.clone();
+    let mut exporter = Exporter::new(format, rows);
+    let body = stream::iter(chunks)
+        .then(move |ids| {
+            let connection = connection.clone();
+            async move { connection.load_receipts(&ids).await }
+        })
+        .map(move |records| Ok::<_, Infallible>(web::Bytes::from(exporter.encode(&records))));
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "receipts.{}",
+                format.extension()
+            ))],
+        })
+        .streaming(body))
+}
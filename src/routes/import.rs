@@ -0,0 +1,31 @@
+use actix_web::{post, web, HttpResponse};
+
+use super::payload::PayloadError;
+use crate::{
+    auth::{Principal, Scope},
+    import, AppState,
+};
+
+/// Import receipts from a CSV file, responding with a report of the receipts stored and the rows rejected.
+#[post("/receipts/import")]
+pub async fn import_receipts(
+    payload: web::Payload,
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::Admin)?;
+    let limit = data.config.import.max_body_bytes;
+    let body = match payload.to_bytes_limited(limit).await {
+        Ok(Ok(body)) => body,
+        Ok(Err(_)) => return Err(PayloadError::Unreadable.into()),
+        Err(_) => return Err(PayloadError::TooLarge { limit }.into()),
+    };
+    let report = import::import(
+        &data.connection,
+        &body[..],
+        &data.config.import.columns,
+        &data.config.receipts,
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(report))
+}
@@ -0,0 +1,27 @@
+use actix_web::{get, web, HttpResponse};
+use uuid::Uuid;
+
+use crate::{
+    auth::{AuthError, Principal, Scope},
+    jobs::JobQueue,
+};
+
+/// Get the status of a receipt submitted for asynchronous processing: the receipt ID once it's stored, or the reason
+/// it couldn't be. Jobs for a member's receipts may only be read by that member, or clients that may read every member.
+#[get("/jobs/{id}")]
+pub async fn get_job(
+    path: web::Path<Uuid>,
+    principal: Principal,
+    jobs: web::Data<JobQueue>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::ReceiptsRead)?;
+    let Some(job) = jobs.load(path.into_inner()) else {
+        return Ok(HttpResponse::NotFound().into());
+    };
+    if job.owner_id.is_some() && principal.user_id != job.owner_id {
+        principal
+            .require_scope(Scope::UsersRead)
+            .map_err(|_| AuthError::Forbidden)?;
+    }
+    Ok(HttpResponse::Ok().json(job))
+}
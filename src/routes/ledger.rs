@@ -0,0 +1,117 @@
+use actix_web::{get, post, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    auth::{AuthError, Principal, Scope},
+    ledger::Transaction,
+    AppState,
+};
+
+/// Response sent by the ledger service.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerResponse {
+    pub balance: i64,
+
+    /// The member's transactions, oldest first.
+    pub transactions: Vec<Transaction>,
+}
+
+/// Request to redeem a member's points.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RedeemRequest {
+    pub points: u64,
+
+    /// What the points were redeemed for.
+    pub description: String,
+}
+
+/// Request to grant or revoke a member's points.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdjustRequest {
+    /// The points to grant, or to revoke if negative.
+    pub points: i64,
+
+    /// Why the points were adjusted.
+    pub reason: String,
+}
+
+/// Checks that the client may act on the member's ledger: members may always act on their own ledger, and other
+/// clients need the given scope.
+fn authorize(principal: &Principal, user_id: Uuid, scope: Scope) -> Result<(), AuthError> {
+    if principal.user_id == Some(user_id) {
+        return Ok(());
+    }
+    principal
+        .require_scope(scope)
+        .map_err(|_| AuthError::Forbidden)
+}
+
+/// Get a member's points balance and the transactions making it up.
+#[get("/users/{id}/ledger")]
+pub async fn get_ledger(
+    path: web::Path<Uuid>,
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    let id = path.into_inner();
+    authorize(&principal, id, Scope::UsersRead)?;
+    if data.connection.load_user(id).await.is_none() {
+        return Ok(HttpResponse::NotFound().into());
+    }
+    let ledger = data.connection.load_ledger(id).await;
+    Ok(HttpResponse::Ok().json(LedgerResponse {
+        balance: ledger.balance(),
+        transactions: ledger.transactions().to_vec(),
+    }))
+}
+
+/// Redeem points from a member's balance.
+#[post("/users/{id}/redemptions")]
+pub async fn redeem_points(
+    path: web::Path<Uuid>,
+    web::Json(request): web::Json<RedeemRequest>,
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    let id = path.into_inner();
+    authorize(&principal, id, Scope::UsersWrite)?;
+    if data.connection.load_user(id).await.is_none() {
+        return Ok(HttpResponse::NotFound().into());
+    }
+    let transaction = data
+        .connection
+        .redeem_points(id, request.points, request.description)
+        .await?;
+    Ok(HttpResponse::Ok().json(transaction))
+}
+
+/// Grant or revoke points from a member's balance.
+#[post("/users/{id}/adjustments")]
+pub async fn adjust_points(
+    path: web::Path<Uuid>,
+    web::Json(request): web::Json<AdjustRequest>,
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::Admin)?;
+    let id = path.into_inner();
+    if data.connection.load_user(id).await.is_none() {
+        return Ok(HttpResponse::NotFound().into());
+    }
+    let transaction = data
+        .connection
+        .adjust_points(id, request.points, request.reason, principal.subject)
+        .await?;
+    Ok(HttpResponse::Ok().json(transaction))
+}
+
+/// Check that the ledger agrees with the stored receipts.
+#[get("/ledger/check")]
+pub async fn check_ledger(
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::Admin)?;
+    Ok(HttpResponse::Ok().json(data.connection.check_ledger().await))
+}
@@ -0,0 +1,54 @@
+use std::{fmt::Write, sync::atomic::Ordering};
+
+use actix_web::{get, http::header::ContentType, web, HttpResponse};
+
+use crate::{
+    auth::{Principal, Scope},
+    AppState,
+};
+
+/// Get operational metrics in the Prometheus text exposition format.
+#[get("/metrics")]
+pub async fn get_metrics(
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::Admin)?;
+    let connection = &data.connection;
+    let evictions = connection.evictions();
+
+    let mut body = String::new();
+    writeln!(
+        body,
+        "# HELP serve_ex_receipts_stored Receipts currently in the store."
+    )
+    .unwrap();
+    writeln!(body, "# TYPE serve_ex_receipts_stored gauge").unwrap();
+    writeln!(
+        body,
+        "serve_ex_receipts_stored {}",
+        connection.receipt_count().await
+    )
+    .unwrap();
+    writeln!(
+        body,
+        "# HELP serve_ex_receipt_evictions_total Receipts evicted from the store."
+    )
+    .unwrap();
+    writeln!(body, "# TYPE serve_ex_receipt_evictions_total counter").unwrap();
+    for (reason, count) in [
+        ("capacity", &evictions.capacity),
+        ("expired", &evictions.expired),
+    ] {
+        writeln!(
+            body,
+            "serve_ex_receipt_evictions_total{{reason=\"{reason}\"}} {}",
+            count.load(Ordering::Relaxed)
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::plaintext())
+        .body(body))
+}
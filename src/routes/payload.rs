@@ -0,0 +1,268 @@
+//! Parsing for receipt request bodies. Bodies are decoded and checked against the configured limits before being
+//! deserialized into a [`Receipt`], so oversized payloads are rejected with a precise message instead of a generic
+//! parse error. The body size limit is enforced while the body is read; the item and string limits can only be
+//! checked once it has been decoded, so decoding is bounded by the body size limit alone.
+//!
+//! As with `web::Json`, the `Content-Type` of a body is enforced: bodies must be declared as JSON or another
+//! [`Encoding`], and are otherwise rejected as an unsupported media type.
+
+use std::fmt;
+
+use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use serde_json::Value;
+
+use super::{encoding::Encoding, error::ErrorResponse};
+use crate::{config::ReceiptConfig, data::Receipt};
+
+/// Reasons a receipt request body can be rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PayloadError {
+    /// The body is larger than the configured limit.
+    TooLarge { limit: usize },
+    /// The body's `Content-Type` is missing or isn't a supported [`Encoding`].
+    UnsupportedMediaType,
+    /// The body could not be read from the client.
+    Unreadable,
+    /// The body could not be decoded or does not describe a receipt.
+    Malformed(String),
+    /// The receipt has more items than the configured limit.
+    TooManyItems { count: usize, limit: usize },
+    /// A string in the receipt is longer than the configured limit.
+    StringTooLong { path: String, limit: usize },
+    /// Strict mode is enabled and the receipt contains fields the application does not recognize.
+    UnknownFields(Vec<String>),
+}
+
+impl fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooLarge { limit } => write!(f, "request body exceeds {limit} bytes"),
+            Self::UnsupportedMediaType => {
+                write!(f, "request body must be JSON, MessagePack or CBOR")
+            }
+            Self::Unreadable => write!(f, "request body could not be read"),
+            Self::Malformed(msg) => write!(f, "malformed receipt: {msg}"),
+            Self::TooManyItems { count, limit } => {
+                write!(
+                    f,
+                    "receipt has {count} items, but at most {limit} are allowed"
+                )
+            }
+            Self::StringTooLong { path, limit } => {
+                write!(f, "`{path}` exceeds {limit} characters")
+            }
+            Self::UnknownFields(paths) => {
+                write!(f, "unknown fields: ")?;
+                for (i, path) in paths.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "`{path}`")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl ResponseError for PayloadError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::TooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ErrorResponse {
+            error: self.to_string(),
+        })
+    }
+}
+
+/// Reads a receipt from a request body in the given encoding, enforcing the limits in the given configuration.
+pub async fn read_receipt(
+    payload: web::Payload,
+    encoding: Encoding,
+    config: &ReceiptConfig,
+) -> Result<Receipt, PayloadError> {
+    let body = read_body(payload, config).await?;
+    parse_receipt(&body, encoding, config)
+}
+
+/// Reads a request body without parsing it, enforcing the body size limit in the given configuration.
+pub async fn read_body(
+    payload: web::Payload,
+    config: &ReceiptConfig,
+) -> Result<web::Bytes, PayloadError> {
+    match payload.to_bytes_limited(config.max_body_bytes).await {
+        Ok(Ok(body)) => Ok(body),
+        Ok(Err(_)) => Err(PayloadError::Unreadable),
+        Err(_) => Err(PayloadError::TooLarge {
+            limit: config.max_body_bytes,
+        }),
+    }
+}
+
+/// Parses a receipt from bytes in the given encoding, enforcing the limits in the given configuration.
+pub fn parse_receipt(
+    body: &[u8],
+    encoding: Encoding,
+    config: &ReceiptConfig,
+) -> Result<Receipt, PayloadError> {
+    let value = encoding.decode(body).map_err(PayloadError::Malformed)?;
+    parse_receipt_value(value, config)
+}
+
+/// Parses a receipt from its decoded form, enforcing the item and string limits in the given configuration.
+pub fn parse_receipt_value(value: Value, config: &ReceiptConfig) -> Result<Receipt, PayloadError> {
+    check_limits(&value, config)?;
+
+    let mut unknown = Vec::new();
+    let receipt = serde_ignored::deserialize(value, |path| unknown.push(format_path(&path)))
+        .map_err(|e| PayloadError::Malformed(e.to_string()))?;
+    if config.strict && !unknown.is_empty() {
+        return Err(PayloadError::UnknownFields(unknown));
+    }
+    Ok(receipt)
+}
+
+/// Checks the item count and string lengths of a receipt before it is deserialized.
+pub fn check_limits(value: &Value, config: &ReceiptConfig) -> Result<(), PayloadError> {
+    if let Some(Value::Array(items)) = value.get("items") {
+        if items.len() > config.max_items {
+            return Err(PayloadError::TooManyItems {
+                count: items.len(),
+                limit: config.max_items,
+            });
+        }
+    }
+    check_strings(value, &mut String::new(), config.max_string_length)
+}
+
+/// Recursively checks that every string within the value is within the length limit.
+/// `path` holds the location of `value` within the receipt, for error messages.
+fn check_strings(value: &Value, path: &mut String, limit: usize) -> Result<(), PayloadError> {
+    let len = path.len();
+    match value {
+        Value::String(s) if s.chars().count() > limit => Err(PayloadError::StringTooLong {
+            path: path.clone(),
+            limit,
+        }),
+        Value::Array(values) => values.iter().enumerate().try_for_each(|(i, v)| {
+            path.push_str(&format!("[{i}]"));
+            let result = check_strings(v, path, limit);
+            path.truncate(len);
+            result
+        }),
+        Value::Object(map) => map.iter().try_for_each(|(key, v)| {
+            if !path.is_empty() {
+                path.push('.');
+            }
+            path.push_str(key);
+            let result = check_strings(v, path, limit);
+            path.truncate(len);
+            result
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Formats a path to an ignored field in the same style as [`check_strings`], e.g. `items[0].price`.
+fn format_path(path: &serde_ignored::Path) -> String {
+    use serde_ignored::Path;
+    match path {
+        Path::Root => String::new(),
+        Path::Seq { parent, index } => format!("{}[{index}]", format_path(parent)),
+        Path::Map { parent, key } => match format_path(parent) {
+            parent if parent.is_empty() => key.clone(),
+            parent => format!("{parent}.{key}"),
+        },
+        Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => format_path(parent),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECEIPT: &str = r#"
+        {
+            "retailer": "Target",
+            "purchaseDate": "2022-01-02",
+            "purchaseTime": "13:13",
+            "total": "1.25",
+            "items": [
+                { "shortDescription": "Pepsi - 12-oz", "price": "1.25", "colour": "blue" }
+            ],
+            "purchasedate": "2022-01-02"
+        }
+    "#;
+
+    #[test]
+    fn unknown_fields() {
+        let lenient = ReceiptConfig::default();
+        assert!(parse_receipt(RECEIPT.as_bytes(), Encoding::Json, &lenient).is_ok());
+
+        let strict = ReceiptConfig {
+            strict: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            parse_receipt(RECEIPT.as_bytes(), Encoding::Json, &strict),
+            Err(PayloadError::UnknownFields(vec![
+                "items[0].colour".to_owned(),
+                "purchasedate".to_owned(),
+            ]))
+        );
+    }
+
+    #[test]
+    fn binary_unknown_fields() {
+        let value: Value = serde_json::from_str(RECEIPT).unwrap();
+        let strict = ReceiptConfig {
+            strict: true,
+            ..Default::default()
+        };
+        let msgpack = rmp_serde::to_vec_named(&value).unwrap();
+        let mut cbor = Vec::new();
+        ciborium::into_writer(&value, &mut cbor).unwrap();
+        for (body, encoding) in [(msgpack, Encoding::MessagePack), (cbor, Encoding::Cbor)] {
+            assert!(parse_receipt(&body, encoding, &ReceiptConfig::default()).is_ok());
+            assert_eq!(
+                parse_receipt(&body, encoding, &strict),
+                Err(PayloadError::UnknownFields(vec![
+                    "items[0].colour".to_owned(),
+                    "purchasedate".to_owned(),
+                ]))
+            );
+        }
+    }
+
+    #[test]
+    fn limits() {
+        let config = ReceiptConfig {
+            max_items: 0,
+            ..Default::default()
+        };
+        assert_eq!(
+            parse_receipt(RECEIPT.as_bytes(), Encoding::Json, &config),
+            Err(PayloadError::TooManyItems { count: 1, limit: 0 })
+        );
+
+        let config = ReceiptConfig {
+            max_string_length: 10,
+            ..Default::default()
+        };
+        assert_eq!(
+            parse_receipt(RECEIPT.as_bytes(), Encoding::Json, &config),
+            Err(PayloadError::StringTooLong {
+                path: "items[0].shortDescription".to_owned(),
+                limit: 10,
+            })
+        );
+    }
+}
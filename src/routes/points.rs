@@ -1,78 +1,132 @@
-use std::num::Saturating;
-
-use actix_web::{get, web, HttpResponse};
-use serde::{Serialize, Deserialize};
-use time::macros::time;
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::{data::Receipt, AppState};
-
-/// Calculates points for the receipt, as follows:
-/// - 1 pt for each letter or numeral in the retailer name
-/// - 50 pt if the total has .00 cents
-/// - 25 pt if the total is a multiple of .25 cents
-/// - 5 pt for every two items (e.g. 10 items = 5 pt, 3 items = 1 pt)
-/// - if the (trimmed) length of an item description is a multiple of 3, add points according to
-///   ceil(price * 0.2)
-/// - 6 pt if the day of the purchase date is odd
-/// - 10 pt if the time of the purchase is between 14:00 and 16:00 (exclusive)
-fn calculate_points(receipt: &Receipt) -> u64 {
-    // overflow is unlikely, but if it happens we just saturate
-    let mut total_pts = Saturating(0u64);
+use super::{
+    encoding::Encoding,
+    error::ErrorResponse,
+    receipt::{authorize_reader, missing_receipt},
+};
+use crate::{
+    auth::{Principal, Scope},
+    ledger::ReasonCode,
+    review::ReceiptStatus,
+    AppState,
+};
 
-    let letter_digit_count: u64 = receipt
-        .retailer
-        .chars()
-        .filter(|c| c.is_alphabetic() || c.is_numeric())
-        .count()
-        .try_into()
-        .expect("length should fit in 64 bits");
-    total_pts += letter_digit_count;
+/// Response sent by the points service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct PointsResponse {
+    pub points: u64,
+}
 
-    if receipt.total.cents == 0 {
-        total_pts += 50;
-    }
+/// Get the points for the given receipt. Receipts pending review are worth no points yet, so respond with 202 Accepted.
+/// The response may be received as JSON, MessagePack or CBOR. A member's receipts may only be read by that member, or
+/// clients that may read every member.
+#[get("/receipts/{id}/points")]
+pub async fn get_points(
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::ReceiptsRead)?;
+    let id = path.into_inner();
+    let Some(record) = data.connection.load_receipt(id).await else {
+        return Ok(missing_receipt(&data.connection, id).await);
+    };
+    authorize_reader(&principal, &record)?;
+    let Some(points) = data.connection.load_points(id).await else {
+        return Ok(missing_receipt(&data.connection, id).await);
+    };
+    let response = match record.status {
+        ReceiptStatus::Pending => HttpResponse::Accepted(),
+        _ => HttpResponse::Ok(),
+    };
+    Ok(Encoding::accepted_by(&req).respond(response, &PointsResponse { points }))
+}
 
-    if receipt.total.cents % 25 == 0 {
-        total_pts += 25;
-    }
+/// Query for the points preview service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PreviewQuery {
+    /// The version of the ruleset to calculate points under.
+    pub ruleset: u32,
+}
 
-    let item_pts =
-        5 * u64::try_from(receipt.items.len() / 2).expect("length should fit in 64 bits");
-    total_pts += item_pts;
+/// Response sent by the points preview service.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewPointsResponse {
+    /// The points the receipt would be awarded under the ruleset.
+    pub points: u64,
 
-    for item in receipt.items.iter() {
-        if item.short_description.trim().len() % 3 == 0 {
-            let float_price = item.price.dollars as f64 + f64::from(item.price.cents) / 100.0;
-            let price_pts = (float_price * 0.2).ceil() as u64;
-            total_pts += price_pts;
-        }
-    }
+    pub ruleset_version: u32,
+    pub ruleset_name: String,
+}
 
-    if receipt.purchase_date.day() % 2 != 0 {
-        total_pts += 6;
-    }
+/// Calculate the points the given receipt would be awarded under a ruleset, without changing the points it was
+/// awarded. A member's receipts may only be previewed by that member, or clients that may read every member.
+#[get("/receipts/{id}/points/preview")]
+pub async fn preview_points(
+    path: web::Path<Uuid>,
+    query: web::Query<PreviewQuery>,
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::ReceiptsRead)?;
+    let Some(ruleset) = data.connection.rulesets().get(query.ruleset) else {
+        return Ok(HttpResponse::NotFound().json(ErrorResponse {
+            error: format!("unknown ruleset version {}", query.ruleset),
+        }));
+    };
+    let id = path.into_inner();
+    let Some(record) = data.connection.load_receipt(id).await else {
+        return Ok(missing_receipt(&data.connection, id).await);
+    };
+    authorize_reader(&principal, &record)?;
+    Ok(HttpResponse::Ok().json(PreviewPointsResponse {
+        points: ruleset.calculate_points(&record),
+        ruleset_version: ruleset.version,
+        ruleset_name: ruleset.name.clone(),
+    }))
+}
 
-    if receipt.purchase_time > time!(14:00) && receipt.purchase_time < time!(16:00) {
-        total_pts += 10;
-    }
+/// Request to adjust the points for a receipt.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdjustReceiptRequest {
+    /// The points to add, or to remove if negative.
+    pub points: i64,
 
-    total_pts.0
-}
+    pub reason: ReasonCode,
 
-/// Response sent by the points service.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
-pub struct PointsResponse {
-    pub points: u64,
+    /// Free-form detail about the adjustment.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
 }
 
-/// Compute and get points for the given receipt.
-#[get("/receipts/{id}/points")]
-pub async fn get_points(path: web::Path<Uuid>, data: web::Data<AppState>) -> HttpResponse {
+/// Grant or revoke points for the given receipt. The adjustment is credited to the receipt owner's ledger and recorded
+/// in the audit log.
+#[post("/receipts/{id}/adjustments")]
+pub async fn adjust_receipt(
+    path: web::Path<Uuid>,
+    web::Json(request): web::Json<AdjustReceiptRequest>,
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::Admin)?;
     let id = path.into_inner();
-    let Some(receipt) = data.connection.load_receipt(id).await else {
-        return HttpResponse::NotFound().into();
-    };
-    let points = calculate_points(&receipt);
-    HttpResponse::Ok().json(PointsResponse { points })
+    let adjustment = data
+        .connection
+        .adjust_receipt(
+            id,
+            request.points,
+            request.reason,
+            request.note,
+            principal.subject,
+        )
+        .await?;
+    Ok(match adjustment {
+        Some(adjustment) => HttpResponse::Ok().json(adjustment),
+        None => missing_receipt(&data.connection, id).await,
+    })
 }
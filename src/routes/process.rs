@@ -1,23 +1,106 @@
-use actix_web::{post, web, HttpResponse};
-use serde::{Serialize, Deserialize};
+use actix_web::{http::header, post, web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::{data::Receipt, AppState};
+use super::{
+    encoding::Encoding,
+    error::{ErrorResponse, StorageError, CONFLICTING_TRANSACTION},
+    payload,
+};
+use crate::{
+    auth::{Principal, Scope},
+    db::Stored,
+    jobs::JobQueue,
+    AppState,
+};
 
 /// Response sent by the process service.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct ProcessReceiptResponse {
     pub id: Uuid,
+
+    /// Whether the receipt was already stored, so this submission was a duplicate of it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub duplicate: bool,
+}
+
+/// Response sent by the process service when processing asynchronously.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessJobResponse {
+    pub job_id: Uuid,
 }
 
-/// Send receipt data for a new receipt to the database.
+/// Query parameters accepted by the process service.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct ProcessQuery {
+    /// Whether to process the receipt in the background, responding with a job to poll instead of the receipt ID.
+    #[serde(default, rename = "async")]
+    pub asynchronous: bool,
+}
+
+/// Send receipt data for a new receipt to the database. If the client acts on behalf of a member, the receipt is
+/// owned by that member. Submitting a receipt for a transaction that is already stored responds with the existing
+/// receipt's ID, marked as a duplicate, or with 409 Conflict if the existing receipt belongs to someone else. With
+/// `?async=true`, the receipt is queued for a background worker and the response is 202 Accepted with a job to poll at
+/// `/jobs/{id}`. The receipt may be sent, and the response received, as JSON, MessagePack or CBOR.
 #[post("/receipts/process")]
 pub async fn process_receipt(
-    web::Json(receipt): web::Json<Receipt>,
+    req: HttpRequest,
+    payload: web::Payload,
+    query: web::Query<ProcessQuery>,
+    principal: Principal,
     data: web::Data<AppState>,
-) -> HttpResponse {
-    match data.connection.store_receipt(receipt).await {
-        Some(id) => HttpResponse::Ok().json(ProcessReceiptResponse { id }),
-        None => HttpResponse::BadRequest().into(),
+    jobs: Option<web::Data<JobQueue>>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::ReceiptsWrite)?;
+    let response_encoding = Encoding::accepted_by(&req);
+    let encoding = Encoding::of_request(&req).ok_or(payload::PayloadError::UnsupportedMediaType)?;
+    let owner_id = principal.user_id;
+    if let Some(owner_id) = owner_id {
+        if data.connection.load_user(owner_id).await.is_none() {
+            return Ok(HttpResponse::Unauthorized().json(ErrorResponse {
+                error: "unknown user".to_owned(),
+            }));
+        }
+    }
+    if query.asynchronous {
+        let Some(jobs) = jobs else {
+            return Ok(HttpResponse::ServiceUnavailable().json(ErrorResponse {
+                error: "asynchronous processing is not available".to_owned(),
+            }));
+        };
+        let body = payload::read_body(payload, &data.config.receipts).await?;
+        let job_id = jobs.submit(body, encoding, owner_id)?;
+        let mut response = HttpResponse::Accepted();
+        response.insert_header((header::LOCATION, format!("/jobs/{job_id}")));
+        return Ok(response_encoding.respond(response, &ProcessJobResponse { job_id }));
     }
+    let receipt = payload::read_receipt(payload, encoding, &data.config.receipts).await?;
+    let response = match data
+        .connection
+        .store_new_receipt(receipt, owner_id)
+        .await
+        .map_err(StorageError)?
+    {
+        Some(Stored::New(id)) => ProcessReceiptResponse {
+            id,
+            duplicate: false,
+        },
+        Some(Stored::Duplicate(id)) => ProcessReceiptResponse {
+            id,
+            duplicate: true,
+        },
+        Some(Stored::Conflict(_)) => {
+            return Ok(HttpResponse::Conflict().json(ErrorResponse {
+                error: CONFLICTING_TRANSACTION.to_owned(),
+            }));
+        }
+        None => {
+            return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+                error: "receipt is not acceptable".to_owned(),
+            }));
+        }
+    };
+    Ok(response_encoding.respond(HttpResponse::Ok(), &response))
 }
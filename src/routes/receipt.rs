@@ -0,0 +1,52 @@
+use actix_web::{get, web, HttpResponse};
+use uuid::Uuid;
+
+use super::error::ErrorResponse;
+use crate::{
+    auth::{AuthError, Principal, Scope},
+    db::{Connection, ReceiptRecord},
+    AppState,
+};
+
+/// Get the stored data for the given receipt. A member's receipts may only be read by that member, or clients that may
+/// read every member.
+#[get("/receipts/{id}")]
+pub async fn get_receipt(
+    path: web::Path<Uuid>,
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::ReceiptsRead)?;
+    let id = path.into_inner();
+    let Some(record) = data.connection.load_receipt(id).await else {
+        return Ok(missing_receipt(&data.connection, id).await);
+    };
+    authorize_reader(&principal, &record)?;
+    Ok(HttpResponse::Ok().json(record))
+}
+
+/// Checks that the client may read the receipt: receipts with an owner may only be read by the owner, or clients that
+/// may read every member.
+pub(super) fn authorize_reader(
+    principal: &Principal,
+    record: &ReceiptRecord,
+) -> Result<(), AuthError> {
+    if record.owner_id.is_some() && principal.user_id != record.owner_id {
+        principal
+            .require_scope(Scope::UsersRead)
+            .map_err(|_| AuthError::Forbidden)?;
+    }
+    Ok(())
+}
+
+/// Builds the response for a receipt that could not be loaded: 410 Gone if the receipt was evicted from the store,
+/// otherwise 404 Not Found.
+pub(super) async fn missing_receipt(connection: &Connection, id: Uuid) -> HttpResponse {
+    if connection.receipt_evicted(id).await {
+        HttpResponse::Gone().json(ErrorResponse {
+            error: "receipt has expired".to_owned(),
+        })
+    } else {
+        HttpResponse::NotFound().into()
+    }
+}
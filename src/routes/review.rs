@@ -0,0 +1,125 @@
+use std::fmt;
+
+use actix_web::{get, http::StatusCode, post, web, HttpResponse, ResponseError};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{error::ErrorResponse, receipt::missing_receipt};
+use crate::{
+    auth::{Principal, Scope},
+    db::ReceiptRecord,
+    AppState,
+};
+
+/// A receipt and its ID, as sent by the review services.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReviewReceiptResponse {
+    pub id: Uuid,
+
+    #[serde(flatten)]
+    pub record: ReceiptRecord,
+}
+
+/// Request to approve or reject a receipt pending review.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReviewRequest {
+    /// Free-form detail about the decision.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+/// A review request body that is present but isn't a valid [`ReviewRequest`].
+#[derive(Debug)]
+struct MalformedReview(serde_json::Error);
+
+impl fmt::Display for MalformedReview {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "malformed review request: {}", self.0)
+    }
+}
+
+impl ResponseError for MalformedReview {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ErrorResponse {
+            error: self.to_string(),
+        })
+    }
+}
+
+/// Gets the note on a review request. The body is optional, so a decision without a note may be sent with an empty
+/// body, but a body that is present must be a valid request.
+fn note(body: &[u8]) -> Result<Option<String>, MalformedReview> {
+    if body.is_empty() {
+        return Ok(None);
+    }
+    serde_json::from_slice::<ReviewRequest>(body)
+        .map(|request| request.note)
+        .map_err(MalformedReview)
+}
+
+/// Get every receipt pending review, oldest first.
+#[get("/review/pending")]
+pub async fn list_pending_receipts(
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::Admin)?;
+    let receipts: Vec<_> = data
+        .connection
+        .load_pending_receipts()
+        .await
+        .into_iter()
+        .map(|(id, record)| ReviewReceiptResponse { id, record })
+        .collect();
+    Ok(HttpResponse::Ok().json(receipts))
+}
+
+/// Approve a receipt pending review, crediting its points to its owner.
+#[post("/receipts/{id}/approve")]
+pub async fn approve_receipt(
+    path: web::Path<Uuid>,
+    body: web::Bytes,
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::Admin)?;
+    let note = note(&body)?;
+    let id = path.into_inner();
+    Ok(
+        match data
+            .connection
+            .approve_receipt(id, note, principal.subject)
+            .await?
+        {
+            Some(record) => HttpResponse::Ok().json(ReviewReceiptResponse { id, record }),
+            None => missing_receipt(&data.connection, id).await,
+        },
+    )
+}
+
+/// Reject a receipt pending review, leaving it worth no points.
+#[post("/receipts/{id}/reject")]
+pub async fn reject_receipt(
+    path: web::Path<Uuid>,
+    body: web::Bytes,
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::Admin)?;
+    let note = note(&body)?;
+    let id = path.into_inner();
+    Ok(
+        match data
+            .connection
+            .reject_receipt(id, note, principal.subject)
+            .await?
+        {
+            Some(record) => HttpResponse::Ok().json(ReviewReceiptResponse { id, record }),
+            None => missing_receipt(&data.connection, id).await,
+        },
+    )
+}
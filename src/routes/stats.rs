@@ -0,0 +1,87 @@
+use actix_web::{get, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::{Principal, Scope},
+    stats::{self, Aggregate, Bucket, DateRange, Grouping},
+    AppState,
+};
+
+/// Query parameters accepted by the purchase date statistics service.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DateStatsQuery {
+    #[serde(flatten)]
+    pub range: DateRange,
+
+    /// The length of the periods receipts are grouped into.
+    #[serde(default)]
+    pub bucket: Bucket,
+}
+
+/// Get statistics over every approved receipt purchased within the requested range.
+#[get("/stats")]
+pub async fn get_stats(
+    query: web::Query<DateRange>,
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::Admin)?;
+    let samples = data.connection.load_stats_samples(query.into_inner()).await;
+    Ok(HttpResponse::Ok().json(Aggregate::of(&samples)))
+}
+
+/// Get statistics over approved receipts purchased within the requested range, grouped by retailer.
+#[get("/stats/retailers")]
+pub async fn get_retailer_stats(
+    query: web::Query<DateRange>,
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::Admin)?;
+    grouped_stats(&data, query.into_inner(), Grouping::Retailer).await
+}
+
+/// Get statistics over approved receipts purchased within the requested range, grouped by day, week or month of
+/// purchase.
+#[get("/stats/dates")]
+pub async fn get_date_stats(
+    query: web::Query<DateStatsQuery>,
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::Admin)?;
+    grouped_stats(&data, query.range, Grouping::Date(query.bucket)).await
+}
+
+/// Get statistics over approved receipts purchased within the requested range, grouped by day of the week of purchase,
+/// from Monday to Sunday.
+#[get("/stats/weekdays")]
+pub async fn get_weekday_stats(
+    query: web::Query<DateRange>,
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::Admin)?;
+    grouped_stats(&data, query.into_inner(), Grouping::Weekday).await
+}
+
+/// Get statistics over approved receipts purchased within the requested range, grouped by hour of purchase.
+#[get("/stats/hours")]
+pub async fn get_hour_stats(
+    query: web::Query<DateRange>,
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::Admin)?;
+    grouped_stats(&data, query.into_inner(), Grouping::Hour).await
+}
+
+/// Builds the response for statistics grouped in the given way.
+async fn grouped_stats(
+    data: &AppState,
+    range: DateRange,
+    grouping: Grouping,
+) -> actix_web::Result<HttpResponse> {
+    let samples = data.connection.load_stats_samples(range).await;
+    Ok(HttpResponse::Ok().json(stats::group(&samples, grouping)))
+}
@@ -0,0 +1,105 @@
+use actix_web::{get, post, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use time::{Date, OffsetDateTime};
+use uuid::Uuid;
+
+use super::error::StorageError;
+use crate::{
+    auth::{AuthError, Principal, Scope},
+    data::{serialization, User},
+    AppState,
+};
+
+/// Response sent by the create user service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct CreateUserResponse {
+    pub id: Uuid,
+}
+
+/// Create a new member.
+#[post("/users")]
+pub async fn create_user(
+    web::Json(user): web::Json<User>,
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::UsersWrite)?;
+    Ok(
+        match data
+            .connection
+            .store_user(user)
+            .await
+            .map_err(StorageError)?
+        {
+            Some(id) => HttpResponse::Ok().json(CreateUserResponse { id }),
+            None => HttpResponse::BadRequest().into(),
+        },
+    )
+}
+
+/// The period to total a member's points over. Both ends are inclusive. If neither end is given, the period is the
+/// current calendar month.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeriodQuery {
+    #[serde(default, with = "serialization::date::option")]
+    pub from: Option<Date>,
+
+    #[serde(default, with = "serialization::date::option")]
+    pub to: Option<Date>,
+}
+
+impl PeriodQuery {
+    /// Determines whether the date falls within the period.
+    fn contains(&self, date: Date) -> bool {
+        let (from, to) = match (self.from, self.to) {
+            (None, None) => {
+                let today = OffsetDateTime::now_utc().date();
+                (today.replace_day(1).ok(), Some(today))
+            }
+            bounds => bounds,
+        };
+        from.map_or(true, |from| from <= date) && to.map_or(true, |to| date <= to)
+    }
+}
+
+/// Response sent by the user points service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct UserPointsResponse {
+    /// The points earned from all of the member's receipts.
+    pub lifetime: u64,
+
+    /// The points earned from the member's receipts purchased within the requested period.
+    pub period: u64,
+}
+
+/// Get the points a member has earned across their receipts. Members may always get their own points.
+#[get("/users/{id}/points")]
+pub async fn get_user_points(
+    path: web::Path<Uuid>,
+    query: web::Query<PeriodQuery>,
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    let id = path.into_inner();
+    if principal.user_id != Some(id) {
+        principal
+            .require_scope(Scope::UsersRead)
+            .map_err(|_| AuthError::Forbidden)?;
+    }
+    if data.connection.load_user(id).await.is_none() {
+        return Ok(HttpResponse::NotFound().into());
+    }
+
+    let mut response = UserPointsResponse {
+        lifetime: 0,
+        period: 0,
+    };
+    for record in data.connection.load_receipts_for_owner(id).await {
+        let points = record.points();
+        response.lifetime = response.lifetime.saturating_add(points);
+        if query.contains(record.receipt.purchase_date) {
+            response.period = response.period.saturating_add(points);
+        }
+    }
+    Ok(HttpResponse::Ok().json(response))
+}
@@ -0,0 +1,100 @@
+use actix_web::{delete, get, post, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::error::{ErrorResponse, StorageError};
+use crate::{
+    auth::{Principal, Scope},
+    webhooks::{EventType, Subscription},
+    AppState,
+};
+
+/// Response sent by the create webhook service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct CreateWebhookResponse {
+    pub id: Uuid,
+}
+
+/// A webhook subscription and its ID, as sent by the webhook services. The secret is never sent back.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebhookResponse {
+    pub id: Uuid,
+    pub url: String,
+    pub events: Vec<EventType>,
+}
+
+/// Subscribe to webhook notifications.
+#[post("/webhooks")]
+pub async fn create_webhook(
+    web::Json(subscription): web::Json<Subscription>,
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::Admin)?;
+    Ok(
+        match data
+            .connection
+            .store_subscription(subscription)
+            .await
+            .map_err(StorageError)?
+        {
+            Some(id) => HttpResponse::Ok().json(CreateWebhookResponse { id }),
+            None => HttpResponse::BadRequest().json(ErrorResponse {
+                error: "webhook subscription is not acceptable".to_owned(),
+            }),
+        },
+    )
+}
+
+/// Get every webhook subscription.
+#[get("/webhooks")]
+pub async fn list_webhooks(
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::Admin)?;
+    let webhooks: Vec<_> = data
+        .connection
+        .load_subscriptions()
+        .await
+        .into_iter()
+        .map(|(id, subscription)| WebhookResponse {
+            id,
+            url: subscription.url,
+            events: subscription.events,
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(webhooks))
+}
+
+/// Unsubscribe from webhook notifications. Deliveries already under way are still attempted.
+#[delete("/webhooks/{id}")]
+pub async fn delete_webhook(
+    path: web::Path<Uuid>,
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::Admin)?;
+    Ok(
+        if data
+            .connection
+            .delete_subscription(path.into_inner())
+            .await
+            .map_err(StorageError)?
+        {
+            HttpResponse::NoContent().into()
+        } else {
+            HttpResponse::NotFound().into()
+        },
+    )
+}
+
+/// Get the most recent attempts to deliver webhook notifications, oldest first.
+#[get("/webhooks/deliveries")]
+pub async fn list_webhook_deliveries(
+    principal: Principal,
+    data: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    principal.require_scope(Scope::Admin)?;
+    Ok(HttpResponse::Ok().json(data.connection.load_delivery_log().await))
+}
@@ -0,0 +1,340 @@
+//! Contains the aggregation of stored receipts into reporting statistics. Receipts are sampled from the database,
+//! optionally grouped by retailer, purchase date bucket, purchase weekday or purchase hour, and each group is summarized by its count and
+//! the sums and percentiles of its receipts' totals and points.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use time::{Date, Duration, Time};
+
+use crate::data::{serialization, Price};
+
+/// The range of purchase dates to aggregate over. Both ends are inclusive, and either may be omitted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DateRange {
+    #[serde(default, with = "serialization::date::option")]
+    pub from: Option<Date>,
+
+    #[serde(default, with = "serialization::date::option")]
+    pub to: Option<Date>,
+}
+
+impl DateRange {
+    /// Determines whether the date falls within the range.
+    pub fn contains(&self, date: Date) -> bool {
+        self.from.map_or(true, |from| from <= date) && self.to.map_or(true, |to| date <= to)
+    }
+}
+
+/// The length of the periods purchase dates are grouped into.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Bucket {
+    #[default]
+    Day,
+    /// Weeks starting on Monday.
+    Week,
+    Month,
+}
+
+impl Bucket {
+    /// Gets the first date of the period the date falls in.
+    pub fn start(self, date: Date) -> Date {
+        match self {
+            Self::Day => date,
+            Self::Week => date
+                .checked_sub(Duration::days(
+                    date.weekday().number_days_from_monday().into(),
+                ))
+                .unwrap_or(date),
+            Self::Month => date.replace_day(1).unwrap_or(date),
+        }
+    }
+}
+
+/// A day of the week, ordered from Monday.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl From<time::Weekday> for Weekday {
+    fn from(weekday: time::Weekday) -> Self {
+        match weekday {
+            time::Weekday::Monday => Self::Monday,
+            time::Weekday::Tuesday => Self::Tuesday,
+            time::Weekday::Wednesday => Self::Wednesday,
+            time::Weekday::Thursday => Self::Thursday,
+            time::Weekday::Friday => Self::Friday,
+            time::Weekday::Saturday => Self::Saturday,
+            time::Weekday::Sunday => Self::Sunday,
+        }
+    }
+}
+
+/// How receipts are grouped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grouping {
+    /// By the canonical name of the retailer.
+    Retailer,
+    /// By the period of the purchase date.
+    Date(Bucket),
+    /// By the day of the week of the purchase date.
+    Weekday,
+    /// By the hour of the purchase time.
+    Hour,
+}
+
+/// What a group of receipts have in common. Keys are deserialized as the first variant they match, so retailers
+/// come last, as any other key would match them.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum GroupKey {
+    /// The first date of the period.
+    Date(#[serde(with = "serialization::date")] Date),
+    Weekday(Weekday),
+    Hour(u8),
+    Retailer(String),
+}
+
+/// The data about a stored receipt that statistics are aggregated from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sample {
+    /// The canonical name of the retailer, or the name on the receipt if the retailer isn't known.
+    pub retailer: String,
+    pub purchase_date: Date,
+    pub purchase_time: Time,
+    pub total: Price,
+    pub points: u64,
+}
+
+impl Sample {
+    /// Gets the key of the group the sample falls in.
+    fn key(&self, grouping: Grouping) -> GroupKey {
+        match grouping {
+            Grouping::Retailer => GroupKey::Retailer(self.retailer.clone()),
+            Grouping::Date(bucket) => GroupKey::Date(bucket.start(self.purchase_date)),
+            Grouping::Weekday => GroupKey::Weekday(self.purchase_date.weekday().into()),
+            Grouping::Hour => GroupKey::Hour(self.purchase_time.hour()),
+        }
+    }
+}
+
+/// The sum, extremes and percentiles of a set of values. Percentiles use the nearest-rank method, so each is one of
+/// the values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Summary<T> {
+    pub sum: T,
+    pub min: T,
+    pub p50: T,
+    pub p90: T,
+    pub p99: T,
+    pub max: T,
+}
+
+impl Summary<u64> {
+    /// Summarizes the values, or returns None if there are none.
+    pub fn of(mut values: Vec<u64>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_unstable();
+        let percentile = |p: usize| values[(p * values.len()).div_ceil(100).max(1) - 1];
+        Some(Self {
+            sum: values
+                .iter()
+                .fold(0u64, |sum, &value| sum.saturating_add(value)),
+            min: values[0],
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: values[values.len() - 1],
+        })
+    }
+}
+
+impl<T> Summary<T> {
+    /// Converts each statistic with the given function.
+    pub fn map<U>(self, mut f: impl FnMut(T) -> U) -> Summary<U> {
+        Summary {
+            sum: f(self.sum),
+            min: f(self.min),
+            p50: f(self.p50),
+            p90: f(self.p90),
+            p99: f(self.p99),
+            max: f(self.max),
+        }
+    }
+}
+
+/// Statistics over a set of receipts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Aggregate {
+    /// The number of receipts.
+    pub count: usize,
+
+    /// The receipts' totals. Absent if there are no receipts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<Summary<Price>>,
+
+    /// The receipts' points. Absent if there are no receipts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub points: Option<Summary<u64>>,
+}
+
+impl Aggregate {
+    /// Aggregates statistics over the samples.
+    pub fn of<'a>(samples: impl IntoIterator<Item = &'a Sample>) -> Self {
+        let (totals, points): (Vec<_>, Vec<_>) = samples
+            .into_iter()
+            .map(|sample| (sample.total.to_cents().unwrap_or(u64::MAX), sample.points))
+            .unzip();
+        Self {
+            count: totals.len(),
+            total: Summary::of(totals).map(|summary| summary.map(Price::from_cents)),
+            points: Summary::of(points),
+        }
+    }
+}
+
+/// Statistics over a group of receipts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Group {
+    pub key: GroupKey,
+
+    #[serde(flatten)]
+    pub aggregate: Aggregate,
+}
+
+/// Groups the samples, aggregating statistics over each group. Groups are ordered by key.
+pub fn group(samples: &[Sample], grouping: Grouping) -> Vec<Group> {
+    let mut groups: BTreeMap<GroupKey, Vec<&Sample>> = BTreeMap::new();
+    for sample in samples {
+        groups.entry(sample.key(grouping)).or_default().push(sample);
+    }
+    groups
+        .into_iter()
+        .map(|(key, samples)| Group {
+            key,
+            aggregate: Aggregate::of(samples),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::date;
+
+    use super::*;
+
+    fn sample(retailer: &str, purchase_date: Date, hour: u8, cents: u64, points: u64) -> Sample {
+        Sample {
+            retailer: retailer.to_owned(),
+            purchase_date,
+            purchase_time: Time::from_hms(hour, 30, 0).unwrap(),
+            total: Price::from_cents(cents),
+            points,
+        }
+    }
+
+    #[test]
+    fn percentiles() {
+        let summary = Summary::of((1..=200).collect()).unwrap();
+        assert_eq!(summary.sum, 20100);
+        assert_eq!(summary.min, 1);
+        assert_eq!(summary.p50, 100);
+        assert_eq!(summary.p90, 180);
+        assert_eq!(summary.p99, 198);
+        assert_eq!(summary.max, 200);
+
+        let summary = Summary::of(vec![7]).unwrap();
+        assert_eq!((summary.p50, summary.p99), (7, 7));
+        assert!(Summary::of(Vec::new()).is_none());
+    }
+
+    #[test]
+    fn buckets() {
+        // 2022-01-05 was a Wednesday
+        let date = date!(2022 - 01 - 05);
+        assert_eq!(Bucket::Day.start(date), date);
+        assert_eq!(Bucket::Week.start(date), date!(2022 - 01 - 03));
+        assert_eq!(Bucket::Month.start(date), date!(2022 - 01 - 01));
+        assert_eq!(
+            Bucket::Week.start(date!(2022 - 01 - 03)),
+            date!(2022 - 01 - 03)
+        );
+    }
+
+    #[test]
+    fn grouping() {
+        let samples = [
+            sample("Target", date!(2022 - 01 - 03), 13, 125, 31),
+            sample("Target", date!(2022 - 01 - 09), 14, 3535, 28),
+            sample("M&M Corner Market", date!(2022 - 01 - 10), 14, 900, 109),
+        ];
+
+        let by_retailer = group(&samples, Grouping::Retailer);
+        assert_eq!(by_retailer.len(), 2);
+        assert_eq!(by_retailer[1].key, GroupKey::Retailer("Target".to_owned()));
+        assert_eq!(by_retailer[1].aggregate.count, 2);
+        let total = by_retailer[1].aggregate.total.unwrap();
+        assert_eq!(total.sum, Price::from_cents(3660));
+        assert_eq!(total.p50, Price::from_cents(125));
+        assert_eq!(by_retailer[1].aggregate.points.unwrap().sum, 59);
+
+        let by_week = group(&samples, Grouping::Date(Bucket::Week));
+        assert_eq!(
+            by_week
+                .iter()
+                .map(|group| (group.key.clone(), group.aggregate.count))
+                .collect::<Vec<_>>(),
+            vec![
+                (GroupKey::Date(date!(2022 - 01 - 03)), 2),
+                (GroupKey::Date(date!(2022 - 01 - 10)), 1),
+            ]
+        );
+
+        // weeks start on Monday
+        let by_weekday = group(&samples, Grouping::Weekday);
+        assert_eq!(
+            by_weekday
+                .iter()
+                .map(|group| (group.key.clone(), group.aggregate.count))
+                .collect::<Vec<_>>(),
+            vec![
+                (GroupKey::Weekday(Weekday::Monday), 2),
+                (GroupKey::Weekday(Weekday::Sunday), 1),
+            ]
+        );
+
+        let by_hour = group(&samples, Grouping::Hour);
+        assert_eq!(by_hour[0].key, GroupKey::Hour(13));
+        assert_eq!(by_hour[1].aggregate.count, 2);
+
+        let json = serde_json::to_value(&by_week[0]).unwrap();
+        assert_eq!(json["key"], "2022-01-03");
+        assert_eq!(json["total"]["sum"], "36.60");
+    }
+
+    #[test]
+    fn deserialize_keys() {
+        for key in [
+            GroupKey::Date(date!(2022 - 01 - 03)),
+            GroupKey::Weekday(Weekday::Monday),
+            GroupKey::Hour(13),
+            GroupKey::Retailer("Target".to_owned()),
+        ] {
+            let json = serde_json::to_value(&key).unwrap();
+            assert_eq!(serde_json::from_value::<GroupKey>(json).unwrap(), key);
+        }
+    }
+}
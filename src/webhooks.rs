@@ -0,0 +1,347 @@
+//! Contains webhook notifications. Subscribers register a URL, a secret and the types of event they want, and each
+//! matching event is POSTed to them as JSON. Every request is signed with an HMAC-SHA256 of its body keyed by the
+//! subscription's secret, sent in the `X-Webhook-Signature` header, so subscribers can check it came from us.
+//!
+//! Deliveries that fail, by error or by a response other than 2xx, are retried with exponential backoff. Every attempt
+//! is recorded in a bounded delivery log.
+//!
+//! Deliveries run on a dispatcher thread with its own runtime, so events can be dispatched from any thread or executor.
+
+use std::{
+    collections::VecDeque,
+    mem,
+    sync::{Arc, Mutex, RwLock},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use actix_rt::System;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use time::OffsetDateTime;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::config::WebhookConfig;
+
+/// The header carrying the signature of a webhook request's body.
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+
+/// The header carrying the type of the event a webhook request notifies of.
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+
+/// The types of event subscribers may be notified of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum EventType {
+    /// A new receipt was stored.
+    #[serde(rename = "receipt.processed")]
+    ReceiptProcessed,
+    /// A receipt held for review was rejected.
+    #[serde(rename = "receipt.rejected")]
+    ReceiptRejected,
+    /// A receipt's points were credited to its owner, when it was stored or when it was approved on review.
+    #[serde(rename = "points.awarded")]
+    PointsAwarded,
+}
+
+impl EventType {
+    /// Gets the name of the event type, as used in JSON.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::ReceiptProcessed => "receipt.processed",
+            Self::ReceiptRejected => "receipt.rejected",
+            Self::PointsAwarded => "points.awarded",
+        }
+    }
+}
+
+/// A webhook subscription.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Subscription {
+    /// The URL events are POSTed to.
+    pub url: String,
+
+    /// The key requests to the subscriber are signed with.
+    pub secret: String,
+
+    /// The types of event the subscriber is notified of.
+    pub events: Vec<EventType>,
+}
+
+impl Subscription {
+    /// Determines whether the subscription is acceptable: it must have an HTTP URL, a secret, and at least one event
+    /// type.
+    pub fn is_acceptable(&self) -> bool {
+        (self.url.starts_with("http://") || self.url.starts_with("https://"))
+            && !self.secret.is_empty()
+            && !self.events.is_empty()
+    }
+}
+
+/// An event, as sent to subscribers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Event {
+    pub id: Uuid,
+
+    #[serde(rename = "type")]
+    pub event_type: EventType,
+
+    #[serde(with = "time::serde::rfc3339")]
+    pub occurred_at: OffsetDateTime,
+
+    /// Details of the event, which depend on its type.
+    pub data: serde_json::Value,
+}
+
+impl Event {
+    /// Constructs a new event occurring now.
+    pub fn new(event_type: EventType, data: serde_json::Value) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            event_type,
+            occurred_at: OffsetDateTime::now_utc(),
+            data,
+        }
+    }
+}
+
+/// An attempt to deliver an event to a subscriber.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeliveryAttempt {
+    pub subscription_id: Uuid,
+    pub event_id: Uuid,
+    pub event_type: EventType,
+
+    /// The number of the attempt, starting from 1.
+    pub attempt: u32,
+
+    /// Whether the subscriber acknowledged the event with a 2xx response.
+    pub delivered: bool,
+
+    /// The status of the subscriber's response, if it responded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_status: Option<u16>,
+
+    /// Why the request failed, if it did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    #[serde(with = "time::serde::rfc3339")]
+    pub attempted_at: OffsetDateTime,
+}
+
+/// The most recent delivery attempts, oldest first.
+#[derive(Debug, Default)]
+pub struct DeliveryLog {
+    attempts: RwLock<VecDeque<DeliveryAttempt>>,
+}
+
+impl DeliveryLog {
+    /// Records an attempt, forgetting the oldest attempts beyond the capacity.
+    fn record(&self, attempt: DeliveryAttempt, capacity: usize) {
+        let mut attempts = self.attempts.write().unwrap_or_else(|e| e.into_inner());
+        attempts.push_back(attempt);
+        while attempts.len() > capacity {
+            attempts.pop_front();
+        }
+    }
+
+    /// Gets the recorded attempts, oldest first.
+    pub fn attempts(&self) -> Vec<DeliveryAttempt> {
+        self.attempts
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .cloned()
+            .collect()
+    }
+}
+
+/// Signs a request body with the given secret, giving the value of the signature header.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// An event waiting to be delivered to a subscriber.
+#[derive(Debug)]
+struct Delivery {
+    subscription_id: Uuid,
+    subscription: Subscription,
+    event: Event,
+    config: Arc<WebhookConfig>,
+    log: Arc<DeliveryLog>,
+}
+
+/// The state of a dispatcher's delivery thread.
+#[derive(Debug, Default)]
+enum DispatcherState {
+    /// No event has been dispatched yet, so there's no thread.
+    #[default]
+    Idle,
+    /// The thread is delivering the events sent to it.
+    Running {
+        sender: mpsc::UnboundedSender<Delivery>,
+        thread: JoinHandle<()>,
+    },
+    /// The dispatcher was drained, and delivers no more events.
+    Drained,
+}
+
+/// Delivers events in the background. The HTTP client deliveries are made with can't move between threads, so they
+/// run on a thread of the dispatcher's own, started when the first event is dispatched. Deliveries still in flight
+/// when the process exits are lost, so the dispatcher should be drained before exiting.
+#[derive(Debug, Default)]
+pub struct Dispatcher {
+    state: Mutex<DispatcherState>,
+}
+
+impl Dispatcher {
+    /// Queues an event for delivery to a subscriber, recording each attempt in the delivery log. Events dispatched
+    /// after the dispatcher is drained are dropped.
+    pub fn dispatch(
+        &self,
+        subscription_id: Uuid,
+        subscription: Subscription,
+        event: Event,
+        config: Arc<WebhookConfig>,
+        log: Arc<DeliveryLog>,
+    ) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if let DispatcherState::Idle = *state {
+            let (sender, mut receiver) = mpsc::unbounded_channel::<Delivery>();
+            let thread = thread::spawn(move || {
+                System::new().block_on(async move {
+                    let mut in_flight = Vec::new();
+                    while let Some(delivery) = receiver.recv().await {
+                        in_flight.retain(|handle: &actix_rt::task::JoinHandle<()>| {
+                            !handle.is_finished()
+                        });
+                        in_flight.push(actix_rt::spawn(async move {
+                            deliver(
+                                delivery.subscription_id,
+                                delivery.subscription,
+                                delivery.event,
+                                &delivery.config,
+                                &delivery.log,
+                            )
+                            .await;
+                        }));
+                    }
+                    // the dispatcher is draining, and stopping the runtime would cancel deliveries still in flight
+                    for handle in in_flight {
+                        let _ = handle.await;
+                    }
+                })
+            });
+            *state = DispatcherState::Running { sender, thread };
+        }
+        let DispatcherState::Running { sender, .. } = &*state else {
+            return;
+        };
+        // the receiver is only dropped if the dispatcher's thread panicked, leaving nowhere to deliver to
+        let _ = sender.send(Delivery {
+            subscription_id,
+            subscription,
+            event,
+            config,
+            log,
+        });
+    }
+
+    /// Stops accepting events and waits for the events already dispatched to be delivered, including their retries.
+    /// Blocks the calling thread until they are.
+    pub fn drain(&self) {
+        let state = mem::replace(
+            &mut *self.state.lock().unwrap_or_else(|e| e.into_inner()),
+            DispatcherState::Drained,
+        );
+        if let DispatcherState::Running { sender, thread } = state {
+            // closing the channel ends the thread's receive loop
+            drop(sender);
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Delivers an event to a subscriber, retrying failed attempts with exponential backoff, and records each attempt in
+/// the delivery log.
+async fn deliver(
+    subscription_id: Uuid,
+    subscription: Subscription,
+    event: Event,
+    config: &WebhookConfig,
+    log: &DeliveryLog,
+) {
+    let body = serde_json::to_vec(&event).expect("events should serialize");
+    let signature = sign(&subscription.secret, &body);
+    let client = awc::Client::builder()
+        .timeout(Duration::from_secs(config.timeout_seconds))
+        .finish();
+    let mut backoff = Duration::from_millis(config.initial_backoff_millis);
+    for attempt in 1..=config.max_attempts {
+        let result = client
+            .post(&subscription.url)
+            .insert_header((awc::http::header::CONTENT_TYPE, "application/json"))
+            .insert_header((SIGNATURE_HEADER, signature.as_str()))
+            .insert_header((EVENT_HEADER, event.event_type.name()))
+            .send_body(body.clone())
+            .await;
+        let (response_status, error) = match result {
+            Ok(response) => (Some(response.status()), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        let delivered = response_status.is_some_and(|status| status.is_success());
+        log.record(
+            DeliveryAttempt {
+                subscription_id,
+                event_id: event.id,
+                event_type: event.event_type,
+                attempt,
+                delivered,
+                response_status: response_status.map(|status| status.as_u16()),
+                error,
+                attempted_at: OffsetDateTime::now_utc(),
+            },
+            config.delivery_log_capacity,
+        );
+        if delivered {
+            return;
+        }
+        if attempt < config.max_attempts {
+            actix_rt::time::sleep(backoff).await;
+            backoff = backoff.saturating_mul(2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature() {
+        // the example from RFC 4231, test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn serialize_event() {
+        let event = Event::new(
+            EventType::PointsAwarded,
+            serde_json::json!({ "points": 31 }),
+        );
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["type"], "points.awarded");
+        assert_eq!(json["data"]["points"], 31);
+    }
+}